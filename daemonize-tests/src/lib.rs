@@ -1,12 +1,16 @@
 extern crate arraystring;
 extern crate daemonize;
+extern crate log;
 
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
 
-use daemonize::{Daemonize, Error, Outcome};
+use daemonize::{
+    After, Child, Daemonize, Error, InitPolicy, LinePrefix, LockStyle, Outcome, Parent,
+    PidFileFormat, PrivilegeDrop, Signal, Stage, WriteStage,
+};
 
 const ARG_PID_FILE: &str = "--pid-file";
 const ARG_CHOWN_PID_FILE: &str = "--chown-pid-file";
@@ -15,10 +19,103 @@ const ARG_USER_STRING: &str = "--user-string";
 const ARG_USER_NUM: &str = "--user-num";
 const ARG_GROUP_STRING: &str = "--group-string";
 const ARG_GROUP_NUM: &str = "--group-num";
+const ARG_SAVED_ROOT_USER: &str = "--saved-root-user";
+const ARG_TEMPORARY_PRIVILEGE_DROP: &str = "--temporary-privilege-drop";
+const ARG_SWITCH_PRIVILEGES: &str = "--switch-privileges";
+const ARG_VERIFY_PRIVILEGE_DROP: &str = "--verify-privilege-drop";
+const ARG_NO_NEW_PRIVILEGES: &str = "--no-new-privileges";
+const ARG_LANDLOCK_READ: &str = "--landlock-read";
+const ARG_LANDLOCK_WRITE: &str = "--landlock-write";
+const ARG_USER_NAMESPACE: &str = "--user-namespace";
+const ARG_UID_MAP: &str = "--uid-map";
+const ARG_GID_MAP: &str = "--gid-map";
+const ARG_NAMESPACE: &str = "--namespace";
+const ARG_PIVOT_ROOT: &str = "--pivot-root";
+const ARG_JAIL_BIND_MOUNT: &str = "--jail-bind-mount";
+const ARG_CHROOT_DEVNULL: &str = "--chroot-devnull";
+const ARG_CHROOT_WORKING_DIRECTORY: &str = "--chroot-working-directory";
 const ARG_UMASK: &str = "--umask";
 const ARG_CHROOT: &str = "--chroot";
+const ARG_STDIN: &str = "--stdin";
+const ARG_STDIN_APPEND: &str = "--stdin-append";
 const ARG_STDOUT: &str = "--stdout";
 const ARG_STDERR: &str = "--stderr";
+const ARG_STDOUT_PATH: &str = "--stdout-path";
+const ARG_STDERR_PATH: &str = "--stderr-path";
+const ARG_STDOUT_APPEND: &str = "--stdout-append";
+const ARG_MERGE_STDERR: &str = "--merge-stderr";
+const ARG_STDOUT_FD: &str = "--stdout-fd";
+const ARG_REOPEN_STREAMS_ON_HUP: &str = "--reopen-streams-on-hup";
+const ARG_STDOUT_DECORATED: &str = "--stdout-decorated";
+const ARG_OUTPUT_AFTER_SLEEP: &str = "--output-after-sleep";
+const ARG_STDERR_TEE: &str = "--stderr-tee";
+const ARG_STDOUT_PIPED: &str = "--stdout-piped";
+const ARG_STDOUT_LOGGER: &str = "--stdout-logger";
+const ARG_FOREGROUND: &str = "--foreground";
+const ARG_KEEP_SESSION: &str = "--keep-session";
+const ARG_KEEP_WORKING_DIRECTORY: &str = "--keep-working-directory";
+const ARG_WORKING_DIRECTORY_USER_HOME: &str = "--working-directory-user-home";
+const ARG_CREATE_WORKING_DIRECTORY: &str = "--create-working-directory";
+const ARG_CHOWN_WORKING_DIRECTORY: &str = "--chown-working-directory";
+const ARG_CHOWN_STREAMS: &str = "--chown-streams";
+const ARG_KEEP_UMASK: &str = "--keep-umask";
+const ARG_SIMPLE: &str = "--simple";
+const ARG_AUTO_DETECT_SUPERVISOR: &str = "--auto-detect-supervisor";
+const ARG_INIT_POLICY: &str = "--init-policy";
+const ARG_WAIT_READY_MS: &str = "--wait-ready-ms";
+const ARG_SKIP_READY: &str = "--skip-ready";
+const ARG_START_SYNC: &str = "--start-sync";
+const ARG_START_EXTENDED: &str = "--start-extended";
+const ARG_PID_FILE_GUARD: &str = "--pid-file-guard";
+const ARG_PID_FILE_START_TIME: &str = "--pid-file-start-time";
+const ARG_PID_FILE_JSON: &str = "--pid-file-json";
+const ARG_PID_FILE_NO_NEWLINE: &str = "--pid-file-no-newline";
+const ARG_ATOMIC_PID_FILE: &str = "--atomic-pid-file";
+const ARG_PID_FILE_LOCK: &str = "--pid-file-lock";
+const ARG_PID_FILE_FD: &str = "--pid-file-fd";
+const ARG_PID_FILE_DEFAULT: &str = "--pid-file-default";
+const ARG_INSTANCE: &str = "--instance";
+const ARG_SINGLE_INSTANCE: &str = "--single-instance";
+const ARG_CONTROL_SOCKET: &str = "--control-socket";
+const ARG_HANDLE_SIGNALS: &str = "--handle-signals";
+const ARG_RESET_SIGNALS: &str = "--reset-signals";
+const ARG_IGNORE_SIGPIPE: &str = "--ignore-sigpipe";
+const ARG_KEEP_IGNORED_SIGNAL: &str = "--keep-ignored-signal";
+const ARG_PARENT_DEATH_SIGNAL: &str = "--parent-death-signal";
+const ARG_SUBREAPER: &str = "--subreaper";
+const ARG_REAP_CHILDREN: &str = "--reap-children";
+const ARG_PROCESS_NAME: &str = "--process-name";
+const ARG_PROC_TITLE: &str = "--proc-title";
+const ARG_NICE: &str = "--nice";
+const ARG_IO_PRIORITY: &str = "--io-priority";
+const ARG_OOM_SCORE_ADJ: &str = "--oom-score-adj";
+const ARG_NOFILE_LIMIT: &str = "--nofile-limit";
+const ARG_DISABLE_CORE_DUMPS: &str = "--disable-core-dumps";
+const ARG_ALLOW_CORE_DUMPS: &str = "--allow-core-dumps";
+const ARG_LOCK_MEMORY: &str = "--lock-memory";
+const ARG_CLOSE_FDS: &str = "--close-fds";
+const ARG_INHERIT_FD: &str = "--inherit-fd";
+const ARG_CLEAR_ENVIRONMENT: &str = "--clear-environment";
+const ARG_ENV_FILE: &str = "--env-file";
+const ARG_DAEMON_ENV: &str = "--daemon-env";
+const ARG_SET_USER_ENV: &str = "--set-user-env";
+const ARG_CLEANUP_PID_FILE_ON_TERM: &str = "--cleanup-pid-file-on-term";
+const ARG_NO_RELAY_ERRORS: &str = "--no-relay-errors";
+const ARG_WAIT_DAEMON_EXIT: &str = "--wait-daemon-exit";
+const ARG_CHANNEL: &str = "--channel";
+const ARG_TIMEOUT_MS: &str = "--timeout-ms";
+const ARG_ON_ERROR: &str = "--on-error";
+const ARG_LOG_FILE: &str = "--log-file";
+const ARG_PREFLIGHT: &str = "--preflight";
+const ARG_STEP_FILE: &str = "--step-file";
+const ARG_PID_FILE_WRITE: &str = "--pid-file-write";
+const ARG_PRIVILEGED_FILES: &str = "--privileged-files";
+const ARG_PRIVILEGED_CONTEXT_FILE: &str = "--privileged-context-file";
+const ARG_FAILING_PRIVILEGED_ACTION: &str = "--failing-privileged-action";
+const ARG_POST_PRIVILEGE_FILE: &str = "--post-privilege-file";
+const ARG_POST_FORK_FILE: &str = "--post-fork-file";
+const ARG_FAILING_PRE_FORK_ACTION: &str = "--failing-pre-fork-action";
+const ARG_FAILING_STEP: &str = "--failing-step";
 const ARG_ADDITIONAL_FILE: &str = "--additional-file";
 const ARG_SLEEP_MS: &str = "--sleep-ms";
 const ARG_HUMAN_READABLE: &str = "--human-readable";
@@ -31,10 +128,11 @@ const TESTER_PATH: &str = "../target/debug/examples/tester";
 
 const MAX_WAIT_DURATION: std::time::Duration = std::time::Duration::from_secs(5);
 
-const DATA_LEN: usize = std::mem::size_of::<Result<EnvData, Error>>();
+const DATA_LEN: usize = std::mem::size_of::<Result<EnvData, TesterError>>();
 
 pub struct Tester {
     command: Command,
+    stderr: Option<std::fs::File>,
 }
 
 impl Default for Tester {
@@ -46,7 +144,10 @@ impl Default for Tester {
 impl Tester {
     pub fn new() -> Self {
         let command = Command::new(TESTER_PATH);
-        Self { command }
+        Self {
+            command,
+            stderr: None,
+        }
     }
 
     pub fn pid_file<F: AsRef<Path>>(&mut self, pid_file: F) -> &mut Self {
@@ -70,7 +171,7 @@ impl Tester {
     }
 
     pub fn user_num(&mut self, user: u32) -> &mut Self {
-        self.command.arg(ARG_USER_STRING).arg(user.to_string());
+        self.command.arg(ARG_USER_NUM).arg(user.to_string());
         self
     }
 
@@ -80,7 +181,98 @@ impl Tester {
     }
 
     pub fn group_num(&mut self, group: u32) -> &mut Self {
-        self.command.arg(ARG_GROUP_STRING).arg(group.to_string());
+        self.command.arg(ARG_GROUP_NUM).arg(group.to_string());
+        self
+    }
+
+    /// The tester switches real and effective user ids to `uid` keeping root in the saved id.
+    pub fn saved_root_user(&mut self, uid: u32) -> &mut Self {
+        self.command.arg(ARG_SAVED_ROOT_USER).arg(uid.to_string());
+        self
+    }
+
+    pub fn temporary_privilege_drop(&mut self) -> &mut Self {
+        self.command.arg(ARG_TEMPORARY_PRIVILEGE_DROP);
+        self
+    }
+
+    /// The daemon escalates and relinquishes privileges with its token, writing effective ids
+    /// after each switch to `path`.
+    pub fn switch_privileges<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_SWITCH_PRIVILEGES).arg(path.as_ref());
+        self
+    }
+
+    pub fn verify_privilege_drop(&mut self) -> &mut Self {
+        self.command.arg(ARG_VERIFY_PRIVILEGE_DROP);
+        self
+    }
+
+    pub fn no_new_privileges(&mut self) -> &mut Self {
+        self.command.arg(ARG_NO_NEW_PRIVILEGES);
+        self
+    }
+
+    pub fn landlock_read<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_LANDLOCK_READ).arg(path.as_ref());
+        self
+    }
+
+    pub fn landlock_write<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_LANDLOCK_WRITE).arg(path.as_ref());
+        self
+    }
+
+    pub fn user_namespace(&mut self) -> &mut Self {
+        self.command.arg(ARG_USER_NAMESPACE);
+        self
+    }
+
+    pub fn uid_map(&mut self, inside: u32, outside: u32, count: u32) -> &mut Self {
+        self.command
+            .arg(ARG_UID_MAP)
+            .arg(format!("{}:{}:{}", inside, outside, count));
+        self
+    }
+
+    pub fn gid_map(&mut self, inside: u32, outside: u32, count: u32) -> &mut Self {
+        self.command
+            .arg(ARG_GID_MAP)
+            .arg(format!("{}:{}:{}", inside, outside, count));
+        self
+    }
+
+    pub fn namespace(&mut self, kind: &str) -> &mut Self {
+        self.command.arg(ARG_NAMESPACE).arg(kind);
+        self
+    }
+
+    pub fn pivot_root<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_PIVOT_ROOT).arg(path.as_ref());
+        self
+    }
+
+    pub fn jail_bind_mount<F: AsRef<Path>, J: AsRef<Path>>(
+        &mut self,
+        host_path: F,
+        jail_path: J,
+    ) -> &mut Self {
+        self.command
+            .arg(ARG_JAIL_BIND_MOUNT)
+            .arg(host_path.as_ref())
+            .arg(jail_path.as_ref());
+        self
+    }
+
+    pub fn chroot_devnull(&mut self) -> &mut Self {
+        self.command.arg(ARG_CHROOT_DEVNULL);
+        self
+    }
+
+    pub fn chroot_working_directory<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command
+            .arg(ARG_CHROOT_WORKING_DIRECTORY)
+            .arg(path.as_ref());
         self
     }
 
@@ -94,6 +286,16 @@ impl Tester {
         self
     }
 
+    pub fn stdin<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDIN).arg(path.as_ref());
+        self
+    }
+
+    pub fn stdin_append<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDIN_APPEND).arg(path.as_ref());
+        self
+    }
+
     pub fn stdout<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
         self.command.arg(ARG_STDOUT).arg(path.as_ref());
         self
@@ -104,6 +306,475 @@ impl Tester {
         self
     }
 
+    pub fn chown_streams(&mut self) -> &mut Self {
+        self.command.arg(ARG_CHOWN_STREAMS);
+        self
+    }
+
+    pub fn stdout_path<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDOUT_PATH).arg(path.as_ref());
+        self
+    }
+
+    pub fn stderr_path<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDERR_PATH).arg(path.as_ref());
+        self
+    }
+
+    pub fn stdout_append<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDOUT_APPEND).arg(path.as_ref());
+        self
+    }
+
+    pub fn merge_stderr(&mut self) -> &mut Self {
+        self.command.arg(ARG_MERGE_STDERR);
+        self
+    }
+
+    pub fn stdout_fd<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDOUT_FD).arg(path.as_ref());
+        self
+    }
+
+    pub fn reopen_streams_on_hup(&mut self) -> &mut Self {
+        self.command.arg(ARG_REOPEN_STREAMS_ON_HUP);
+        self
+    }
+
+    pub fn stdout_decorated<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDOUT_DECORATED).arg(path.as_ref());
+        self
+    }
+
+    pub fn output_after_sleep(&mut self) -> &mut Self {
+        self.command.arg(ARG_OUTPUT_AFTER_SLEEP);
+        self
+    }
+
+    pub fn stderr_tee<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDERR_TEE).arg(path.as_ref());
+        self
+    }
+
+    pub fn tester_stderr<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.stderr =
+            Some(std::fs::File::create(path).expect("unable to create tester stderr file"));
+        self
+    }
+
+    pub fn stdout_piped(&mut self) -> &mut Self {
+        self.command.arg(ARG_STDOUT_PIPED);
+        self
+    }
+
+    pub fn stdout_logger<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_STDOUT_LOGGER).arg(path.as_ref());
+        self
+    }
+
+    pub fn foreground(&mut self) -> &mut Self {
+        self.command.arg(ARG_FOREGROUND);
+        self
+    }
+
+    pub fn keep_session(&mut self) -> &mut Self {
+        self.command.arg(ARG_KEEP_SESSION);
+        self
+    }
+
+    pub fn keep_working_directory(&mut self) -> &mut Self {
+        self.command.arg(ARG_KEEP_WORKING_DIRECTORY);
+        self
+    }
+
+    pub fn working_directory_user_home(&mut self) -> &mut Self {
+        self.command.arg(ARG_WORKING_DIRECTORY_USER_HOME);
+        self
+    }
+
+    pub fn create_working_directory(&mut self, mode: u32) -> &mut Self {
+        self.command
+            .arg(ARG_CREATE_WORKING_DIRECTORY)
+            .arg(mode.to_string());
+        self
+    }
+
+    pub fn chown_working_directory(&mut self) -> &mut Self {
+        self.command.arg(ARG_CHOWN_WORKING_DIRECTORY);
+        self
+    }
+
+    pub fn keep_umask(&mut self) -> &mut Self {
+        self.command.arg(ARG_KEEP_UMASK);
+        self
+    }
+
+    /// Start from `Daemonize::simple`, has to be called before other options.
+    pub fn simple(&mut self, nochdir: bool, noclose: bool) -> &mut Self {
+        self.command
+            .arg(ARG_SIMPLE)
+            .arg(nochdir.to_string())
+            .arg(noclose.to_string());
+        self
+    }
+
+    pub fn auto_detect_supervisor(&mut self) -> &mut Self {
+        self.command.arg(ARG_AUTO_DETECT_SUPERVISOR);
+        self
+    }
+
+    pub fn env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.command.env(key, value);
+        self
+    }
+
+    pub fn env_remove(&mut self, key: &str) -> &mut Self {
+        self.command.env_remove(key);
+        self
+    }
+
+    /// Run the tester as PID 1 of a new PID namespace, has to be called before other options.
+    pub fn as_init(&mut self) -> &mut Self {
+        self.command = Command::new("unshare");
+        self.command.arg("--pid").arg("--fork").arg(TESTER_PATH);
+        self
+    }
+
+    /// `policy` is one of `refuse`, `foreground` or `reaper`.
+    pub fn init_policy(&mut self, policy: &str) -> &mut Self {
+        self.command.arg(ARG_INIT_POLICY).arg(policy);
+        self
+    }
+
+    pub fn wait_ready(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.command
+            .arg(ARG_WAIT_READY_MS)
+            .arg(timeout.as_millis().to_string());
+        self
+    }
+
+    pub fn timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.command
+            .arg(ARG_TIMEOUT_MS)
+            .arg(timeout.as_millis().to_string());
+        self
+    }
+
+    /// Create the file in a custom step at `after`, one of `setsid`, `fork`, `pid-file`,
+    /// `redirect-streams`, `privileged-action`, `chroot`, `privilege-drop` or `daemonized`.
+    pub fn step_file<F: AsRef<Path>>(&mut self, after: &str, path: F) -> &mut Self {
+        self.command
+            .arg(ARG_STEP_FILE)
+            .arg(after)
+            .arg(path.as_ref());
+        self
+    }
+
+    /// `stage` is one of `last`, `before-chroot` or `after-privilege-drop`.
+    pub fn pid_file_write(&mut self, stage: &str) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_WRITE).arg(stage);
+        self
+    }
+
+    /// Create both files in separate privileged actions, the second one checks the order.
+    pub fn privileged_files<F: AsRef<Path>>(&mut self, first: F, second: F) -> &mut Self {
+        self.command
+            .arg(ARG_PRIVILEGED_FILES)
+            .arg(first.as_ref())
+            .arg(second.as_ref());
+        self
+    }
+
+    /// Write the context of the privileged action to the file.
+    pub fn privileged_context_file<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command
+            .arg(ARG_PRIVILEGED_CONTEXT_FILE)
+            .arg(path.as_ref());
+        self
+    }
+
+    /// Create the file after privileges are dropped.
+    pub fn post_privilege_file<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_POST_PRIVILEGE_FILE).arg(path.as_ref());
+        self
+    }
+
+    /// Append the forked process and its PID to the file after each fork.
+    pub fn post_fork_file<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_POST_FORK_FILE).arg(path.as_ref());
+        self
+    }
+
+    pub fn failing_pre_fork_action(&mut self) -> &mut Self {
+        self.command.arg(ARG_FAILING_PRE_FORK_ACTION);
+        self
+    }
+
+    pub fn failing_privileged_action(&mut self) -> &mut Self {
+        self.command.arg(ARG_FAILING_PRIVILEGED_ACTION);
+        self
+    }
+
+    pub fn failing_step(&mut self) -> &mut Self {
+        self.command.arg(ARG_FAILING_STEP);
+        self
+    }
+
+    pub fn preflight(&mut self) -> &mut Self {
+        self.command.arg(ARG_PREFLIGHT);
+        self
+    }
+
+    /// Write log records of daemonize to the file.
+    pub fn log_file<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_LOG_FILE).arg(path.as_ref());
+        self
+    }
+
+    /// Append the failed stage to the file on error.
+    pub fn on_error<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_ON_ERROR).arg(path.as_ref());
+        self
+    }
+
+    pub fn skip_ready(&mut self) -> &mut Self {
+        self.command.arg(ARG_SKIP_READY);
+        self
+    }
+
+    /// Use `start_sync` instead of `execute`, the result is only available with `run_status`.
+    pub fn start_sync(&mut self) -> &mut Self {
+        self.command.arg(ARG_START_SYNC);
+        self
+    }
+
+    /// Use `start_extended` instead of `execute`, the daemon writes the result to the file.
+    pub fn start_extended<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_START_EXTENDED).arg(path.as_ref());
+        self
+    }
+
+    /// Remove the pid file when the daemon exits.
+    pub fn pid_file_guard(&mut self) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_GUARD);
+        self
+    }
+
+    pub fn pid_file_start_time(&mut self) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_START_TIME);
+        self
+    }
+
+    /// Write the pid file in JSON format with the daemon `version`.
+    pub fn pid_file_json(&mut self, version: &str) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_JSON).arg(version);
+        self
+    }
+
+    /// Write the pid file without the trailing newline.
+    pub fn pid_file_no_newline(&mut self) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_NO_NEWLINE);
+        self
+    }
+
+    /// `style` is one of `flock`, `ofd` (Linux only) or `posix`.
+    pub fn pid_file_lock(&mut self, style: &str) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_LOCK).arg(style);
+        self
+    }
+
+    pub fn pid_file_fd<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_FD).arg(path.as_ref());
+        self
+    }
+
+    pub fn pid_file_default(&mut self, name: &str) -> &mut Self {
+        self.command.arg(ARG_PID_FILE_DEFAULT).arg(name);
+        self
+    }
+
+    pub fn instance(&mut self, instance: &str) -> &mut Self {
+        self.command.arg(ARG_INSTANCE).arg(instance);
+        self
+    }
+
+    pub fn single_instance(&mut self, name: &str) -> &mut Self {
+        self.command.arg(ARG_SINGLE_INSTANCE).arg(name);
+        self
+    }
+
+    pub fn control_socket<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_CONTROL_SOCKET).arg(path.as_ref());
+        self
+    }
+
+    pub fn handle_signals<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_HANDLE_SIGNALS).arg(path.as_ref());
+        self
+    }
+
+    pub fn reset_signals(&mut self) -> &mut Self {
+        self.command.arg(ARG_RESET_SIGNALS);
+        self
+    }
+
+    pub fn ignore_sigpipe(&mut self) -> &mut Self {
+        self.command.arg(ARG_IGNORE_SIGPIPE);
+        self
+    }
+
+    /// The daemon spawns an orphan which writes the PID of its new parent to `path`.
+    pub fn subreaper<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_SUBREAPER).arg(path.as_ref());
+        self
+    }
+
+    /// The daemon spawns a child exiting with code 3 and reaps it, the PID and the exit code are
+    /// written to `path`.
+    pub fn reap_children<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_REAP_CHILDREN).arg(path.as_ref());
+        self
+    }
+
+    pub fn process_name(&mut self, name: &str) -> &mut Self {
+        self.command.arg(ARG_PROCESS_NAME).arg(name);
+        self
+    }
+
+    pub fn nice(&mut self, nice: libc::c_int) -> &mut Self {
+        self.command.arg(ARG_NICE).arg(nice.to_string());
+        self
+    }
+
+    /// Set the best effort I/O priority `level`.
+    pub fn io_priority(&mut self, level: u8) -> &mut Self {
+        self.command.arg(ARG_IO_PRIORITY).arg(level.to_string());
+        self
+    }
+
+    pub fn oom_score_adj(&mut self, adjustment: i16) -> &mut Self {
+        self.command
+            .arg(ARG_OOM_SCORE_ADJ)
+            .arg(adjustment.to_string());
+        self
+    }
+
+    /// Set the soft and hard limits of open file descriptors.
+    pub fn nofile_limit(&mut self, soft: u64, hard: u64) -> &mut Self {
+        self.command
+            .arg(ARG_NOFILE_LIMIT)
+            .arg(format!("{}:{}", soft, hard));
+        self
+    }
+
+    pub fn disable_core_dumps(&mut self) -> &mut Self {
+        self.command.arg(ARG_DISABLE_CORE_DUMPS);
+        self
+    }
+
+    pub fn allow_core_dumps<F: AsRef<Path>>(&mut self, directory: F) -> &mut Self {
+        self.command
+            .arg(ARG_ALLOW_CORE_DUMPS)
+            .arg(directory.as_ref());
+        self
+    }
+
+    pub fn lock_memory(&mut self) -> &mut Self {
+        self.command.arg(ARG_LOCK_MEMORY);
+        self
+    }
+
+    /// The tester opens two descriptors and keeps the first one, the daemon writes whether they
+    /// are open to `path`.
+    pub fn close_fds<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_CLOSE_FDS).arg(path.as_ref());
+        self
+    }
+
+    /// The tester passes stdin and a descriptor with `FD_CLOEXEC` to the daemon, which writes
+    /// the passed descriptors and whether `FD_CLOEXEC` is set to `path`.
+    pub fn inherit_fd<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_INHERIT_FD).arg(path.as_ref());
+        self
+    }
+
+    /// Clear the environment of the daemon except `keep`.
+    pub fn clear_environment(&mut self, keep: &[&str]) -> &mut Self {
+        self.command.arg(ARG_CLEAR_ENVIRONMENT).arg(keep.join(","));
+        self
+    }
+
+    /// Set the environment variable `key` in the daemon, the tester's one is left intact.
+    pub fn daemon_env(&mut self, key: &str, value: &str) -> &mut Self {
+        self.command
+            .arg(ARG_DAEMON_ENV)
+            .arg(format!("{}={}", key, value));
+        self
+    }
+
+    /// Set the environment of the daemon user.
+    pub fn set_user_env(&mut self) -> &mut Self {
+        self.command.arg(ARG_SET_USER_ENV);
+        self
+    }
+
+    /// The daemon writes its environment to `path`, a `NAME=value` line per variable.
+    pub fn env_file<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
+        self.command.arg(ARG_ENV_FILE).arg(path.as_ref());
+        self
+    }
+
+    /// The daemon sets its process title after daemonization.
+    pub fn proc_title(&mut self, title: &str) -> &mut Self {
+        self.command.arg(ARG_PROC_TITLE).arg(title);
+        self
+    }
+
+    /// The tester becomes a subreaper of the daemon, which is terminated when the tester exits.
+    pub fn parent_death_signal(&mut self) -> &mut Self {
+        self.command.arg(ARG_PARENT_DEATH_SIGNAL);
+        self
+    }
+
+    pub fn keep_ignored_signal(&mut self, signal: libc::c_int) -> &mut Self {
+        self.command
+            .arg(ARG_KEEP_IGNORED_SIGNAL)
+            .arg(signal.to_string());
+        self
+    }
+
+    /// Start the tester with `ignored` signals ignored and `blocked` signals blocked.
+    pub fn signal_state(&mut self, ignored: &[libc::c_int], blocked: &[libc::c_int]) -> &mut Self {
+        use std::os::unix::process::CommandExt;
+
+        let (ignored, blocked) = (ignored.to_vec(), blocked.to_vec());
+        unsafe {
+            self.command.pre_exec(move || {
+                for signal in &ignored {
+                    libc::signal(*signal, libc::SIG_IGN);
+                }
+                let mut mask: libc::sigset_t = std::mem::zeroed();
+                libc::sigemptyset(&mut mask);
+                for signal in &blocked {
+                    libc::sigaddset(&mut mask, *signal);
+                }
+                libc::sigprocmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut());
+                Ok(())
+            });
+        }
+        self
+    }
+
+    pub fn atomic_pid_file(&mut self) -> &mut Self {
+        self.command.arg(ARG_ATOMIC_PID_FILE);
+        self
+    }
+
+    pub fn cleanup_pid_file_on_term(&mut self) -> &mut Self {
+        self.command.arg(ARG_CLEANUP_PID_FILE_ON_TERM);
+        self
+    }
+
     pub fn additional_file<F: AsRef<Path>>(&mut self, path: F) -> &mut Self {
         self.command.arg(ARG_ADDITIONAL_FILE).arg(path.as_ref());
         self
@@ -116,11 +787,40 @@ impl Tester {
         self
     }
 
-    pub fn run(&mut self) -> Result<EnvData, Error> {
+    /// Make the parent wait until the daemon exits using the process file descriptor.
+    pub fn wait_daemon_exit(&mut self) -> &mut Self {
+        self.command.arg(ARG_WAIT_DAEMON_EXIT);
+        self
+    }
+
+    /// The daemon sends its PID to the parent over the channel.
+    pub fn channel(&mut self) -> &mut Self {
+        self.command.arg(ARG_CHANNEL);
+        self
+    }
+
+    pub fn no_relay_errors(&mut self) -> &mut Self {
+        self.command.arg(ARG_NO_RELAY_ERRORS);
+        self
+    }
+
+    /// Run the tester and return its exit status with stderr.
+    pub fn run_status(&mut self) -> (std::process::ExitStatus, String) {
+        let output = self
+            .command
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+            .expect("unable to run tester");
+        let stderr = String::from_utf8(output.stderr).expect("invalid tester stderr");
+        (output.status, stderr)
+    }
+
+    pub fn run(&mut self) -> Result<EnvData, TesterError> {
         let mut child = self
             .command
             .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
+            .stderr(self.stderr.take().map_or_else(Stdio::piped, Stdio::from))
             .spawn()
             .expect("unable to spawn child");
 
@@ -139,11 +839,11 @@ impl Tester {
 
         if !exit_status.success() {
             let mut stderr = String::new();
-            child
-                .stderr
-                .expect("unable to get stderr")
-                .read_to_string(&mut stderr)
-                .expect("unable to read tester stderr");
+            if let Some(mut child_stderr) = child.stderr {
+                child_stderr
+                    .read_to_string(&mut stderr)
+                    .expect("unable to read tester stderr");
+            }
             panic!(
                 "invalid tester exit status ({}), stderr: {}",
                 exit_status.code().expect("unable to get status code"),
@@ -162,16 +862,62 @@ impl Tester {
     }
 }
 
+/// Daemonization error sent by the tester, it's plain data unlike `Error` which may have a source.
+#[derive(Debug)]
+pub struct TesterError {
+    stage: Stage,
+    message: arraystring::ArrayString<arraystring::typenum::U100>,
+}
+
+impl TesterError {
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+}
+
+impl std::fmt::Display for TesterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl From<Error> for TesterError {
+    fn from(err: Error) -> Self {
+        TesterError {
+            stage: err.stage(),
+            message: arraystring::ArrayString::from_str_truncate(err.to_string()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct EnvData {
     pub cwd: arraystring::ArrayString<arraystring::typenum::U255>,
     pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
     pub euid: u32,
     pub egid: u32,
+    pub suid: u32,
+    pub ppid: u32,
+    pub sid: u32,
+    pub umask: u32,
+    pub no_new_privileges: bool,
+    pub stdin: arraystring::ArrayString<arraystring::typenum::U255>,
+    pub user_namespace: u64,
+    pub mount_namespace: u64,
+    pub uts_namespace: u64,
 }
 
 impl EnvData {
-    fn new() -> EnvData {
+    fn new(read_stdin: bool) -> EnvData {
+        let mut stdin = String::new();
+        if read_stdin {
+            std::io::stdin()
+                .read_to_string(&mut stdin)
+                .expect("unable to read stdin");
+        }
+
         Self {
             cwd: arraystring::ArrayString::from_str(
                 std::env::current_dir()
@@ -181,12 +927,118 @@ impl EnvData {
             )
             .expect("too long path"),
             pid: std::process::id(),
+            uid: unsafe { libc::getuid() as u32 },
+            gid: unsafe { libc::getgid() as u32 },
             euid: unsafe { libc::geteuid() as u32 },
             egid: unsafe { libc::getegid() as u32 },
+            suid: saved_uid(),
+            ppid: unsafe { libc::getppid() as u32 },
+            sid: unsafe { libc::getsid(0) as u32 },
+            umask: get_umask(),
+            no_new_privileges: no_new_privileges(),
+            stdin: arraystring::ArrayString::from_str(&stdin).expect("too long stdin"),
+            user_namespace: namespace("user"),
+            mount_namespace: namespace("mnt"),
+            uts_namespace: namespace("uts"),
+        }
+    }
+}
+
+/// Wait until the process exits, the process file descriptor becomes readable.
+#[cfg(target_os = "linux")]
+fn wait_pidfd(pidfd: &std::os::unix::io::OwnedFd) {
+    use std::os::unix::io::AsRawFd;
+
+    let mut pollfd = libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ret = unsafe { libc::poll(&mut pollfd, 1, MAX_WAIT_DURATION.as_millis() as libc::c_int) };
+    assert_eq!(ret, 1, "daemon is still running");
+}
+
+/// Spawn an orphan with a double fork, it writes the PID of the process it's reparented to.
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn spawn_orphan(path: PathBuf) {
+    unsafe {
+        let middle = libc::fork();
+        assert!(middle >= 0, "unable to fork");
+        if middle == 0 {
+            let middle = libc::getpid();
+            if libc::fork() != 0 {
+                libc::_exit(0)
+            }
+            while libc::getppid() == middle {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            let ok = std::fs::write(path, libc::getppid().to_string()).is_ok();
+            libc::_exit(if ok { 0 } else { 1 })
         }
+        libc::waitpid(middle, std::ptr::null_mut(), 0);
+    }
+}
+
+struct FileLogger(std::sync::Mutex<std::fs::File>);
+
+impl log::Log for FileLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let mut file = self.0.lock().expect("poisoned log file");
+        writeln!(file, "{} {}", record.level(), record.args()).ok();
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn get_umask() -> u32 {
+    unsafe {
+        let umask = libc::umask(0o022);
+        libc::umask(umask);
+        umask as u32
     }
 }
 
+#[cfg(target_os = "linux")]
+fn no_new_privileges() -> bool {
+    unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) == 1 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn no_new_privileges() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn saved_uid() -> u32 {
+    let (mut real, mut effective, mut saved) = (0, 0, 0);
+    unsafe { libc::getresuid(&mut real, &mut effective, &mut saved) };
+    saved
+}
+
+#[cfg(not(target_os = "linux"))]
+fn saved_uid() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(target_os = "linux")]
+pub fn namespace(kind: &str) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // procfs is not available after root change.
+    std::fs::metadata(format!("/proc/self/ns/{}", kind))
+        .map(|metadata| metadata.ino())
+        .unwrap_or(0)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn namespace(_kind: &str) -> u64 {
+    0
+}
+
 pub fn execute_tester() {
     let mut daemonize = Daemonize::new();
     let mut args = std::env::args().skip(1);
@@ -203,23 +1055,146 @@ pub fn execute_tester() {
             .unwrap_or_else(|_| panic!("invalid value for key {}", key))
     }
 
+    #[cfg(target_os = "linux")]
+    fn read_id_map(args: &mut dyn Iterator<Item = String>, key: &str) -> (u32, u32, u32) {
+        let value = read_value::<String>(args, key);
+        let parts = value
+            .split(':')
+            .map(|part| part.parse().expect("invalid id map"))
+            .collect::<Vec<u32>>();
+        match parts.as_slice() {
+            [inside, outside, count] => (*inside, *outside, *count),
+            _ => panic!("invalid value for key {}", key),
+        }
+    }
+
     let mut additional_files = Vec::new();
     let mut sleep_duration = None;
     let mut human_readable = false;
+    let mut read_stdin = false;
+    let mut output_after_sleep = false;
+    let mut skip_ready = false;
+    let mut start_sync = false;
+    let mut start_extended = None;
+    let mut pid_file_guard = false;
+    let mut signals_file = None;
+    let mut spawn_child = false;
+    let mut proc_title = None;
+    let mut fds_file = None;
+    let mut inherited_fds_file = None;
+    let mut env_file = None;
+    #[cfg(target_os = "linux")]
+    let mut all_namespaces = None;
+    let mut privileges_file = None;
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    let mut orphan_file = None;
+    #[cfg(target_os = "linux")]
+    let mut wait_daemon_exit = false;
+    let mut privileged_files = None;
+    let mut channel = false;
 
     while let Some(key) = args.next() {
         daemonize = match key.as_str() {
             ARG_PID_FILE => daemonize.pid_file(read_value::<PathBuf>(&mut args, &key)),
+            ARG_PID_FILE_WRITE => {
+                daemonize.pid_file_write(match read_value::<String>(&mut args, &key).as_str() {
+                    "last" => WriteStage::Last,
+                    "before-chroot" => WriteStage::BeforeChroot,
+                    "after-privilege-drop" => WriteStage::AfterPrivilegeDrop,
+                    _ => panic!("invalid value for key {}", key),
+                })
+            }
+            ARG_PID_FILE_LOCK => {
+                daemonize.pid_file_lock(match read_value::<String>(&mut args, &key).as_str() {
+                    "flock" => LockStyle::Flock,
+                    #[cfg(target_os = "linux")]
+                    "ofd" => LockStyle::OfdFcntl,
+                    "posix" => LockStyle::PosixFcntl,
+                    _ => panic!("invalid value for key {}", key),
+                })
+            }
             ARG_CHOWN_PID_FILE => daemonize.chown_pid_file(true),
             ARG_WORKING_DIRECTORY => {
                 daemonize.working_directory(read_value::<PathBuf>(&mut args, &key))
             }
             ARG_USER_STRING => daemonize.user(read_value::<String>(&mut args, &key).as_str()),
             ARG_USER_NUM => daemonize.user(read_value::<u32>(&mut args, &key)),
+            #[cfg(target_os = "linux")]
+            ARG_SAVED_ROOT_USER => {
+                let uid = read_value::<u32>(&mut args, &key);
+                let ret = unsafe { libc::setresuid(uid, uid, 0) };
+                assert_eq!(ret, 0, "unable to set user ids");
+                daemonize
+            }
             ARG_GROUP_STRING => daemonize.group(read_value::<String>(&mut args, &key).as_str()),
             ARG_GROUP_NUM => daemonize.group(read_value::<u32>(&mut args, &key)),
+            ARG_TEMPORARY_PRIVILEGE_DROP => daemonize.privilege_drop_mode(PrivilegeDrop::Temporary),
+            ARG_SWITCH_PRIVILEGES => {
+                privileges_file = Some(read_value::<PathBuf>(&mut args, &key));
+                daemonize
+            }
+            ARG_VERIFY_PRIVILEGE_DROP => daemonize.verify_privilege_drop(true),
+            ARG_NO_NEW_PRIVILEGES => daemonize.no_new_privileges(true),
+            #[cfg(target_os = "linux")]
+            ARG_LANDLOCK_READ => daemonize.landlock_read(&[read_value::<PathBuf>(&mut args, &key)]),
+            #[cfg(target_os = "linux")]
+            ARG_LANDLOCK_WRITE => {
+                daemonize.landlock_write(&[read_value::<PathBuf>(&mut args, &key)])
+            }
+            #[cfg(target_os = "linux")]
+            ARG_USER_NAMESPACE => daemonize.user_namespace(true),
+            #[cfg(target_os = "linux")]
+            ARG_UID_MAP => {
+                let (inside, outside, count) = read_id_map(&mut args, &key);
+                daemonize.uid_map(inside, outside, count)
+            }
+            #[cfg(target_os = "linux")]
+            ARG_GID_MAP => {
+                let (inside, outside, count) = read_id_map(&mut args, &key);
+                daemonize.gid_map(inside, outside, count)
+            }
+            #[cfg(target_os = "linux")]
+            ARG_NAMESPACE => {
+                let namespaces = match read_value::<String>(&mut args, &key).as_str() {
+                    "mnt" => daemonize::Namespaces::MOUNT,
+                    "uts" => daemonize::Namespaces::UTS,
+                    "ipc" => daemonize::Namespaces::IPC,
+                    "net" => daemonize::Namespaces::NETWORK,
+                    kind => panic!("unknown namespace: {}", kind),
+                };
+                // The builder replaces namespaces, the option can be repeated.
+                let namespaces = all_namespaces.map_or(namespaces, |all| all | namespaces);
+                all_namespaces = Some(namespaces);
+                daemonize.namespaces(namespaces)
+            }
+            #[cfg(target_os = "linux")]
+            ARG_PIVOT_ROOT => {
+                daemonize.pivot_root(read_value::<PathBuf>(&mut args, &key), "old-root")
+            }
+            #[cfg(target_os = "linux")]
+            ARG_JAIL_BIND_MOUNT => {
+                let host_path = read_value::<PathBuf>(&mut args, &key);
+                let jail_path = read_value::<PathBuf>(&mut args, &key);
+                daemonize.jail_bind_mount(host_path, jail_path, true)
+            }
+            ARG_CHROOT_DEVNULL => daemonize.chroot_devnull(true),
+            ARG_CHROOT_WORKING_DIRECTORY => {
+                daemonize.chroot_working_directory(read_value::<PathBuf>(&mut args, &key))
+            }
             ARG_UMASK => daemonize.umask(read_value::<u32>(&mut args, &key)),
             ARG_CHROOT => daemonize.chroot(read_value::<PathBuf>(&mut args, &key)),
+            ARG_STDIN => {
+                let file = std::fs::File::open(read_value::<PathBuf>(&mut args, &key))
+                    .expect("unable to open stdin file");
+                read_stdin = true;
+                daemonize.stdin(file)
+            }
+            ARG_STDIN_APPEND => {
+                read_stdin = true;
+                daemonize.stdin(daemonize::Stdio::append(read_value::<PathBuf>(
+                    &mut args, &key,
+                )))
+            }
             ARG_STDOUT => {
                 let file = std::fs::File::create(read_value::<PathBuf>(&mut args, &key))
                     .expect("unable to open stdout file");
@@ -230,6 +1205,315 @@ pub fn execute_tester() {
                     .expect("unable to open stder file");
                 daemonize.stderr(file)
             }
+            ARG_STDOUT_PATH => daemonize.stdout_path(read_value::<PathBuf>(&mut args, &key)),
+            ARG_STDERR_PATH => daemonize.stderr_path(read_value::<PathBuf>(&mut args, &key)),
+            ARG_STDOUT_APPEND => daemonize.stdout(daemonize::Stdio::append(read_value::<PathBuf>(
+                &mut args, &key,
+            ))),
+            ARG_CHOWN_STREAMS => daemonize.chown_streams(true),
+            ARG_MERGE_STDERR => daemonize.stderr(daemonize::Stdio::merge_with_stdout()),
+            ARG_STDOUT_FD => {
+                let file = std::fs::File::create(read_value::<PathBuf>(&mut args, &key))
+                    .expect("unable to open stdout file");
+                daemonize.stdout(daemonize::Stdio::from_fd(std::os::unix::io::OwnedFd::from(
+                    file,
+                )))
+            }
+            ARG_REOPEN_STREAMS_ON_HUP => daemonize.reopen_streams_on(Signal::Hup),
+            ARG_PID_FILE_START_TIME => daemonize.pid_file_start_time(true),
+            ARG_ATOMIC_PID_FILE => daemonize.atomic_pid_file(true),
+            ARG_INSTANCE => daemonize.instance(&read_value::<String>(&mut args, &key)),
+            ARG_CONTROL_SOCKET => daemonize.control_socket(read_value::<PathBuf>(&mut args, &key)),
+            #[cfg(target_os = "linux")]
+            ARG_SINGLE_INSTANCE => {
+                daemonize.single_instance(daemonize::InstanceGuard::AbstractSocket(read_value::<
+                    String,
+                >(
+                    &mut args, &key,
+                )))
+            }
+            ARG_PID_FILE_DEFAULT => {
+                daemonize.pid_file_default(&read_value::<String>(&mut args, &key))
+            }
+            ARG_PID_FILE_FD => {
+                let file = std::fs::OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(read_value::<PathBuf>(&mut args, &key))
+                    .expect("unable to open pid file");
+                let fd = std::os::unix::io::AsRawFd::as_raw_fd(&file);
+                let ret = unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) };
+                assert_eq!(ret, 0, "unable to lock pid file");
+                daemonize.pid_file_fd(file)
+            }
+            ARG_PID_FILE_NO_NEWLINE => daemonize.pid_file_contents(|pid| pid.to_string()),
+            ARG_PID_FILE_JSON => daemonize.pid_file_format(PidFileFormat::Json {
+                version: Some(read_value::<String>(&mut args, &key)),
+            }),
+            ARG_CLEANUP_PID_FILE_ON_TERM => daemonize.cleanup_pid_file_on_signals(&[Signal::Term]),
+            ARG_STDOUT_DECORATED => {
+                let file = std::fs::File::create(read_value::<PathBuf>(&mut args, &key))
+                    .expect("unable to open stdout file");
+                daemonize.stdout(daemonize::Stdio::decorated(
+                    file,
+                    LinePrefix::TIMESTAMP | LinePrefix::STREAM,
+                ))
+            }
+            ARG_OUTPUT_AFTER_SLEEP => {
+                output_after_sleep = true;
+                daemonize
+            }
+            ARG_STDERR_TEE => {
+                let file = std::fs::File::create(read_value::<PathBuf>(&mut args, &key))
+                    .expect("unable to open stderr file");
+                daemonize.stderr(daemonize::Stdio::tee(file))
+            }
+            ARG_STDOUT_PIPED => daemonize.stdout(daemonize::Stdio::piped()),
+            ARG_STDOUT_LOGGER => daemonize.stdout(daemonize::Stdio::logger(read_value::<PathBuf>(
+                &mut args, &key,
+            ))),
+            ARG_FOREGROUND => daemonize.foreground(true),
+            ARG_AUTO_DETECT_SUPERVISOR => daemonize.auto_detect_supervisor(true),
+            ARG_KEEP_SESSION => daemonize.detach_session(false),
+            ARG_KEEP_WORKING_DIRECTORY => daemonize.keep_working_directory(),
+            ARG_WORKING_DIRECTORY_USER_HOME => daemonize.working_directory_user_home(),
+            ARG_CREATE_WORKING_DIRECTORY => {
+                daemonize.create_working_directory(read_value::<u32>(&mut args, &key))
+            }
+            ARG_CHOWN_WORKING_DIRECTORY => daemonize.chown_working_directory(true),
+            ARG_KEEP_UMASK => daemonize.keep_umask(),
+            ARG_SIMPLE => {
+                let nochdir = read_value::<bool>(&mut args, &key);
+                let noclose = read_value::<bool>(&mut args, &key);
+                Daemonize::simple(nochdir, noclose)
+            }
+            ARG_INIT_POLICY => {
+                daemonize.init_policy(match read_value::<String>(&mut args, &key).as_str() {
+                    "refuse" => InitPolicy::Refuse,
+                    "foreground" => InitPolicy::Foreground,
+                    "reaper" => InitPolicy::ForegroundReaper,
+                    _ => panic!("invalid value for key {}", key),
+                })
+            }
+            ARG_WAIT_READY_MS => daemonize.wait_ready(std::time::Duration::from_millis(
+                read_value::<u64>(&mut args, &key),
+            )),
+            ARG_TIMEOUT_MS => daemonize.timeout(std::time::Duration::from_millis(
+                read_value::<u64>(&mut args, &key),
+            )),
+            ARG_PREFLIGHT => daemonize.preflight(true),
+            ARG_STEP_FILE => {
+                let after = match read_value::<String>(&mut args, &key).as_str() {
+                    "setsid" => After::SetSid,
+                    "fork" => After::Fork,
+                    "pid-file" => After::PidFile,
+                    "redirect-streams" => After::RedirectStreams,
+                    "privileged-action" => After::PrivilegedAction,
+                    "chroot" => After::Chroot,
+                    "privilege-drop" => After::PrivilegeDrop,
+                    "daemonized" => After::Daemonized,
+                    _ => panic!("invalid value for key {}", key),
+                };
+                let path = read_value::<PathBuf>(&mut args, &key);
+                daemonize.insert_step(after, move || std::fs::File::create(&path).map(drop))
+            }
+            ARG_PRIVILEGED_FILES => {
+                privileged_files = Some((
+                    read_value::<PathBuf>(&mut args, &key),
+                    read_value::<PathBuf>(&mut args, &key),
+                ));
+                daemonize
+            }
+            ARG_PRIVILEGED_CONTEXT_FILE => {
+                let path = read_value::<PathBuf>(&mut args, &key);
+                daemonize.privileged_action_with_context(move |context| {
+                    std::fs::write(
+                        path,
+                        format!(
+                            "{:?} {:?} {} {} {}",
+                            context.uid,
+                            context.gid,
+                            context.pid_file_fd.is_some(),
+                            context.pid,
+                            context.working_directory.display()
+                        ),
+                    )
+                    .expect("unable to write privileged context file");
+                })
+            }
+            ARG_POST_PRIVILEGE_FILE => {
+                let path = read_value::<PathBuf>(&mut args, &key);
+                daemonize.post_privilege_action(move || std::fs::File::create(path).map(drop))
+            }
+            ARG_POST_FORK_FILE => {
+                let path = read_value::<PathBuf>(&mut args, &key);
+                daemonize.post_fork_action(move |forked| {
+                    let mut file = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .expect("unable to open post fork file");
+                    writeln!(file, "{:?} {}", forked, std::process::id())
+                        .expect("unable to write post fork file");
+                })
+            }
+            ARG_FAILING_PRE_FORK_ACTION => daemonize
+                .pre_fork_action(|| Err(std::io::Error::from_raw_os_error(libc::EADDRINUSE))),
+            ARG_FAILING_PRIVILEGED_ACTION => {
+                daemonize.try_privileged_action(|| Err::<(), _>("action failed"))
+            }
+            ARG_FAILING_STEP => daemonize.insert_step(After::Fork, || Err::<(), _>("step failed")),
+            ARG_LOG_FILE => {
+                let file = std::fs::File::create(read_value::<PathBuf>(&mut args, &key))
+                    .expect("unable to create log file");
+                let logger = Box::leak(Box::new(FileLogger(std::sync::Mutex::new(file))));
+                log::set_logger(logger).expect("unable to set logger");
+                log::set_max_level(log::LevelFilter::Debug);
+                daemonize
+            }
+            ARG_ON_ERROR => {
+                let path = read_value::<PathBuf>(&mut args, &key);
+                daemonize.on_error(move |stage, err| {
+                    let mut file = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(path)
+                        .expect("unable to open on error file");
+                    let line = match std::error::Error::source(err) {
+                        Some(source) => format!("{:?}: {}", stage, source),
+                        None => format!("{:?}", stage),
+                    };
+                    writeln!(file, "{}", line).expect("unable to write on error file");
+                })
+            }
+            ARG_SKIP_READY => {
+                skip_ready = true;
+                daemonize
+            }
+            ARG_NO_RELAY_ERRORS => daemonize.relay_errors(false),
+            ARG_CHANNEL => {
+                channel = true;
+                daemonize
+            }
+            #[cfg(target_os = "linux")]
+            ARG_WAIT_DAEMON_EXIT => {
+                wait_daemon_exit = true;
+                daemonize
+            }
+            ARG_START_SYNC => {
+                start_sync = true;
+                daemonize
+            }
+            ARG_PID_FILE_GUARD => {
+                pid_file_guard = true;
+                daemonize
+            }
+            ARG_RESET_SIGNALS => daemonize.reset_signals(true),
+            ARG_IGNORE_SIGPIPE => daemonize.ignore_sigpipe(true),
+            #[cfg(target_os = "linux")]
+            ARG_PARENT_DEATH_SIGNAL => {
+                let ret = unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) };
+                assert_eq!(ret, 0, "unable to become a subreaper");
+                daemonize.parent_death_signal(Signal::Term)
+            }
+            ARG_KEEP_IGNORED_SIGNAL => {
+                daemonize.keep_ignored_signals(&[read_value::<libc::c_int>(&mut args, &key)])
+            }
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            ARG_SUBREAPER => {
+                orphan_file = Some(read_value::<PathBuf>(&mut args, &key));
+                daemonize.subreaper(true)
+            }
+            ARG_PROCESS_NAME => daemonize.process_name(&read_value::<String>(&mut args, &key)),
+            ARG_NICE => daemonize.nice(read_value::<libc::c_int>(&mut args, &key)),
+            #[cfg(target_os = "linux")]
+            ARG_IO_PRIORITY => daemonize.io_priority(
+                daemonize::IoClass::BestEffort,
+                read_value::<u8>(&mut args, &key),
+            ),
+            #[cfg(target_os = "linux")]
+            ARG_OOM_SCORE_ADJ => daemonize.oom_score_adj(read_value::<i16>(&mut args, &key)),
+            ARG_NOFILE_LIMIT => {
+                let value = read_value::<String>(&mut args, &key);
+                let (soft, hard) = value.split_once(':').expect("invalid limits");
+                daemonize.rlimit(
+                    daemonize::Resource::NoFile,
+                    soft.parse().expect("invalid soft limit"),
+                    hard.parse().expect("invalid hard limit"),
+                )
+            }
+            ARG_DISABLE_CORE_DUMPS => daemonize.disable_core_dumps(true),
+            ARG_ALLOW_CORE_DUMPS => {
+                daemonize.allow_core_dumps(read_value::<PathBuf>(&mut args, &key))
+            }
+            ARG_LOCK_MEMORY => daemonize
+                .lock_memory(daemonize::MlockFlags::CURRENT | daemonize::MlockFlags::FUTURE),
+            ARG_CLOSE_FDS => {
+                // High numbers, so they aren't reused by descriptors the daemon opens later.
+                let open = |fd| {
+                    use std::os::unix::io::AsRawFd;
+                    let file = std::fs::File::open("/dev/null").expect("unable to open /dev/null");
+                    let ret = unsafe { libc::dup2(file.as_raw_fd(), fd) };
+                    assert_eq!(ret, fd, "unable to duplicate descriptor");
+                    fd
+                };
+                let (kept, closed) = (open(100), open(101));
+                fds_file = Some((read_value::<PathBuf>(&mut args, &key), kept, closed));
+                unsafe { daemonize.close_fds(true) }.keep_fds(&[kept])
+            }
+            ARG_INHERIT_FD => {
+                let fd = {
+                    use std::os::unix::io::IntoRawFd;
+                    // Opened with `FD_CLOEXEC`.
+                    std::fs::File::open("/dev/null")
+                        .expect("unable to open /dev/null")
+                        .into_raw_fd()
+                };
+                inherited_fds_file = Some(read_value::<PathBuf>(&mut args, &key));
+                daemonize.inherit_fd(fd).inherit_fd(libc::STDIN_FILENO)
+            }
+            ARG_CLEAR_ENVIRONMENT => {
+                let keep = read_value::<String>(&mut args, &key);
+                let keep = keep.split(',').filter(|name| !name.is_empty());
+                daemonize.clear_environment(&keep.collect::<Vec<_>>())
+            }
+            ARG_DAEMON_ENV => {
+                let var = read_value::<String>(&mut args, &key);
+                let (name, value) = var.split_once('=').expect("invalid environment variable");
+                daemonize.env(name, value)
+            }
+            ARG_SET_USER_ENV => daemonize.set_user_env(true),
+            ARG_ENV_FILE => {
+                env_file = Some(read_value::<PathBuf>(&mut args, &key));
+                daemonize
+            }
+            ARG_PROC_TITLE => {
+                proc_title = Some(read_value::<String>(&mut args, &key));
+                daemonize
+            }
+            ARG_REAP_CHILDREN => {
+                let path = read_value::<PathBuf>(&mut args, &key);
+                spawn_child = true;
+                daemonize.reap_children(move |pid, status| {
+                    let line = format!("{} {:?}\n", pid, status.code());
+                    let mut file = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .expect("unable to open reap file");
+                    file.write_all(line.as_bytes())
+                        .expect("unable to write reap file");
+                })
+            }
+            ARG_HANDLE_SIGNALS => {
+                signals_file = Some(read_value::<PathBuf>(&mut args, &key));
+                daemonize.handle_signals(&[Signal::Hup, Signal::Term])
+            }
+            ARG_START_EXTENDED => {
+                start_extended = Some(read_value::<PathBuf>(&mut args, &key));
+                daemonize
+            }
             ARG_ADDITIONAL_FILE => {
                 additional_files.push(read_value::<PathBuf>(&mut args, &key));
                 daemonize
@@ -249,49 +1533,311 @@ pub fn execute_tester() {
         }
     }
 
+    let options = Options {
+        additional_files,
+        sleep_duration,
+        human_readable,
+        read_stdin,
+        output_after_sleep,
+        skip_ready,
+        start_sync,
+        start_extended,
+        pid_file_guard,
+        signals_file,
+        spawn_child,
+        proc_title,
+        fds_file,
+        inherited_fds_file,
+        env_file,
+        privileges_file,
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        orphan_file,
+        #[cfg(target_os = "linux")]
+        wait_daemon_exit,
+    };
+    match privileged_files {
+        Some((first, second)) => {
+            let first_path = first.clone();
+            let daemonize = daemonize
+                .and_privileged_action(move || std::fs::File::create(first).is_ok())
+                .and_privileged_action(move || {
+                    assert!(first_path.exists(), "privileged actions are out of order");
+                    std::fs::File::create(second).is_ok()
+                });
+            finish_channel(daemonize, channel, options)
+        }
+        None => finish_channel(daemonize, channel, options),
+    }
+}
+
+fn finish_channel<T>(daemonize: Daemonize<T>, channel: bool, options: Options) {
+    if channel {
+        finish(daemonize.with_channel::<u32>(), options)
+    } else {
+        finish(daemonize, options)
+    }
+}
+
+/// Message type of the channel, `()` if the channel is not opened.
+trait Message: Sized {
+    fn send<T>(child: &mut Child<T, Self>);
+    fn check(parent: &mut Parent<Self>);
+}
+
+impl Message for () {
+    fn send<T>(_child: &mut Child<T, Self>) {}
+    fn check(_parent: &mut Parent<Self>) {}
+}
+
+impl Message for u32 {
+    fn send<T>(child: &mut Child<T, Self>) {
+        if let Some(mut sender) = child.sender() {
+            sender
+                .send(&std::process::id())
+                .expect("unable to send message");
+        }
+    }
+
+    fn check(parent: &mut Parent<Self>) {
+        if let Some(mut receiver) = parent.receiver() {
+            let pid = receiver.recv().expect("unable to receive message");
+            assert_eq!(pid, parent.daemon_pid.map(|pid| pid as u32));
+            assert!(receiver
+                .recv()
+                .expect("unable to receive message")
+                .is_none());
+        }
+    }
+}
+
+struct Options {
+    additional_files: Vec<PathBuf>,
+    sleep_duration: Option<std::time::Duration>,
+    human_readable: bool,
+    read_stdin: bool,
+    output_after_sleep: bool,
+    skip_ready: bool,
+    start_sync: bool,
+    start_extended: Option<PathBuf>,
+    pid_file_guard: bool,
+    signals_file: Option<PathBuf>,
+    spawn_child: bool,
+    proc_title: Option<String>,
+    fds_file: Option<(PathBuf, libc::c_int, libc::c_int)>,
+    inherited_fds_file: Option<PathBuf>,
+    env_file: Option<PathBuf>,
+    privileges_file: Option<PathBuf>,
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    orphan_file: Option<PathBuf>,
+    #[cfg(target_os = "linux")]
+    wait_daemon_exit: bool,
+}
+
+fn finish<T, M: Message>(daemonize: Daemonize<T, M>, options: Options) {
+    if options.start_sync {
+        // The parent never returns, the daemon has nothing to report.
+        let code = if daemonize.start_sync().is_ok() { 0 } else { 1 };
+        std::process::exit(code)
+    }
+
+    if let Some(path) = options.start_extended {
+        // The parent exits with the code of the first child.
+        let daemonized = daemonize.start_extended().expect("unable to daemonize");
+        if let (Some(path), Some(token)) = (&options.privileges_file, &daemonized.privilege_token) {
+            let ids = || unsafe { format!("{} {}\n", libc::geteuid(), libc::getegid()) };
+            token.escalate().expect("unable to escalate privileges");
+            let escalated = ids();
+            token.relinquish().expect("unable to relinquish privileges");
+            std::fs::write(path, escalated + &ids()).expect("unable to write privileges file");
+        }
+        std::fs::write(
+            path,
+            format!(
+                "{:?} {:?} {} {}",
+                daemonized.uid,
+                daemonized.gid,
+                daemonized.pid,
+                daemonized.pid_file.is_some()
+            ),
+        )
+        .expect("unable to write start extended file");
+        std::process::exit(0)
+    }
+
     let (mut read_pipe, mut write_pipe) = os_pipe::pipe().expect("unable to open pipe");
+    // The daemon reports the result through the pipe and drops the read end, so both are kept
+    // even if inherited descriptors are closed.
+    let daemonize = {
+        use std::os::unix::io::AsRawFd;
+        daemonize.keep_fds(&[read_pipe.as_raw_fd(), write_pipe.as_raw_fd()])
+    };
+
+    let tester_pid = std::process::id();
 
     match daemonize.execute() {
-        Outcome::Parent(_) => {
+        Outcome::Parent(Err(err)) => {
+            let result: Result<EnvData, TesterError> = Err(err.into());
+            let data: [u8; DATA_LEN] = unsafe { std::mem::transmute(result) };
+            std::io::stdout()
+                .write_all(&data)
+                .expect("unable to write data")
+        }
+        Outcome::Parent(mut parent) => {
             drop(write_pipe);
             let mut data = Vec::new();
             read_pipe
                 .read_to_end(&mut data)
                 .expect("unable to read pipe");
-            if !human_readable && data.len() != DATA_LEN {
+            if !options.human_readable && data.len() != DATA_LEN {
                 panic!("invalid data len");
             }
+            if let Ok(parent) = &mut parent {
+                M::check(parent);
+                assert!(parent.first_child_pid > 0);
+                assert_ne!(parent.first_child_pid as u32, tester_pid);
+                if !options.human_readable {
+                    let mut result = [0; DATA_LEN];
+                    result.copy_from_slice(&data);
+                    let result: Result<EnvData, TesterError> =
+                        unsafe { std::mem::transmute(result) };
+                    if let Ok(env) = result {
+                        assert_eq!(parent.daemon_pid.map(|pid| pid as u32), Some(env.pid));
+                    }
+                }
+            }
+            #[cfg(target_os = "linux")]
+            if options.wait_daemon_exit {
+                let parent = parent.as_ref().expect("daemonization failed");
+                wait_pidfd(parent.daemon_pidfd.as_ref().expect("no daemon pidfd"));
+            }
+            if let Some(mut stdout) = parent.ok().and_then(|parent| parent.stdout) {
+                let mut output = String::new();
+                stdout
+                    .read_to_string(&mut output)
+                    .expect("unable to read daemon stdout");
+                assert_eq!(output, STDOUT_DATA);
+            }
             std::io::stdout()
                 .write_all(&data)
                 .expect("unable to write data")
         }
         Outcome::Child(result) => {
             drop(read_pipe);
-            let result = result.map(|_| EnvData::new());
+            let mut pid_file_guard = None;
+            let mut handle = None;
+            let result = result.map_err(TesterError::from).map(|mut child| {
+                if options.pid_file_guard {
+                    pid_file_guard = child.pid_file_guard();
+                }
+                handle = child.handle.take();
+                if let Some(path) = &options.inherited_fds_file {
+                    let fds = child
+                        .inherited_fds
+                        .iter()
+                        .map(|&fd| {
+                            let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+                            format!("{} {}\n", fd, flags & libc::FD_CLOEXEC != 0)
+                        })
+                        .collect::<String>();
+                    std::fs::write(path, fds).expect("unable to write inherited fds file");
+                }
+                if let (Some(path), Some(token)) =
+                    (&options.privileges_file, &child.privilege_token)
+                {
+                    let ids = || unsafe { format!("{} {}\n", libc::geteuid(), libc::getegid()) };
+                    token.escalate().expect("unable to escalate privileges");
+                    let escalated = ids();
+                    token.relinquish().expect("unable to relinquish privileges");
+                    std::fs::write(path, escalated + &ids())
+                        .expect("unable to write privileges file");
+                }
+                if let Some(path) = &options.env_file {
+                    let env = std::env::vars()
+                        .map(|(name, value)| format!("{}={}\n", name, value))
+                        .collect::<String>();
+                    std::fs::write(path, env).expect("unable to write env file");
+                }
+                if let Some(title) = &options.proc_title {
+                    unsafe { daemonize::proc_title::set(title) }
+                        .expect("unable to set process title");
+                }
+                M::send(&mut child);
+                if let (Some(ready), false) = (child.ready, options.skip_ready) {
+                    ready.notify();
+                }
+                EnvData::new(options.read_stdin)
+            });
+            let foreground = std::process::id() == tester_pid;
 
-            print!("{}", STDOUT_DATA);
-            eprint!("{}", STDERR_DATA);
+            // Standard streams are not redirected if daemonization failed early or in foreground
+            // mode, stdout of the tester is reserved for the result.
+            if result.is_ok() && !foreground {
+                print!("{}", STDOUT_DATA);
+                std::io::stdout().flush().ok();
+                eprint!("{}", STDERR_DATA);
+            }
 
-            for file_path in additional_files {
+            for file_path in options.additional_files {
                 if let Ok(mut file) = std::fs::File::create(&file_path) {
                     file.write_all(ADDITIONAL_FILE_DATA.as_bytes()).ok();
                 }
             }
 
-            if human_readable {
+            if options.human_readable {
                 write_pipe
                     .write_all(format!("{:?}\n", result).as_bytes())
                     .ok();
             } else {
                 let data: [u8; DATA_LEN] = unsafe { std::mem::transmute(result) };
-                write_pipe.write_all(&data).ok();
+                if foreground {
+                    std::io::stdout().write_all(&data).ok();
+                } else {
+                    write_pipe.write_all(&data).ok();
+                }
             }
 
             drop(write_pipe);
 
-            if let Some(duration) = sleep_duration {
+            if let Some((path, kept, closed)) = options.fds_file {
+                let is_open = |fd| unsafe { libc::fcntl(fd, libc::F_GETFD) != -1 };
+                std::fs::write(path, format!("{} {}", is_open(kept), is_open(closed)))
+                    .expect("unable to write fds file");
+            }
+
+            if options.spawn_child {
+                // Never waited, left to the reaper.
+                #[allow(clippy::zombie_processes)]
+                std::process::Command::new("sh")
+                    .args(["-c", "exit 3"])
+                    .spawn()
+                    .expect("unable to spawn child");
+            }
+
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            if let Some(path) = options.orphan_file {
+                spawn_orphan(path);
+            }
+
+            if let Some(duration) = options.sleep_duration {
                 std::thread::sleep(duration)
             }
+
+            if let (Some(handle), Some(path)) = (handle, options.signals_file) {
+                let mut signals = String::new();
+                for signal in handle.signals() {
+                    signals.push_str(&format!("{:?}\n", signal));
+                    if handle.should_shutdown() {
+                        break;
+                    }
+                }
+                std::fs::write(path, signals).expect("unable to write signals file");
+            }
+
+            if options.output_after_sleep {
+                print!("{}", STDOUT_DATA);
+            }
+
+            drop(pid_file_guard);
         }
     }
 }
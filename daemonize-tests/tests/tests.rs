@@ -1,7 +1,10 @@
+extern crate clap;
+extern crate daemonize;
 extern crate daemonize_tests;
+extern crate libc;
 extern crate tempfile;
 
-use daemonize_tests::{Tester, STDERR_DATA, STDOUT_DATA};
+use daemonize_tests::{get_umask, namespace, Tester, STDERR_DATA, STDOUT_DATA};
 use tempfile::TempDir;
 
 #[test]
@@ -17,34 +20,2381 @@ fn chdir() {
 
     let result = Tester::new().working_directory("/usr").run();
     assert_eq!(result.unwrap().cwd.as_str(), "/usr");
+
+    let result = Tester::new().keep_working_directory().run();
+    assert_eq!(
+        result.unwrap().cwd.as_str(),
+        std::env::current_dir().unwrap().to_str().unwrap()
+    );
+}
+
+#[test]
+fn same_user() {
+    let result = Tester::new()
+        .user_string("root")
+        .group_string("root")
+        .run()
+        .unwrap();
+    assert_eq!(result.euid, 0);
+    assert_eq!(result.egid, 0);
+}
+
+#[test]
+fn saved_root_user() {
+    let result = Tester::new()
+        .saved_root_user(65534)
+        .user_num(65534)
+        .run()
+        .unwrap();
+    assert_eq!(
+        (result.uid, result.euid, result.suid),
+        (65534, 65534, 65534)
+    );
+}
+
+#[test]
+fn temporary_privilege_drop() {
+    let result = Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .temporary_privilege_drop()
+        .run()
+        .unwrap();
+    assert_eq!(result.uid, 0);
+    assert_eq!(result.gid, 0);
+    assert_eq!(result.euid, 65534);
+    assert_eq!(result.egid, 1);
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(
+        tmpdir.path(),
+        std::os::unix::fs::PermissionsExt::from_mode(0o777),
+    )
+    .unwrap();
+    let path = tmpdir.path().join("privileges");
+    Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .temporary_privilege_drop()
+        .switch_privileges(&path)
+        .run()
+        .unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "0 0\n65534 1\n");
+}
+
+#[test]
+fn verify_privilege_drop() {
+    let result = Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .verify_privilege_drop()
+        .run()
+        .unwrap();
+    assert_eq!(result.uid, 65534);
+    assert_eq!(result.euid, 65534);
+    assert_eq!(result.gid, 1);
+    assert_eq!(result.egid, 1);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn no_new_privileges() {
+    let result = Tester::new().run().unwrap();
+    assert!(!result.no_new_privileges);
+
+    let result = Tester::new().no_new_privileges().run().unwrap();
+    assert!(result.no_new_privileges);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn landlock() {
+    let allowed = TempDir::new().unwrap();
+    let forbidden = TempDir::new().unwrap();
+    let allowed_path = allowed.path().join("landlock-test");
+    let forbidden_path = forbidden.path().join("landlock-test");
+
+    Tester::new()
+        .landlock_read("/")
+        .landlock_write(allowed.path())
+        .additional_file(&allowed_path)
+        .additional_file(&forbidden_path)
+        .run()
+        .unwrap();
+    assert!(allowed_path.exists());
+    assert!(!forbidden_path.exists());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn user_namespace() {
+    let result = Tester::new().run().unwrap();
+    assert_eq!(result.user_namespace, namespace("user"));
+
+    let result = Tester::new().user_namespace().run().unwrap();
+    assert_ne!(result.user_namespace, namespace("user"));
+    assert_eq!(result.euid, 0);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn user_namespace_id_maps() {
+    let result = Tester::new()
+        .user_namespace()
+        .uid_map(0, 0, 1)
+        .uid_map(1000, 65534, 1)
+        .gid_map(0, 0, 1)
+        .gid_map(1000, 1, 1)
+        .user_num(1000)
+        .group_num(1000)
+        .run()
+        .unwrap();
+    assert_ne!(result.user_namespace, namespace("user"));
+    assert_eq!(result.euid, 1000);
+    assert_eq!(result.egid, 1000);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn namespaces() {
+    let result = Tester::new()
+        .namespace("mnt")
+        .namespace("uts")
+        .run()
+        .unwrap();
+    assert_eq!(result.user_namespace, namespace("user"));
+    assert_ne!(result.mount_namespace, namespace("mnt"));
+    assert_ne!(result.uts_namespace, namespace("uts"));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn pivot_root() {
+    let tmpdir = TempDir::new().unwrap();
+
+    Tester::new()
+        .pivot_root(tmpdir.path())
+        .additional_file("/pivot-root-test")
+        .run()
+        .unwrap();
+    assert!(tmpdir.path().join("pivot-root-test").exists());
+    assert_eq!(
+        std::fs::read_dir(tmpdir.path().join("old-root"))
+            .unwrap()
+            .count(),
+        0
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn pivot_root_replaced() {
+    use daemonize::{Daemonize, Stage};
+
+    let plan = Daemonize::new().pivot_root("/tmp", "old").plan().unwrap();
+    assert!(plan.steps.contains(&Stage::Namespaces));
+
+    // The mount namespace is implied only while pivot_root is used.
+    let plan = Daemonize::new()
+        .pivot_root("/tmp", "old")
+        .chroot("/tmp")
+        .plan()
+        .unwrap();
+    assert!(!plan.steps.contains(&Stage::Namespaces));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn jail_bind_mount() {
+    let jail = TempDir::new().unwrap();
+    let host = TempDir::new().unwrap();
+    let host_file = host.path().join("data");
+    std::fs::write(&host_file, "host data").unwrap();
+
+    Tester::new()
+        .pivot_root(jail.path())
+        .jail_bind_mount(host.path(), "/host")
+        .jail_bind_mount(&host_file, "/etc/data")
+        .additional_file("/host/bind-mount-test")
+        .run()
+        .unwrap();
+    // Mounts are read only and not visible outside of the mount namespace.
+    assert!(!host.path().join("bind-mount-test").exists());
+    assert!(jail.path().join("host").is_dir());
+    assert_eq!(
+        std::fs::read_to_string(jail.path().join("etc/data")).unwrap(),
+        ""
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn chroot_devnull() {
+    use std::os::unix::fs::FileTypeExt;
+
+    let jail = TempDir::new().unwrap();
+
+    Tester::new()
+        .pivot_root(jail.path())
+        .chroot_devnull()
+        .run()
+        .unwrap();
+    let metadata = std::fs::metadata(jail.path().join("dev/null")).unwrap();
+    assert!(metadata.file_type().is_char_device());
+}
+
+#[test]
+fn chroot_working_directory() {
+    let jail = TempDir::new().unwrap();
+    std::fs::create_dir(jail.path().join("var")).unwrap();
+
+    let result = Tester::new()
+        .working_directory("/usr")
+        .chroot(jail.path())
+        .run();
+    assert_eq!(result.unwrap().cwd.as_str(), "/");
+
+    let result = Tester::new()
+        .chroot(jail.path())
+        .chroot_working_directory("/var")
+        .run();
+    assert_eq!(result.unwrap().cwd.as_str(), "/var");
+}
+
+#[test]
+fn redirect_stdin() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdin = tmpdir.path().join("stdin");
+    std::fs::write(&stdin, "stdin data").unwrap();
+
+    let result = Tester::new().stdin(&stdin).run().unwrap();
+    assert_eq!(result.stdin.as_str(), "stdin data");
+
+    let result = Tester::new().stdin_append(&stdin).run().unwrap();
+    assert_eq!(result.stdin.as_str(), "stdin data");
+}
+
+#[test]
+fn redirect_stream_path() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let jail = TempDir::new().unwrap();
+    std::fs::set_permissions(jail.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+
+    Tester::new()
+        .chroot(jail.path())
+        .user_string("nobody")
+        .umask(0o077)
+        .stdout_path("/stdout")
+        .stderr_path("/stderr")
+        .run()
+        .unwrap();
+
+    let stdout = jail.path().join("stdout");
+    assert_eq!(&std::fs::read_to_string(&stdout).unwrap(), STDOUT_DATA);
+    assert_eq!(std::fs::metadata(&stdout).unwrap().uid(), 65534);
+    assert_eq!(std::fs::metadata(&stdout).unwrap().mode() & 0o777, 0o600);
+    assert_eq!(
+        &std::fs::read_to_string(jail.path().join("stderr")).unwrap(),
+        STDERR_DATA
+    );
+}
+
+#[test]
+fn redirect_stream_append() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+    std::fs::write(&stdout, "previous data").unwrap();
+
+    Tester::new().stdout_append(&stdout).run().unwrap();
+    Tester::new().stdout_append(&stdout).run().unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&stdout).unwrap(),
+        format!("previous data{}{}", STDOUT_DATA, STDOUT_DATA)
+    );
+}
+
+#[test]
+fn redirect_stream_fd() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+
+    Tester::new().stdout_fd(&stdout).run().unwrap();
+    assert_eq!(&std::fs::read_to_string(&stdout).unwrap(), STDOUT_DATA);
+}
+
+#[test]
+fn reopen_streams() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+    let rotated = tmpdir.path().join("stdout.1");
+
+    let result = Tester::new()
+        .stdout_path(&stdout)
+        .reopen_streams_on_hup()
+        .sleep(std::time::Duration::from_millis(200))
+        .output_after_sleep()
+        .run()
+        .unwrap();
+
+    std::fs::rename(&stdout, &rotated).unwrap();
+    assert_eq!(
+        unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGHUP) },
+        0
+    );
+
+    let st = std::time::Instant::now();
+    while std::fs::read_to_string(&stdout)
+        .unwrap_or_default()
+        .is_empty()
+    {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(&std::fs::read_to_string(&stdout).unwrap(), STDOUT_DATA);
+    assert_eq!(&std::fs::read_to_string(&rotated).unwrap(), STDOUT_DATA);
+}
+
+#[test]
+fn redirect_stream_decorated() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+
+    Tester::new()
+        .stdout_decorated(&stdout)
+        .sleep(std::time::Duration::from_millis(200))
+        .run()
+        .unwrap();
+
+    let st = std::time::Instant::now();
+    while std::fs::read_to_string(&stdout).unwrap().is_empty() {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    // 2024-01-31T12:00:00.000Z stdout: stdout data
+    let data = std::fs::read_to_string(&stdout).unwrap();
+    let (timestamp, rest) = data.split_at(24);
+    assert!(timestamp.ends_with('Z'), "{}", data);
+    assert_eq!(&timestamp[10..11], "T");
+    assert_eq!(rest, format!(" stdout: {}", STDOUT_DATA));
+}
+
+#[test]
+fn redirect_stream_tee() {
+    let tmpdir = TempDir::new().unwrap();
+    let stderr = tmpdir.path().join("stderr");
+    let terminal = tmpdir.path().join("terminal");
+
+    Tester::new()
+        .stderr_tee(&stderr)
+        .tester_stderr(&terminal)
+        .sleep(std::time::Duration::from_millis(200))
+        .run()
+        .unwrap();
+
+    let st = std::time::Instant::now();
+    while std::fs::read_to_string(&terminal).unwrap().is_empty() {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(&std::fs::read_to_string(&stderr).unwrap(), STDERR_DATA);
+    assert_eq!(&std::fs::read_to_string(&terminal).unwrap(), STDERR_DATA);
+}
+
+#[test]
+fn redirect_stream_piped() {
+    // Tester checks the output received by the parent.
+    let result = Tester::new().stdout_piped().run();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn redirect_stream_logger() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+    std::fs::write(&stdout, "previous data").unwrap();
+
+    let result = Tester::new()
+        .stdout_logger(&stdout)
+        .user_string("nobody")
+        .run()
+        .unwrap();
+    assert_eq!(result.uid, 65534);
+
+    let st = std::time::Instant::now();
+    while std::fs::read_to_string(&stdout).unwrap() == "previous data" {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(
+        std::fs::read_to_string(&stdout).unwrap(),
+        format!("previous data{}", STDOUT_DATA)
+    );
+}
+
+#[test]
+fn merge_stderr_with_stdout() {
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+
+    Tester::new()
+        .stdout_path(&stdout)
+        .merge_stderr()
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&stdout).unwrap(),
+        format!("{}{}", STDOUT_DATA, STDERR_DATA)
+    );
+}
+
+#[test]
+fn foreground() {
+    let result = Tester::new().run().unwrap();
+    assert_ne!(result.ppid, std::process::id());
+
+    let tmpdir = TempDir::new().unwrap();
+    let pid_file = tmpdir.path().join("pid");
+    let result = Tester::new()
+        .foreground()
+        .pid_file(&pid_file)
+        .working_directory(tmpdir.path())
+        .run()
+        .unwrap();
+    assert_eq!(result.ppid, std::process::id());
+    assert_eq!(result.cwd.as_str(), tmpdir.path().to_str().unwrap());
+    assert_eq!(
+        std::fs::read_to_string(&pid_file).unwrap(),
+        format!("{}\n", result.pid)
+    );
+}
+
+#[test]
+fn auto_detect_supervisor() {
+    let result = Tester::new()
+        .auto_detect_supervisor()
+        .env_remove("INVOCATION_ID")
+        .env_remove("NOTIFY_SOCKET")
+        .run()
+        .unwrap();
+    assert_ne!(result.ppid, std::process::id());
+
+    let result = Tester::new()
+        .auto_detect_supervisor()
+        .env("INVOCATION_ID", "0123456789abcdef")
+        .run()
+        .unwrap();
+    assert_eq!(result.ppid, std::process::id());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn init_policy() {
+    let result = Tester::new().as_init().run();
+    assert!(result.is_err());
+
+    let result = Tester::new()
+        .as_init()
+        .init_policy("foreground")
+        .run()
+        .unwrap();
+    assert_eq!(result.pid, 1);
+
+    let result = Tester::new().as_init().init_policy("reaper").run().unwrap();
+    assert_eq!(result.pid, 1);
+}
+
+#[test]
+fn wait_ready() {
+    let timeout = std::time::Duration::from_secs(5);
+
+    let result = Tester::new().wait_ready(timeout).run();
+    assert!(result.is_ok());
+
+    let result = Tester::new().wait_ready(timeout).skip_ready().run();
+    assert!(result.is_err());
+
+    let result = Tester::new()
+        .wait_ready(timeout)
+        .chroot("/nonexistent")
+        .run();
+    assert!(result.is_err());
+}
+
+#[test]
+fn start_sync() {
+    let (status, stderr) = Tester::new().start_sync().run_status();
+    assert!(status.success());
+    assert!(stderr.is_empty());
+
+    let (status, stderr) = Tester::new()
+        .start_sync()
+        .chroot("/nonexistent")
+        .run_status();
+    assert_eq!(status.code(), Some(1));
+    assert!(
+        stderr.contains("unable to chroot into directory"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn start_extended() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+    let path = tmpdir.path().join("start-extended");
+    let pid_file = tmpdir.path().join("pid");
+
+    let (status, _) = Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .pid_file(&pid_file)
+        .start_extended(&path)
+        .run_status();
+    assert!(status.success());
+
+    let st = std::time::Instant::now();
+    while std::fs::read_to_string(&path)
+        .unwrap_or_default()
+        .is_empty()
+    {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("Some(65534) Some(1) {} true", pid.trim())
+    );
+}
+
+#[test]
+fn start_extended_privilege_token() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+    let path = tmpdir.path().join("start-extended");
+    let privileges = tmpdir.path().join("privileges");
+
+    let (status, _) = Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .temporary_privilege_drop()
+        .switch_privileges(&privileges)
+        .start_extended(&path)
+        .run_status();
+    assert!(status.success());
+
+    let st = std::time::Instant::now();
+    while std::fs::read_to_string(&path)
+        .unwrap_or_default()
+        .is_empty()
+    {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(
+        std::fs::read_to_string(&privileges).unwrap(),
+        "0 0\n65534 1\n"
+    );
+
+    let (status, _) = Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .temporary_privilege_drop()
+        .start_sync()
+        .run_status();
+    assert!(!status.success());
+}
+
+#[test]
+fn relay_errors() {
+    let err = Tester::new().chroot("/nonexistent").run().unwrap_err();
+    assert!(err.to_string().contains("unable to chroot"), "{}", err);
+
+    let err = Tester::new()
+        .no_relay_errors()
+        .chroot("/nonexistent")
+        .run()
+        .unwrap_err();
+    assert!(err.to_string().contains("unable to chroot"), "{}", err);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn daemon_pidfd() {
+    let result = Tester::new()
+        .sleep(std::time::Duration::from_millis(100))
+        .wait_daemon_exit()
+        .run();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn timeout() {
+    let timeout = std::time::Duration::from_millis(200);
+
+    let result = Tester::new().timeout(timeout).run();
+    assert!(result.is_ok());
+
+    // Opening a FIFO for writing blocks until there is a reader.
+    let tmpdir = TempDir::new().unwrap();
+    let fifo = tmpdir.path().join("fifo");
+    let path = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(path.as_ptr(), 0o644) }, 0);
+
+    let err = Tester::new()
+        .timeout(timeout)
+        .stdout_path(&fifo)
+        .run()
+        .unwrap_err();
+    assert!(err.to_string().contains("not finished in time"), "{}", err);
+
+    // Unblock the daemon.
+    let mut reader = std::fs::File::open(&fifo).unwrap();
+    std::io::Read::read_to_string(&mut reader, &mut String::new()).unwrap();
+}
+
+#[test]
+fn on_error() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("on-error");
+
+    let result = Tester::new().on_error(&path).run();
+    assert!(result.is_ok());
+    assert!(!path.exists());
+
+    let result = Tester::new().on_error(&path).chroot("/nonexistent").run();
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Chroot\n");
+}
+
+#[test]
+fn log_records() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("log");
+
+    let result = Tester::new().log_file(&path).run();
+    assert!(result.is_ok());
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(log.contains("DEBUG forking the daemon\n"), "{}", log);
+    assert!(log.contains("DEBUG daemonized\n"), "{}", log);
+
+    let result = Tester::new().log_file(&path).chroot("/nonexistent").run();
+    assert!(result.is_err());
+    let log = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        log.contains("WARN daemonization failed at Chroot stage"),
+        "{}",
+        log
+    );
+}
+
+#[test]
+fn plan() {
+    use daemonize::{Daemonize, Stage, StreamTarget};
+
+    let plan = Daemonize::new()
+        .pid_file("test.pid")
+        .user("nobody")
+        .group(1)
+        .chroot("/tmp")
+        .plan()
+        .unwrap();
+    assert_eq!(plan.uid, Some(65534));
+    assert_eq!(plan.gid, Some(1));
+    assert_eq!(plan.pid_file, Some("/test.pid".into()));
+    assert_eq!(plan.root, Some("/tmp".into()));
+    assert_eq!(plan.stdin, StreamTarget::Devnull);
+    assert_eq!(plan.stdout, StreamTarget::Devnull);
+    assert_eq!(
+        plan.steps,
+        vec![
+            Stage::Fork,
+            Stage::ChangeDirectory,
+            Stage::DetachSession,
+            Stage::Umask,
+            Stage::Fork,
+            Stage::PidFile,
+            Stage::RedirectStreams,
+            Stage::PrivilegedAction,
+            Stage::Chroot,
+            Stage::SetGroup,
+            Stage::SetUser,
+            Stage::PidFile,
+        ]
+    );
+
+    let result = Daemonize::new().user("nonexistent-user").plan();
+    assert_eq!(result.unwrap_err().stage(), Stage::SetUser);
+}
+
+#[test]
+fn validate() {
+    use daemonize::Daemonize;
+
+    let tmpdir = TempDir::new().unwrap();
+
+    let result = Daemonize::new()
+        .pid_file(tmpdir.path().join("test.pid"))
+        .validate();
+    assert!(result.is_ok());
+
+    let result = Daemonize::new()
+        .pid_file(tmpdir.path().join("nonexistent/test.pid"))
+        .validate();
+    assert!(result.is_err());
+
+    let result = Daemonize::new().chroot("/nonexistent").validate();
+    assert!(result.is_err());
+
+    let result = Daemonize::new()
+        .chroot(tmpdir.path())
+        .stdout(daemonize::Stdio::from_path("/logs/stdout"))
+        .validate();
+    assert!(result.is_err());
+    std::fs::create_dir(tmpdir.path().join("logs")).unwrap();
+    let result = Daemonize::new()
+        .chroot(tmpdir.path())
+        .stdout(daemonize::Stdio::from_path("/logs/stdout"))
+        .validate();
+    assert!(result.is_ok());
+
+    let result = Daemonize::new()
+        .pid_file(tmpdir.path().join("test.pid"))
+        .handle_signals(&[daemonize::Signal::Hup, daemonize::Signal::Term])
+        .cleanup_pid_file_on_signals(&[daemonize::Signal::Term])
+        .validate();
+    assert_eq!(result.unwrap_err().stage(), daemonize::Stage::Configuration);
+}
+
+#[test]
+fn preflight() {
+    let tmpdir = TempDir::new().unwrap();
+    let log_path = tmpdir.path().join("log");
+    let pid_file = tmpdir.path().join("nonexistent").join("test.pid");
+
+    let result = Tester::new().preflight().run();
+    assert!(result.is_ok());
+
+    let result = Tester::new()
+        .log_file(&log_path)
+        .preflight()
+        .pid_file(&pid_file)
+        .run();
+    assert!(result.is_err());
+    let log = std::fs::read_to_string(&log_path).unwrap();
+    assert!(
+        log.contains("WARN daemonization failed at PidFile stage"),
+        "{}",
+        log
+    );
+    assert!(!log.contains("forking"), "{}", log);
+}
+
+#[test]
+fn custom_step() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+    let setsid_file = tmpdir.path().join("setsid");
+    let drop_file = tmpdir.path().join("drop");
+
+    let result = Tester::new()
+        .user_string("nobody")
+        .step_file("setsid", &setsid_file)
+        .step_file("privilege-drop", &drop_file)
+        .run();
+    assert!(result.is_ok());
+    assert_eq!(setsid_file.metadata().unwrap().uid(), 0);
+    assert_eq!(drop_file.metadata().unwrap().uid(), 65534);
+
+    let on_error = tmpdir.path().join("on-error");
+    let result = Tester::new().on_error(&on_error).failing_step().run();
+    assert!(result.is_err());
+    assert_eq!(
+        std::fs::read_to_string(&on_error).unwrap(),
+        "Custom: step failed\n"
+    );
+}
+
+#[test]
+fn channel() {
+    let result = Tester::new().channel().run();
+    assert!(result.is_ok());
+}
+
+#[test]
+fn detach_session() {
+    let sid = unsafe { libc::getsid(0) } as u32;
+
+    let result = Tester::new().run().unwrap();
+    assert_ne!(result.sid, sid);
+
+    let result = Tester::new().keep_session().run().unwrap();
+    assert_eq!(result.sid, sid);
+}
+
+#[test]
+fn umask() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("umask-test");
+
+    let result = Tester::new().umask(0o222).additional_file(&path).run();
+    assert!(result.is_ok());
+    assert!(path.metadata().unwrap().permissions().readonly());
+}
+
+#[test]
+fn mask() {
+    use daemonize::Mask;
+
+    assert_eq!(
+        Mask::from_octal(0o027),
+        Mask::GROUP_WRITE | Mask::OTHERS_ALL
+    );
+    assert_eq!(Mask::from_octal(0o027), Mask::from(0o027));
+    assert_eq!(Mask::from_octal(0o777).bits(), 0o777);
+    assert!(std::panic::catch_unwind(|| Mask::from_octal(777)).is_err());
+
+    let result = Tester::new()
+        .umask(Mask::GROUP_ALL.bits() | Mask::OTHERS_WRITE.bits())
+        .run()
+        .unwrap();
+    assert_eq!(result.umask, 0o072);
+}
+
+#[test]
+fn keep_umask() {
+    let result = Tester::new().run().unwrap();
+    assert_eq!(result.umask, 0o027);
+
+    let result = Tester::new().keep_umask().run().unwrap();
+    assert_eq!(result.umask, get_umask());
+}
+
+#[test]
+fn simple_constructor() {
+    let result = Tester::new().simple(false, false).run().unwrap();
+    assert_eq!(result.cwd.as_str(), "/");
+    assert_eq!(result.umask, get_umask());
+
+    let tmpdir = TempDir::new().unwrap();
+    let stdout = tmpdir.path().join("stdout");
+    let stderr = tmpdir.path().join("stderr");
+    // stdout of the tester is reserved for the result.
+    let result = Tester::new()
+        .simple(true, true)
+        .stdout(&stdout)
+        .tester_stderr(&stderr)
+        .run()
+        .unwrap();
+    assert_eq!(
+        result.cwd.as_str(),
+        std::env::current_dir().unwrap().to_str().unwrap()
+    );
+    assert_eq!(&std::fs::read_to_string(&stderr).unwrap(), STDERR_DATA);
+}
+
+#[test]
+fn pid() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run();
+    let pid_content = std::fs::read_to_string(&path).unwrap();
+    assert!(pid_content.ends_with('\n'));
+    let pid: u32 = pid_content[..pid_content.len() - 1].parse().unwrap();
+    assert_eq!(result.unwrap().pid, pid);
+
+    let result = Tester::new().pid_file(&path).run();
+    assert!(result.is_err());
+}
+
+#[test]
+fn pid_file_write() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .user_string("nobody")
+        .pid_file(&path)
+        .pid_file_write("after-privilege-drop")
+        .run();
+    let pid_content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(pid_content, format!("{}\n", result.unwrap().pid));
+    assert_eq!(path.metadata().unwrap().uid(), 65534);
+    std::fs::remove_file(&path).unwrap();
+
+    let root = TempDir::new().unwrap();
+    let result = Tester::new()
+        .chroot(root.path())
+        .pid_file(&path)
+        .pid_file_write("before-chroot")
+        .run();
+    let pid_content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(pid_content, format!("{}\n", result.unwrap().pid));
+}
+
+#[test]
+fn multiple_privileged_actions() {
+    let tmpdir = TempDir::new().unwrap();
+    let first = tmpdir.path().join("first");
+    let second = tmpdir.path().join("second");
+
+    Tester::new()
+        .privileged_files(&first, &second)
+        .run()
+        .unwrap();
+    assert!(first.exists());
+    assert!(second.exists());
+}
+
+#[test]
+fn privileged_context() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("context");
+
+    let result = Tester::new()
+        .user_string("nobody")
+        .group_string("daemon")
+        .pid_file(tmpdir.path().join("pid"))
+        .working_directory(tmpdir.path())
+        .privileged_context_file(&path)
+        .run()
+        .unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(
+        content,
+        format!(
+            "Some(65534) Some(1) true {} {}",
+            result.pid,
+            tmpdir.path().display()
+        )
+    );
+
+    let root = TempDir::new().unwrap();
+    let result = Tester::new()
+        .chroot(root.path())
+        .privileged_context_file(&path)
+        .run()
+        .unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(content, format!("None None false {} /", result.pid));
+}
+
+#[test]
+fn try_privileged_action() {
+    let tmpdir = TempDir::new().unwrap();
+    let on_error = tmpdir.path().join("on-error");
+    let step_file = tmpdir.path().join("step");
+
+    let result = Tester::new()
+        .on_error(&on_error)
+        .failing_privileged_action()
+        .step_file("privileged-action", &step_file)
+        .run();
+    assert!(result.is_err());
+    assert_eq!(
+        std::fs::read_to_string(&on_error).unwrap(),
+        "PrivilegedAction: action failed\n"
+    );
+    assert!(!step_file.exists());
+}
+
+#[test]
+fn post_privilege_action() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+    let path = tmpdir.path().join("post-privilege");
+
+    let result = Tester::new()
+        .user_string("nobody")
+        .post_privilege_file(&path)
+        .run();
+    assert!(result.is_ok());
+    assert_eq!(path.metadata().unwrap().uid(), 65534);
+
+    let result = Tester::new()
+        .user_string("nobody")
+        .post_privilege_file(tmpdir.path().join("missing").join("file"))
+        .run();
+    assert!(result.is_err());
+}
+
+#[test]
+fn post_fork_action() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("post-fork");
+
+    let result = Tester::new().post_fork_file(&path).run().unwrap();
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines = content.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("FirstChild "));
+    assert_ne!(lines[0], format!("FirstChild {}", result.pid));
+    assert_eq!(lines[1], format!("Daemon {}", result.pid));
+
+    std::fs::remove_file(&path).unwrap();
+    Tester::new()
+        .foreground()
+        .post_fork_file(&path)
+        .run()
+        .unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn pre_fork_action() {
+    let tmpdir = TempDir::new().unwrap();
+    let on_error = tmpdir.path().join("on-error");
+    let post_fork = tmpdir.path().join("post-fork");
+
+    let result = Tester::new()
+        .on_error(&on_error)
+        .post_fork_file(&post_fork)
+        .failing_pre_fork_action()
+        .run();
+    assert!(result.is_err());
+    assert_eq!(
+        std::fs::read_to_string(&on_error).unwrap(),
+        "Configuration: Address already in use (os error 98)\n"
+    );
+    assert!(!post_fork.exists());
+}
+
+#[test]
+fn pid_file_guard() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .pid_file_guard()
+        .sleep(std::time::Duration::from_millis(100))
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n", result.pid)
+    );
+
+    let st = std::time::Instant::now();
+    while path.exists() {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    // Created before the root change, removed from inside the new root.
+    let jail = TempDir::new().unwrap();
+    let path = jail.path().join("pid");
+    Tester::new()
+        .pid_file(&path)
+        .chroot(jail.path())
+        .pid_file_guard()
+        .run()
+        .unwrap();
+    let st = std::time::Instant::now();
+    while path.exists() {
+        assert!(st.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn cleanup_pid_file_on_signals() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .cleanup_pid_file_on_term()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert!(path.exists());
+    assert_eq!(
+        unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGTERM) },
+        0
+    );
+
+    let st = std::time::Instant::now();
+    while path.exists() {
+        assert!(st.elapsed() < std::time::Duration::from_secs(4));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn pid_file_start_time() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .pid_file_start_time()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    let start_time = daemonize::process_start_time(pid).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n{}\n", pid, start_time)
+    );
+    let info = daemonize::PidFileInfo::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(info.pid, pid);
+    assert_eq!(info.start_time, Some(start_time));
+    assert!(daemonize::is_process_running(pid, None));
+    assert!(daemonize::is_process_running(pid, Some(start_time)));
+    assert!(!daemonize::is_process_running(pid, Some(start_time + 1)));
+    assert!(!daemonize::is_process_running(0, None));
+    assert!(!daemonize::is_process_running(-1, None));
+    assert!(daemonize::PidFileInfo::parse("0\n").is_err());
+    assert!(daemonize::PidFileInfo::parse("-1\n").is_err());
+
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+}
+
+#[test]
+fn pid_file_contents() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .pid_file_no_newline()
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        result.pid.to_string()
+    );
+}
+
+#[test]
+fn pid_file_lock() {
+    let tmpdir = TempDir::new().unwrap();
+
+    let mut styles = vec!["flock", "posix"];
+    if cfg!(target_os = "linux") {
+        styles.push("ofd");
+    }
+    for style in styles {
+        let path = tmpdir.path().join(style);
+        let result = Tester::new()
+            .pid_file(&path)
+            .pid_file_lock(style)
+            .sleep(std::time::Duration::from_secs(5))
+            .run()
+            .unwrap();
+        let second = Tester::new().pid_file(&path).pid_file_lock(style).run();
+        assert!(second.is_err(), "{}", style);
+        let atomic = Tester::new()
+            .pid_file(&path)
+            .pid_file_lock(style)
+            .atomic_pid_file()
+            .run();
+        assert!(atomic.is_err(), "{}", style);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("{}\n", result.pid)
+        );
+        assert_eq!(
+            daemonize::read_pid_file(&path).unwrap(),
+            result.pid as libc::pid_t,
+            "{}",
+            style
+        );
+        unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+    }
+}
+
+#[test]
+fn read_pid_file() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .pid_file_json("1.0.0")
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(
+        daemonize::read_pid_file(&path).unwrap(),
+        result.pid as libc::pid_t
+    );
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let stale = tmpdir.path().join("stale");
+    std::fs::write(&stale, "1\n").unwrap();
+    let err = daemonize::read_pid_file(&stale).unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::PidFile);
+
+    let invalid = tmpdir.path().join("invalid");
+    std::fs::write(&invalid, "pid\n").unwrap();
+    assert!(daemonize::read_pid_file(&invalid).is_err());
+    assert!(daemonize::read_pid_file(tmpdir.path().join("missing")).is_err());
+}
+
+#[test]
+fn pid_file_fd() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    std::fs::write(&path, "stale contents\n").unwrap();
+    let jail = TempDir::new().unwrap();
+
+    let result = Tester::new()
+        .pid_file_fd(&path)
+        .chroot(jail.path())
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n", result.pid)
+    );
+    assert_eq!(
+        daemonize::read_pid_file(&path).unwrap(),
+        result.pid as libc::pid_t
+    );
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+fn pid_file_default() {
+    let directory = std::path::Path::new("/run").join(format!("daemonize-{}", std::process::id()));
+    let name = format!(
+        "{}/daemon",
+        directory.file_name().unwrap().to_str().unwrap()
+    );
+
+    let plan = daemonize::Daemonize::new()
+        .pid_file_default(&name)
+        .plan()
+        .unwrap();
+    assert_eq!(plan.pid_file, Some(directory.join("daemon.pid")));
+
+    let result = Tester::new().pid_file_default(&name).run().unwrap();
+    let contents = std::fs::read_to_string(directory.join("daemon.pid")).unwrap();
+    std::fs::remove_dir_all(&directory).unwrap();
+    assert_eq!(contents, format!("{}\n", result.pid));
+
+    let result = Tester::new()
+        .pid_file_default(&name)
+        .pid_file(directory.join("daemon.pid"))
+        .run();
+    assert!(!directory.exists());
+    assert_eq!(result.unwrap_err().stage(), daemonize::Stage::PidFile);
+}
+
+#[test]
+fn instance() {
+    let tmpdir = TempDir::new().unwrap();
+
+    let plan = daemonize::Daemonize::new()
+        .instance("worker1")
+        .pid_file(tmpdir.path().join("app-%i-%%i.pid"))
+        .plan()
+        .unwrap();
+    assert_eq!(
+        plan.pid_file,
+        Some(tmpdir.path().join("app-worker1-%i.pid"))
+    );
+
+    let result = Tester::new()
+        .instance("worker1")
+        .pid_file(tmpdir.path().join("app-%i.pid"))
+        .stdout_path(tmpdir.path().join("app-%i.log"))
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(tmpdir.path().join("app-worker1.pid")).unwrap(),
+        format!("{}\n", result.pid)
+    );
+    assert_eq!(
+        std::fs::read_to_string(tmpdir.path().join("app-worker1.log")).unwrap(),
+        STDOUT_DATA
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn single_instance() {
+    let name = format!("daemonize-test-{}", std::process::id());
+
+    let result = Tester::new()
+        .single_instance(&name)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let err = Tester::new().single_instance(&name).run().unwrap_err();
+    assert!(err.to_string().contains("another instance"), "{}", err);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let start = std::time::Instant::now();
+    while Tester::new().single_instance(&name).run().is_err() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn is_running() {
+    use daemonize::control;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    assert_eq!(control::is_running(&path).unwrap(), None);
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    assert_eq!(control::is_running(&path).unwrap(), Some(pid));
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+
+    let start = std::time::Instant::now();
+    while control::is_running(&path).unwrap().is_some() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    std::fs::write(&path, "invalid\n").unwrap();
+    assert!(control::is_running(&path).is_err());
+}
+
+#[test]
+fn stop() {
+    use daemonize::control::{self, Stop, Stopped};
+    use daemonize::Signal;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    assert_eq!(
+        control::stop(&path, Stop::default()).unwrap(),
+        Stopped::NotRunning
+    );
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(
+        control::stop(&path, Stop::default()).unwrap(),
+        Stopped::Exited(result.pid as libc::pid_t)
+    );
+
+    // SIGHUP only reopens the streams, so the daemon keeps running.
+    let path = tmpdir.path().join("hup");
+    let result = Tester::new()
+        .pid_file(&path)
+        .reopen_streams_on_hup()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let hup = Stop {
+        signal: Signal::Hup,
+        grace: std::time::Duration::from_millis(200),
+        kill_after: false,
+    };
+    let err = control::stop(&path, hup).unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::Control);
+    let hup = Stop {
+        kill_after: true,
+        ..hup
+    };
+    assert_eq!(
+        control::stop(&path, hup).unwrap(),
+        Stopped::Killed(result.pid as libc::pid_t)
+    );
+}
+
+#[test]
+fn reload() {
+    use daemonize::control;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    let stdout = tmpdir.path().join("stdout");
+    assert_eq!(control::reload(&path, None).unwrap(), None);
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .stdout_path(&stdout)
+        .reopen_streams_on_hup()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    std::fs::remove_file(&stdout).unwrap();
+    assert_eq!(control::reload(&path, None).unwrap(), Some(pid));
+
+    // The daemon reopens the removed stdout file on SIGHUP.
+    let start = std::time::Instant::now();
+    while !stdout.exists() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert_eq!(control::is_running(&path).unwrap(), Some(pid));
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+}
+
+#[test]
+fn status() {
+    use daemonize::control;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    let status = control::status(&path).unwrap();
+    assert_eq!(
+        (status.pid, status.running, status.locked),
+        (None, false, false)
+    );
+
+    // The boot time is recorded in seconds and drifts with the wall clock.
+    let slack = std::time::Duration::from_secs(5);
+    let before = std::time::SystemTime::now() - slack;
+    let result = Tester::new()
+        .pid_file(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    let status = control::status(&path).unwrap();
+    assert_eq!(
+        (status.pid, status.running, status.locked),
+        (Some(pid), true, true)
+    );
+    if cfg!(target_os = "linux") {
+        let started_at = status.started_at.unwrap();
+        assert!(started_at >= before, "{:?} {:?}", started_at, before);
+        assert!(started_at <= std::time::SystemTime::now() + slack);
+    }
+    unsafe { libc::kill(pid, libc::SIGKILL) };
+
+    // The killed daemon may still hold the lock of the first pid file.
+    let stale = tmpdir.path().join("stale");
+    std::fs::write(&stale, "1\n").unwrap();
+    let status = control::status(&stale).unwrap();
+    assert_eq!(
+        (status.pid, status.running, status.locked),
+        (Some(1), true, false)
+    );
 }
 
 #[test]
-fn umask() {
+fn restart() {
+    use daemonize::control::{self, Stop, Stopped};
+
     let tmpdir = TempDir::new().unwrap();
-    let path = tmpdir.path().join("umask-test");
+    let path = tmpdir.path().join("pid");
+    let launch = || {
+        Tester::new()
+            .pid_file(&path)
+            .sleep(std::time::Duration::from_secs(5))
+            .run()
+            .unwrap()
+            .pid as libc::pid_t
+    };
 
-    let result = Tester::new().umask(0o222).additional_file(&path).run();
-    assert!(result.is_ok());
-    assert!(path.metadata().unwrap().permissions().readonly());
+    let (stopped, first) = control::restart(&path, Stop::default(), launch).unwrap();
+    assert_eq!(stopped, Stopped::NotRunning);
+    let (stopped, second) = control::restart(&path, Stop::default(), launch).unwrap();
+    assert_eq!(stopped, Stopped::Exited(first));
+    assert_eq!(control::is_running(&path).unwrap(), Some(second));
+    unsafe { libc::kill(second, libc::SIGKILL) };
+
+    // The lock is still held although the process recorded in the pid file is gone.
+    let mut exited = std::process::Command::new("true").spawn().unwrap();
+    exited.wait().unwrap();
+    let held = tmpdir.path().join("held");
+    let mut pid_file = daemonize::PidFile::new(&held);
+    pid_file.lock().unwrap();
+    pid_file.write(exited.id() as libc::pid_t).unwrap();
+    let stop = Stop {
+        grace: std::time::Duration::from_millis(100),
+        ..Stop::default()
+    };
+    let err = control::restart(&held, stop, || unreachable!()).unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::Control);
 }
 
 #[test]
-fn pid() {
+fn cli() {
+    use clap::Parser;
+    use daemonize::cli::{Command, Control, DaemonizeArgs};
+    use daemonize::control::Stopped;
+
+    #[derive(clap::Parser)]
+    struct Cli {
+        #[command(flatten)]
+        daemonize: DaemonizeArgs,
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    let parse = |args: &[&str]| {
+        let pid_file = path.to_str().unwrap();
+        Cli::try_parse_from(["app", "--pid-file", pid_file].iter().chain(args)).unwrap()
+    };
+
+    let cli = parse(&[
+        "--user", "nobody", "--chdir", "/tmp", "--umask", "027", "start",
+    ]);
+    assert_eq!(cli.daemonize.umask, Some(0o027));
+    let daemonize = match cli.daemonize.run(&cli.command).unwrap() {
+        Control::Start(daemonize) => daemonize,
+        control => panic!("{:?}", control),
+    };
+    let plan = daemonize.plan().unwrap();
+    assert_eq!(plan.uid, Some(65534));
+    assert_eq!(plan.working_directory, Some("/tmp".into()));
+    assert_eq!(plan.pid_file, Some(path.clone()));
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    let cli = parse(&["status"]);
+    match cli.daemonize.run(&cli.command).unwrap() {
+        Control::Status(status) => assert_eq!(status.pid, Some(pid)),
+        control => panic!("{:?}", control),
+    }
+    let cli = parse(&["stop", "--grace", "5"]);
+    match cli.daemonize.run(&cli.command).unwrap() {
+        Control::Stopped(stopped) => assert_eq!(stopped, Stopped::Exited(pid)),
+        control => panic!("{:?}", control),
+    }
+
+    let cli = Cli::try_parse_from(["app", "status"]).unwrap();
+    let err = cli.daemonize.run(&cli.command).unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::Configuration);
+}
+
+#[test]
+fn control_socket() {
+    use daemonize::control::{self, Request};
+    use std::os::unix::fs::PermissionsExt;
+
     let tmpdir = TempDir::new().unwrap();
     let path = tmpdir.path().join("pid");
+    let socket = tmpdir.path().join("control.sock");
+    // Left by a killed daemon, nobody listens on it.
+    drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .control_socket(&socket)
+        .reopen_streams_on_hup()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    assert_eq!(control::request(&socket, Request::Ping).unwrap(), "pong");
+    assert_eq!(
+        control::request(&socket, Request::Status).unwrap(),
+        format!("pid {}", pid)
+    );
+    assert_eq!(control::request(&socket, Request::Reload).unwrap(), "ok");
+    assert_eq!(control::request(&socket, Request::Ping).unwrap(), "pong");
+
+    let err = Tester::new().control_socket(&socket).run().unwrap_err();
+    assert!(err.to_string().contains("control socket"), "{}", err);
+
+    // Without the pid file lock a stale socket may belong to an instance about to listen on it.
+    let stale = tmpdir.path().join("stale.sock");
+    drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+    let err = Tester::new().control_socket(&stale).run().unwrap_err();
+    assert!(err.to_string().contains("control socket"), "{}", err);
+
+    assert_eq!(control::request(&socket, Request::Shutdown).unwrap(), "ok");
+    let start = std::time::Instant::now();
+    while control::is_running(&path).unwrap().is_some() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn handle_signals() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("signals");
+
+    let result = Tester::new().handle_signals(&path).run().unwrap();
+    let pid = result.pid as libc::pid_t;
+    for signal in &[libc::SIGHUP, libc::SIGHUP, libc::SIGTERM] {
+        unsafe { libc::kill(pid, *signal) };
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    let start = std::time::Instant::now();
+    while std::fs::read_to_string(&path).unwrap_or_default() != "Hup\nHup\nTerm\n" {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+// The tests enable the signal-hook feature, so all handlers of a signal run.
+#[test]
+fn signal_hook_handlers() {
+    let tmpdir = TempDir::new().unwrap();
+    let signals = tmpdir.path().join("signals");
+    let stdout = tmpdir.path().join("stdout");
+
+    let result = Tester::new()
+        .stdout_path(&stdout)
+        .reopen_streams_on_hup()
+        .handle_signals(&signals)
+        .run()
+        .unwrap();
+    let pid = result.pid as libc::pid_t;
+    std::fs::remove_file(&stdout).unwrap();
+    unsafe { libc::kill(pid, libc::SIGHUP) };
+
+    let start = std::time::Instant::now();
+    while !stdout.exists() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    unsafe { libc::kill(pid, libc::SIGTERM) };
+    while std::fs::read_to_string(&signals).unwrap_or_default() != "Hup\nTerm\n" {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn reset_signals() {
+    // Bit masks of `SigIgn`, `SigBlk` and `SigCgt` lines in /proc/<pid>/status.
+    let signal_masks = |pid: u32| {
+        let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).unwrap();
+        let mask = |name: &str| {
+            let line = status.lines().find(|line| line.starts_with(name)).unwrap();
+            u64::from_str_radix(line[name.len()..].trim(), 16).unwrap()
+        };
+        (mask("SigIgn:"), mask("SigBlk:"), mask("SigCgt:"))
+    };
+    let bit = |signal: libc::c_int| 1u64 << (signal - 1);
+    let ignored = [libc::SIGHUP, libc::SIGUSR1];
+    let blocked = [libc::SIGUSR2];
+
+    let result = Tester::new()
+        .signal_state(&ignored, &blocked)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let (ignored_mask, blocked_mask, _) = signal_masks(result.pid);
+    assert_eq!(ignored_mask & bit(libc::SIGUSR1), bit(libc::SIGUSR1));
+    assert_eq!(blocked_mask & bit(libc::SIGUSR2), bit(libc::SIGUSR2));
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let result = Tester::new()
+        .signal_state(&ignored, &blocked)
+        .reset_signals()
+        .keep_ignored_signal(libc::SIGHUP)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let (ignored_mask, blocked_mask, caught_mask) = signal_masks(result.pid);
+    // Signals reserved by libc can't be changed.
+    let standard = bit(32) - 1;
+    assert_eq!(ignored_mask & standard, bit(libc::SIGHUP));
+    assert_eq!(blocked_mask & standard, 0);
+    // Stack overflow handlers of the Rust runtime.
+    let overflow = bit(libc::SIGSEGV) | bit(libc::SIGBUS);
+    assert_eq!(caught_mask & overflow, overflow);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
 
     let result = Tester::new()
+        .reset_signals()
+        .ignore_sigpipe()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let (ignored_mask, _, _) = signal_masks(result.pid);
+    assert_eq!(ignored_mask & standard, bit(libc::SIGPIPE));
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn parent_death_signal() {
+    use daemonize::control;
+
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    // The daemon outlives its first child and dies with the tester, its subreaper.
+    Tester::new()
         .pid_file(&path)
+        .parent_death_signal()
+        .sleep(std::time::Duration::from_secs(30))
+        .run()
+        .unwrap();
+    let start = std::time::Instant::now();
+    while control::is_running(&path).unwrap().is_some() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    // The privileges drop clears the signal, so it's set afterwards.
+    Tester::new()
+        .pid_file(&path)
+        .user_num(65534)
+        .parent_death_signal()
+        .sleep(std::time::Duration::from_secs(30))
+        .run()
+        .unwrap();
+    let start = std::time::Instant::now();
+    while control::is_running(&path).unwrap().is_some() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
+
+#[test]
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn subreaper() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("orphan");
+
+    let result = Tester::new()
+        .subreaper(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    // The orphan writes the file once it's reparented.
+    let start = std::time::Instant::now();
+    let parent = loop {
+        match std::fs::read_to_string(&path) {
+            Ok(parent) if !parent.is_empty() => break parent,
+            _ => assert!(start.elapsed() < std::time::Duration::from_secs(5)),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert_eq!(parent, result.pid.to_string());
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn process_name() {
+    let result = Tester::new()
+        .process_name("tester-daemon")
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let name = std::fs::read_to_string(format!("/proc/{}/comm", result.pid)).unwrap();
+    assert_eq!(name, "tester-daemon\n");
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn proc_title() {
+    let result = Tester::new()
+        .proc_title("tester: serving")
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let cmdline = std::fs::read_to_string(format!("/proc/{}/cmdline", result.pid)).unwrap();
+    assert_eq!(cmdline.split('\0').next(), Some("tester: serving"));
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+fn nice() {
+    let result = Tester::new()
+        .nice(10)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, result.pid as libc::id_t) };
+    assert_eq!(nice, 10);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    // Raising the priority requires root, the daemon drops privileges afterwards.
+    let result = Tester::new()
+        .nice(-5)
+        .user_num(65534)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let nice = unsafe { libc::getpriority(libc::PRIO_PROCESS, result.pid as libc::id_t) };
+    assert_eq!(nice, -5);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn io_priority() {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+    let result = Tester::new()
+        .io_priority(6)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let priority =
+        unsafe { libc::syscall(libc::SYS_ioprio_get, IOPRIO_WHO_PROCESS, result.pid) as i32 };
+    // Best effort class.
+    assert_eq!(priority, 2 << 13 | 6);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn oom_score_adj() {
+    let result = Tester::new()
+        .oom_score_adj(500)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let path = format!("/proc/{}/oom_score_adj", result.pid);
+    assert_eq!(std::fs::read_to_string(path).unwrap(), "500\n");
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let err = Tester::new().oom_score_adj(2000).run().unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::Priority);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn rlimit() {
+    let limits = |pid: u32| {
+        let limits = std::fs::read_to_string(format!("/proc/{}/limits", pid)).unwrap();
+        let line = limits
+            .lines()
+            .find(|line| line.starts_with("Max open files"))
+            .unwrap()
+            .to_owned();
+        line.split_whitespace()
+            .skip(3)
+            .take(2)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    // The hard limit is lowered by root before privileges are dropped.
+    let result = Tester::new()
+        .nofile_limit(256, 512)
+        .user_num(65534)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(limits(result.pid), "256 512");
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let err = Tester::new().nofile_limit(512, 256).run().unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::ResourceLimits);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn core_dumps() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let core_limit = |pid: u32| {
+        let limits = std::fs::read_to_string(format!("/proc/{}/limits", pid)).unwrap();
+        let line = limits
+            .lines()
+            .find(|line| line.starts_with("Max core file size"))
+            .unwrap()
+            .to_owned();
+        line.split_whitespace()
+            .skip(4)
+            .take(2)
+            .map(str::to_owned)
+            .collect::<Vec<_>>()
+    };
+    // Files in /proc/<pid> belong to root unless the process is dumpable.
+    let proc_owner = |pid: u32| {
+        std::fs::metadata(format!("/proc/{}/status", pid))
+            .unwrap()
+            .uid()
+    };
+
+    let result = Tester::new()
+        .disable_core_dumps()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(core_limit(result.pid), ["0", "0"]);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    let result = Tester::new()
+        .allow_core_dumps(tmpdir.path())
+        .user_num(65534)
         .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let limit = core_limit(result.pid);
+    assert_eq!(limit[0], limit[1]);
+    assert_eq!(proc_owner(result.pid), 65534);
+    assert_eq!(
+        std::fs::read_link(format!("/proc/{}/cwd", result.pid)).unwrap(),
+        tmpdir.path()
+    );
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+
+    let result = Tester::new()
+        .disable_core_dumps()
+        .user_num(65534)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(proc_owner(result.pid), 0);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn lock_memory() {
+    let result = Tester::new()
+        .lock_memory()
+        .user_num(65534)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let status = std::fs::read_to_string(format!("/proc/{}/status", result.pid)).unwrap();
+    let locked = status
+        .lines()
+        .find(|line| line.starts_with("VmLck:"))
+        .unwrap();
+    assert_ne!(locked.split_whitespace().nth(1), Some("0"), "{}", locked);
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+fn close_fds() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("fds");
+
+    Tester::new()
+        .close_fds(&path)
+        .pid_file(tmpdir.path().join("pid"))
+        .run()
+        .unwrap();
+    let start = std::time::Instant::now();
+    let fds = loop {
+        match std::fs::read_to_string(&path) {
+            Ok(fds) if !fds.is_empty() => break fds,
+            _ => assert!(start.elapsed() < std::time::Duration::from_secs(5)),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    assert_eq!(fds, "true false");
+}
+
+#[test]
+fn inherit_fd() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("fds");
+
+    Tester::new().inherit_fd(&path).run().unwrap();
+    let fds = std::fs::read_to_string(&path).unwrap();
+    let fds = fds
+        .lines()
+        .map(|line| {
+            let (fd, cloexec) = line.split_once(' ').unwrap();
+            (fd.parse::<libc::c_int>().unwrap(), cloexec == "true")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(fds.len(), 2);
+    assert!(
+        fds.iter().all(|&(fd, cloexec)| fd > 2 && !cloexec),
+        "{:?}",
+        fds
+    );
+    assert_ne!(fds[0].0, fds[1].0);
+}
+
+#[test]
+fn clear_environment() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("env");
+    let read_env = |path: &std::path::Path| {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let (name, value) = line.split_once('=').unwrap();
+                (name.to_owned(), value.to_owned())
+            })
+            .collect::<std::collections::HashMap<_, _>>()
+    };
+
+    Tester::new()
+        .env("DAEMONIZE_KEPT", "kept")
+        .env("DAEMONIZE_REMOVED", "removed")
+        .env("PATH", "/custom/bin")
+        .clear_environment(&["DAEMONIZE_KEPT", "PATH"])
+        .env_file(&path)
+        .run()
+        .unwrap();
+    let env = read_env(&path);
+    assert_eq!(env.len(), 2, "{:?}", env);
+    assert_eq!(env["DAEMONIZE_KEPT"], "kept");
+    assert_eq!(env["PATH"], "/custom/bin");
+
+    Tester::new()
+        .env("DAEMONIZE_REMOVED", "removed")
+        .clear_environment(&[])
+        .env_file(&path)
+        .run()
+        .unwrap();
+    let env = read_env(&path);
+    assert_eq!(env.len(), 1, "{:?}", env);
+    assert!(env["PATH"].split(':').any(|dir| dir == "/usr/bin"));
+
+    // Can't be removed, so it's kept instead of failing.
+    Tester::new()
+        .env("=malformed", "kept")
+        .clear_environment(&[])
+        .env_file(&path)
+        .run()
+        .unwrap();
+    let env = std::fs::read_to_string(&path).unwrap();
+    assert!(
+        env.lines().any(|line| line == "=malformed=kept"),
+        "{:?}",
+        env
+    );
+}
+
+#[test]
+fn env() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("env");
+
+    Tester::new()
+        .env("DAEMONIZE_OVERRIDDEN", "tester")
+        .daemon_env("DAEMONIZE_OVERRIDDEN", "daemon")
+        .daemon_env("DAEMONIZE_ADDED", "a=b")
+        .env_file(&path)
+        .run()
+        .unwrap();
+    let env = std::fs::read_to_string(&path).unwrap();
+    let lines = env.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"DAEMONIZE_OVERRIDDEN=daemon"), "{}", env);
+    assert!(lines.contains(&"DAEMONIZE_ADDED=a=b"), "{}", env);
+
+    let result = Tester::new().daemon_env("", "value").run();
+    assert_eq!(result.unwrap_err().stage(), daemonize::Stage::Environment);
+}
+
+#[test]
+fn set_user_env() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o777)).unwrap();
+    let path = tmpdir.path().join("env");
+
+    let (home, shell) = unsafe {
+        let entry = &*libc::getpwuid(65534);
+        let string = |ptr| std::ffi::CStr::from_ptr(ptr).to_str().unwrap().to_owned();
+        (string(entry.pw_dir), string(entry.pw_shell))
+    };
+
+    Tester::new()
+        .env("HOME", "/root")
+        .user_num(65534)
+        .set_user_env()
+        .env_file(&path)
+        .run()
+        .unwrap();
+    let env = std::fs::read_to_string(&path).unwrap();
+    let lines = env.lines().collect::<Vec<_>>();
+    for expected in &[
+        format!("HOME={}", home),
+        "USER=nobody".to_owned(),
+        "LOGNAME=nobody".to_owned(),
+        format!("SHELL={}", shell),
+    ] {
+        assert!(lines.contains(&expected.as_str()), "{}", env);
+    }
+
+    Tester::new()
+        .user_num(65534)
+        .set_user_env()
+        .daemon_env("HOME", "/var/lib/daemon")
+        .env_file(&path)
+        .run()
+        .unwrap();
+    let env = std::fs::read_to_string(&path).unwrap();
+    let lines = env.lines().collect::<Vec<_>>();
+    assert!(lines.contains(&"HOME=/var/lib/daemon"), "{}", env);
+    assert!(lines.contains(&"USER=nobody"), "{}", env);
+}
+
+#[test]
+fn working_directory_user_home() {
+    let home = |uid| unsafe {
+        let entry = libc::getpwuid(uid);
+        assert!(!entry.is_null());
+        let home = std::ffi::CStr::from_ptr((*entry).pw_dir).to_str().unwrap();
+        std::fs::canonicalize(home).unwrap()
+    };
+
+    let data = Tester::new().working_directory_user_home().run().unwrap();
+    assert_eq!(
+        std::path::Path::new(data.cwd.as_str()),
+        home(unsafe { libc::getuid() })
+    );
+
+    let result = Tester::new()
+        .user_num(54321)
+        .working_directory_user_home()
         .run();
-    let pid_content = std::fs::read_to_string(&path).unwrap();
-    assert!(pid_content.ends_with('\n'));
-    let pid = pid_content[..pid_content.len() - 1].parse().unwrap();
-    assert_eq!(result.unwrap().pid, pid);
+    assert_eq!(result.unwrap_err().stage(), daemonize::Stage::SetUser);
+}
 
-    let result = Tester::new().pid_file(&path).run();
-    assert!(result.is_err());
+#[test]
+fn create_working_directory() {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmpdir = TempDir::new().unwrap();
+    let directory = tmpdir.path().join("lib").join("daemon");
+
+    let result = Tester::new().working_directory(&directory).run();
+    assert_eq!(
+        result.unwrap_err().stage(),
+        daemonize::Stage::ChangeDirectory
+    );
+
+    let file = tmpdir.path().join("file");
+    std::fs::write(&file, "").unwrap();
+    let err = Tester::new()
+        .working_directory(file.join("daemon"))
+        .create_working_directory(0o750)
+        .run()
+        .unwrap_err();
+    assert_eq!(err.stage(), daemonize::Stage::ChangeDirectory);
+    assert!(
+        err.to_string()
+            .contains("unable to create working directory"),
+        "{}",
+        err
+    );
+
+    let data = Tester::new()
+        .working_directory(&directory)
+        .create_working_directory(0o750)
+        .user_num(65534)
+        .chown_working_directory()
+        .run()
+        .unwrap();
+    assert_eq!(data.cwd.as_str(), directory.to_str().unwrap());
+    let metadata = std::fs::metadata(&directory).unwrap();
+    assert_eq!(metadata.mode() & 0o777, 0o750);
+    assert_eq!(metadata.uid(), 65534);
+}
+
+#[test]
+fn chown_streams() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let tmpdir = TempDir::new().unwrap();
+    std::fs::set_permissions(tmpdir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+    let stdout = tmpdir.path().join("stdout");
+    let stderr = tmpdir.path().join("stderr");
+    std::fs::write(&stdout, "").unwrap();
+
+    // A file left by root isn't writable by the daemon user.
+    let result = Tester::new().user_num(65534).stdout_path(&stdout).run();
+    assert_eq!(
+        result.unwrap_err().stage(),
+        daemonize::Stage::RedirectStreams
+    );
+
+    Tester::new()
+        .user_num(65534)
+        .stdout_path(&stdout)
+        .stderr(&stderr)
+        .chown_streams()
+        .run()
+        .unwrap();
+    assert_eq!(std::fs::metadata(&stdout).unwrap().uid(), 65534);
+    assert_eq!(std::fs::metadata(&stderr).unwrap().uid(), 65534);
+    assert_ne!(std::fs::read_to_string(&stdout).unwrap(), "");
+
+    // Symlinks aren't followed, so a file they point to isn't handed over.
+    let target = tmpdir.path().join("target");
+    let link = tmpdir.path().join("link");
+    std::fs::write(&target, "").unwrap();
+    std::os::unix::fs::symlink(&target, &link).unwrap();
+    let result = Tester::new()
+        .user_num(65534)
+        .stdout_path(&link)
+        .chown_streams()
+        .run();
+    assert_eq!(
+        result.unwrap_err().stage(),
+        daemonize::Stage::RedirectStreams
+    );
+    assert_eq!(std::fs::metadata(&target).unwrap().uid(), 0);
+}
+
+#[test]
+fn reap_children() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("reaped");
+
+    let result = Tester::new()
+        .reap_children(&path)
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    let start = std::time::Instant::now();
+    let reaped = loop {
+        match std::fs::read_to_string(&path) {
+            Ok(reaped) if reaped.ends_with('\n') => break reaped,
+            _ => assert!(start.elapsed() < std::time::Duration::from_secs(5)),
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    };
+    let (pid, code) = reaped.trim_end().split_once(' ').unwrap();
+    assert!(pid.parse::<libc::pid_t>().unwrap() > 0);
+    assert_eq!(code, "Some(3)");
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+fn standalone_pid_file() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let mut pid_file = daemonize::PidFile::new(&path);
+    assert!(pid_file.as_fd().is_none());
+    pid_file.lock().unwrap();
+    pid_file.write(std::process::id() as libc::pid_t).unwrap();
+    assert!(pid_file.as_fd().is_some());
+    assert_eq!(pid_file.read().unwrap(), std::process::id() as libc::pid_t);
+    assert!(!pid_file.is_stale().unwrap());
+
+    let mut other = daemonize::PidFile::new(&path);
+    assert!(other.lock().is_err());
+    assert!(!other.is_stale().unwrap());
+    drop(other);
+
+    pid_file.remove().unwrap();
+    assert!(!path.exists());
+
+    std::fs::write(&path, "1\n").unwrap();
+    let stale = daemonize::PidFile::new(&path);
+    assert_eq!(stale.read().unwrap(), 1);
+    assert!(stale.is_stale().unwrap());
+}
+
+#[test]
+fn standalone_pid_file_read_keeps_posix_lock() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    let path_c = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+
+    let mut pid_file = daemonize::PidFile::new(&path).lock_style(daemonize::LockStyle::PosixFcntl);
+    pid_file.lock().unwrap();
+    pid_file.write(std::process::id() as libc::pid_t).unwrap();
+    assert_eq!(pid_file.read().unwrap(), std::process::id() as libc::pid_t);
+    assert!(!pid_file.is_stale().unwrap());
+
+    // Record locks of the process are only visible to other processes.
+    let locked = unsafe {
+        match libc::fork() {
+            0 => {
+                let fd = libc::open(path_c.as_ptr(), libc::O_RDONLY);
+                let mut flock: libc::flock = std::mem::zeroed();
+                flock.l_type = libc::F_WRLCK as _;
+                flock.l_whence = libc::SEEK_SET as _;
+                libc::fcntl(fd, libc::F_GETLK, &mut flock);
+                libc::_exit((flock.l_type as libc::c_int != libc::F_UNLCK as libc::c_int) as _)
+            }
+            child => {
+                let mut status = 0;
+                libc::waitpid(child, &mut status, 0);
+                libc::WEXITSTATUS(status) == 1
+            }
+        }
+    };
+    assert!(locked);
+}
+
+#[test]
+fn atomic_pid_file() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+    std::fs::write(&path, "stale").unwrap();
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .atomic_pid_file()
+        .sleep(std::time::Duration::from_secs(5))
+        .run()
+        .unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n", result.pid)
+    );
+    assert_eq!(std::fs::read_dir(tmpdir.path()).unwrap().count(), 1);
+
+    let second = Tester::new().pid_file(&path).atomic_pid_file().run();
+    assert!(second.is_err());
+    assert_eq!(
+        std::fs::read_to_string(&path).unwrap(),
+        format!("{}\n", result.pid)
+    );
+    assert_eq!(std::fs::read_dir(tmpdir.path()).unwrap().count(), 1);
+
+    unsafe { libc::kill(result.pid as libc::pid_t, libc::SIGKILL) };
+}
+
+#[test]
+fn pid_file_json() {
+    let tmpdir = TempDir::new().unwrap();
+    let path = tmpdir.path().join("pid");
+
+    let result = Tester::new()
+        .pid_file(&path)
+        .pid_file_json("1.2.3")
+        .run()
+        .unwrap();
+    let info = daemonize::PidFileInfo::parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    assert_eq!(info.pid as u32, result.pid);
+    assert_eq!(info.version.as_deref(), Some("1.2.3"));
+    assert!(info.exe.is_some());
+    assert!(info.cmdline.iter().any(|arg| arg == "1.2.3"));
+    #[cfg(target_os = "linux")]
+    assert!(info.start_time.is_some());
 }
 
 #[test]
@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::marker::PhantomData;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::error::{check_err, ErrorKind};
+
+/// Sending half of the channel from the daemon to the parent process, see
+/// `Daemonize::with_channel`.
+#[derive(Debug)]
+pub struct Sender<M> {
+    file: File,
+    marker: PhantomData<fn(M)>,
+}
+
+impl<M: Serialize> Sender<M> {
+    /// Send a message to the parent process.
+    pub fn send(&mut self, message: &M) -> io::Result<()> {
+        // Serialized messages never contain raw newlines, so every message is a single line.
+        let mut line = serde_json::to_vec(message)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// Receiving half of the channel from the daemon to the parent process, see
+/// `Daemonize::with_channel`.
+#[derive(Debug)]
+pub struct Receiver<M> {
+    reader: BufReader<File>,
+    marker: PhantomData<fn() -> M>,
+}
+
+impl<M: DeserializeOwned> Receiver<M> {
+    /// Receive the next message, `None` means the daemon has closed the channel.
+    pub fn recv(&mut self) -> io::Result<Option<M>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+/// Write end of the channel, not yet bound to a message type.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WriteEnd {
+    fd: RawFd,
+}
+
+impl WriteEnd {
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+
+    pub fn into_sender<M>(self) -> Sender<M> {
+        let file = unsafe { File::from_raw_fd(self.fd) };
+        std::mem::forget(self);
+        Sender {
+            file,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl Drop for WriteEnd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+pub fn receiver<M>(read_end: File) -> Receiver<M> {
+    Receiver {
+        reader: BufReader::new(read_end),
+        marker: PhantomData,
+    }
+}
+
+/// Create the channel pipe, the read end is kept by the parent.
+pub unsafe fn pipe() -> Result<(File, WriteEnd), ErrorKind> {
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::CreatePipe)?;
+    let read_end = File::from_raw_fd(fds[0]);
+    let write_end = WriteEnd { fd: fds[1] };
+    for fd in &fds {
+        check_err(
+            libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC),
+            ErrorKind::CreatePipe,
+        )?;
+    }
+    Ok((read_end, write_end))
+}
@@ -0,0 +1,143 @@
+//! Command line flags and subcommands of a typical daemon, to be embedded into the clap parser
+//! of the daemon binary with `#[command(flatten)]` and `#[command(subcommand)]`.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::control::{self, Status, Stop, Stopped};
+use super::error::{Error, ErrorKind};
+use super::Daemonize;
+
+/// Standard daemon flags which map onto `Daemonize` builder methods.
+#[derive(Debug, Clone, Default, PartialEq, Eq, clap::Args)]
+pub struct DaemonizeArgs {
+    /// Create and lock the pid file at this path.
+    #[arg(long, value_name = "PATH")]
+    pub pid_file: Option<PathBuf>,
+    /// Drop privileges to this user.
+    #[arg(long)]
+    pub user: Option<String>,
+    /// Drop privileges to this group.
+    #[arg(long)]
+    pub group: Option<String>,
+    /// Change the working directory, `/` by default.
+    #[arg(long = "chdir", value_name = "PATH")]
+    pub working_directory: Option<PathBuf>,
+    /// Stay in the foreground.
+    #[arg(long)]
+    pub foreground: bool,
+    /// Umask in octal, `027` by default.
+    #[arg(long, value_parser = parse_octal)]
+    pub umask: Option<u32>,
+    /// Redirect standard output to this file.
+    #[arg(long, value_name = "PATH")]
+    pub stdout: Option<PathBuf>,
+    /// Redirect standard error to this file.
+    #[arg(long, value_name = "PATH")]
+    pub stderr: Option<PathBuf>,
+}
+
+impl DaemonizeArgs {
+    /// Apply the flags to `daemonize`, flags which are not given keep the builder settings.
+    pub fn apply<T, M>(&self, mut daemonize: Daemonize<T, M>) -> Daemonize<T, M> {
+        if let Some(path) = &self.pid_file {
+            daemonize = daemonize.pid_file(path);
+        }
+        if let Some(user) = &self.user {
+            daemonize = daemonize.user(user.as_str());
+        }
+        if let Some(group) = &self.group {
+            daemonize = daemonize.group(group.as_str());
+        }
+        if let Some(path) = &self.working_directory {
+            daemonize = daemonize.working_directory(path);
+        }
+        if self.foreground {
+            daemonize = daemonize.foreground(true);
+        }
+        if let Some(umask) = self.umask {
+            daemonize = daemonize.umask(umask);
+        }
+        if let Some(path) = &self.stdout {
+            daemonize = daemonize.stdout_path(path);
+        }
+        if let Some(path) = &self.stderr {
+            daemonize = daemonize.stderr_path(path);
+        }
+        daemonize
+    }
+
+    /// `Daemonize` configured with the flags.
+    pub fn daemonize(&self) -> Daemonize<()> {
+        self.apply(Daemonize::new())
+    }
+
+    /// Run `command`: stop or query the daemon through the pid file, or return the configured
+    /// `Daemonize` to start if the command starts the daemon.
+    pub fn run(&self, command: &Command) -> Result<Control, Error> {
+        let pid_file = || {
+            self.pid_file
+                .as_ref()
+                .ok_or(Error::from(ErrorKind::PidFileNotConfigured))
+        };
+        match command {
+            Command::Start => Ok(Control::Start(Box::new(self.daemonize()))),
+            Command::Stop(args) => Ok(Control::Stopped(control::stop(pid_file()?, args.into())?)),
+            Command::Status => Ok(Control::Status(control::status(pid_file()?)?)),
+            Command::Restart(args) => {
+                let (_, daemonize) =
+                    control::restart(pid_file()?, args.into(), || self.daemonize())?;
+                Ok(Control::Start(Box::new(daemonize)))
+            }
+        }
+    }
+}
+
+/// Standard daemon subcommands, see `DaemonizeArgs::run`.
+#[derive(Debug, Clone, PartialEq, Eq, clap::Subcommand)]
+pub enum Command {
+    /// Start the daemon.
+    Start,
+    /// Stop the running daemon.
+    Stop(StopArgs),
+    /// Show whether the daemon is running.
+    Status,
+    /// Stop the running daemon and start a new one.
+    Restart(StopArgs),
+}
+
+/// Flags of `stop` and `restart` subcommands.
+#[derive(Debug, Clone, PartialEq, Eq, clap::Args)]
+pub struct StopArgs {
+    /// Seconds to wait for the daemon to exit.
+    #[arg(long, default_value_t = 10)]
+    pub grace: u64,
+    /// Don't kill the daemon if it doesn't exit in time.
+    #[arg(long)]
+    pub no_kill: bool,
+}
+
+impl<'a> From<&'a StopArgs> for Stop {
+    fn from(args: &'a StopArgs) -> Self {
+        Stop {
+            grace: Duration::from_secs(args.grace),
+            kill_after: !args.no_kill,
+            ..Stop::default()
+        }
+    }
+}
+
+/// Result of `DaemonizeArgs::run`.
+#[derive(Debug)]
+pub enum Control {
+    /// Start the daemon with the returned `Daemonize`.
+    Start(Box<Daemonize<()>>),
+    /// The daemon was stopped or wasn't running.
+    Stopped(Stopped),
+    /// State of the daemon.
+    Status(Status),
+}
+
+fn parse_octal(value: &str) -> Result<u32, std::num::ParseIntError> {
+    u32::from_str_radix(value, 8)
+}
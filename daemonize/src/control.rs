@@ -0,0 +1,377 @@
+//! Helpers to control a running daemon through its pid file or control socket, e.g. for
+//! `status` and `stop` subcommands of the daemon binary.
+
+use std::fs::Permissions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::error::{errno, Error, ErrorKind};
+use super::pidfile::{is_process_running, process_start_time, PidFile};
+use super::Signal;
+
+/// How to stop the daemon, see `stop`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Stop {
+    /// Signal sent to the daemon first, `Signal::Term` by default.
+    pub signal: Signal,
+    /// How long to wait for the daemon to exit, 10 seconds by default.
+    pub grace: Duration,
+    /// If true, kill the daemon with `SIGKILL` if it doesn't exit within `grace`, default.
+    pub kill_after: bool,
+}
+
+impl Default for Stop {
+    fn default() -> Self {
+        Self {
+            signal: Signal::Term,
+            grace: Duration::from_secs(10),
+            kill_after: true,
+        }
+    }
+}
+
+/// Result of `stop`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Stopped {
+    /// The daemon wasn't running.
+    NotRunning,
+    /// The daemon exited after `Stop::signal`.
+    Exited(libc::pid_t),
+    /// The daemon was killed with `SIGKILL`.
+    Killed(libc::pid_t),
+}
+
+/// State of the daemon, see `status`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[non_exhaustive]
+pub struct Status {
+    /// PID recorded in the pid file, `None` if the pid file doesn't exist.
+    pub pid: Option<libc::pid_t>,
+    /// Whether the process `pid` exists and, if the pid file records the start time, it was
+    /// started at that time.
+    pub running: bool,
+    /// When the daemon was started, known only on Linux.
+    pub started_at: Option<SystemTime>,
+    /// Whether the lock of the pid file is held.
+    pub locked: bool,
+}
+
+/// Collect the state of the daemon from `pid_file` and its lock, e.g. for a `status`
+/// subcommand or a health probe. The daemon is up if both `running` and `locked` are true.
+pub fn status<P: AsRef<Path>>(pid_file: P) -> Result<Status, Error> {
+    let mut status = Status {
+        pid: None,
+        running: false,
+        started_at: None,
+        locked: false,
+    };
+    let pid_file = PidFile::new(pid_file);
+    let info = match pid_file.read_info().map_err(Error::into_kind) {
+        Ok(info) => info,
+        Err(ErrorKind::ReadPidfile(libc::ENOENT)) => return Ok(status),
+        Err(err) => return Err(err.into()),
+    };
+    status.pid = Some(info.pid);
+    status.running = is_process_running(info.pid, info.start_time);
+    status.locked = match pid_file.is_stale().map_err(Error::into_kind) {
+        Ok(stale) => !stale,
+        Err(ErrorKind::ReadPidfile(libc::ENOENT)) => false,
+        Err(err) => return Err(err.into()),
+    };
+    let start_time = match info.start_time {
+        Some(start_time) => Some(start_time),
+        None if status.running => process_start_time(info.pid),
+        None => None,
+    };
+    status.started_at = start_time.and_then(started_at);
+    Ok(status)
+}
+
+/// Return the PID of the daemon which holds the lock of `pid_file`, or `None` if the pid file
+/// doesn't exist, isn't locked or the process is gone.
+pub fn is_running<P: AsRef<Path>>(pid_file: P) -> Result<Option<libc::pid_t>, Error> {
+    let pid_file = PidFile::new(pid_file);
+    let info = match pid_file.read_info().map_err(Error::into_kind) {
+        Ok(info) => info,
+        Err(ErrorKind::ReadPidfile(libc::ENOENT)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let stale = match pid_file.is_stale().map_err(Error::into_kind) {
+        Ok(stale) => stale,
+        // Removed by the daemon in the meantime.
+        Err(ErrorKind::ReadPidfile(libc::ENOENT)) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if stale || !is_process_running(info.pid, info.start_time) {
+        return Ok(None);
+    }
+    Ok(Some(info.pid))
+}
+
+/// Stop the daemon which holds the lock of `pid_file`: send `Stop::signal`, wait until the lock
+/// is released and kill the daemon if `Stop::grace` expires. Only the process which holds the
+/// lock is signaled, so a process which reused the PID of a stale pid file is never touched.
+pub fn stop<P: AsRef<Path>>(pid_file: P, stop: Stop) -> Result<Stopped, Error> {
+    let pid_file = pid_file.as_ref();
+    let pid = match is_running(pid_file)? {
+        Some(pid) => pid,
+        None => return Ok(Stopped::NotRunning),
+    };
+    signal(pid, stop.signal.number())?;
+    if wait_exit(pid_file, stop.grace)? {
+        return Ok(Stopped::Exited(pid));
+    }
+    if !stop.kill_after {
+        return Err(ErrorKind::StopTimeout.into());
+    }
+    signal(pid, libc::SIGKILL)?;
+    if wait_exit(pid_file, stop.grace)? {
+        return Ok(Stopped::Killed(pid));
+    }
+    Err(ErrorKind::StopTimeout.into())
+}
+
+/// Stop the daemon like `stop` and call `launch` once the old instance has released the lock
+/// of `pid_file`, so the new instance can't fail to lock it. `launch` is called even if the
+/// daemon wasn't running, but not if it failed to stop.
+pub fn restart<P: AsRef<Path>, F: FnOnce() -> T, T>(
+    pid_file: P,
+    stop: Stop,
+    launch: F,
+) -> Result<(Stopped, T), Error> {
+    let pid_file = pid_file.as_ref();
+    let stopped = self::stop(pid_file, stop)?;
+    // The lock may outlive the daemon, e.g. if it's inherited by a child of the daemon.
+    if !wait_unlocked(pid_file, stop.grace)? {
+        return Err(ErrorKind::StopTimeout.into());
+    }
+    Ok((stopped, launch()))
+}
+
+/// Send `signal`, `Signal::Hup` by default, to the daemon which holds the lock of `pid_file`,
+/// e.g. to reload its configuration. Return the PID of the daemon, or `None` if it isn't running.
+pub fn reload<P: AsRef<Path>>(
+    pid_file: P,
+    signal: Option<Signal>,
+) -> Result<Option<libc::pid_t>, Error> {
+    let pid = match is_running(pid_file)? {
+        Some(pid) => pid,
+        None => return Ok(None),
+    };
+    self::signal(pid, signal.unwrap_or(Signal::Hup).number())?;
+    Ok(Some(pid))
+}
+
+/// Request to the control socket of the daemon, see `Daemonize::control_socket`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Request {
+    /// Check that the daemon responds, the response is `pong`.
+    Ping,
+    /// Query the daemon, the response is `pid <pid>`.
+    Status,
+    /// Send `SIGTERM` to the daemon, the response is `ok`.
+    Shutdown,
+    /// Send `SIGHUP` to the daemon, the response is `ok`.
+    Reload,
+}
+
+impl Request {
+    fn as_str(self) -> &'static str {
+        match self {
+            Request::Ping => "ping",
+            Request::Status => "status",
+            Request::Shutdown => "shutdown",
+            Request::Reload => "reload",
+        }
+    }
+}
+
+/// Send `request` to the daemon listening on the control socket at `path` and return the
+/// response. Each request is sent over a new connection as a line of text, and the daemon
+/// answers with a line.
+pub fn request<P: AsRef<Path>>(path: P, request: Request) -> Result<String, Error> {
+    let exchange = || -> io::Result<String> {
+        let mut stream = UnixStream::connect(path)?;
+        writeln!(stream, "{}", request.as_str())?;
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        Ok(response.trim_end().to_owned())
+    };
+    exchange()
+        .map_err(|err| ErrorKind::ControlSocket(err.raw_os_error().unwrap_or(libc::EIO)).into())
+}
+
+/// Bind the control socket at `path` accessible only to its owner. A socket nobody listens on,
+/// e.g. left by a killed daemon, is replaced only if the daemon holds the lock of its pid file,
+/// since another instance may be about to listen on it otherwise.
+pub(crate) fn listen(path: &Path, pid_file_locked: bool) -> Result<UnixListener, ErrorKind> {
+    let to_kind =
+        |err: io::Error| ErrorKind::ControlSocket(err.raw_os_error().unwrap_or(libc::EIO));
+    let listener = match UnixListener::bind(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::AddrInUse => {
+            if !pid_file_locked || UnixStream::connect(path).is_ok() {
+                return Err(ErrorKind::ControlSocket(libc::EADDRINUSE));
+            }
+            std::fs::remove_file(path).map_err(to_kind)?;
+            UnixListener::bind(path).map_err(to_kind)?
+        }
+        result => result.map_err(to_kind)?,
+    };
+    std::fs::set_permissions(path, Permissions::from_mode(0o600)).map_err(to_kind)?;
+    Ok(listener)
+}
+
+/// Answer requests on `listener` until the daemon exits.
+pub(crate) fn serve(listener: UnixListener) {
+    for stream in listener.incoming().flatten() {
+        // A misbehaving client only breaks its own connection.
+        let _ = answer(stream);
+    }
+}
+
+fn answer(mut stream: UnixStream) -> io::Result<()> {
+    // Requests are answered one by one, so a silent client must not block the others.
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let uid = unsafe { libc::geteuid() };
+    if let Some(peer) = peer_uid(&stream)? {
+        if peer != 0 && peer != uid {
+            return writeln!(stream, "error permission denied");
+        }
+    }
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let (response, signal) = match line.trim_end() {
+        "ping" => ("pong".to_owned(), None),
+        "status" => (format!("pid {}", std::process::id()), None),
+        "shutdown" => ("ok".to_owned(), Some(libc::SIGTERM)),
+        "reload" => ("ok".to_owned(), Some(libc::SIGHUP)),
+        _ => ("error unknown request".to_owned(), None),
+    };
+    writeln!(stream, "{}", response)?;
+    if let Some(signal) = signal {
+        unsafe { libc::kill(libc::getpid(), signal) };
+    }
+    Ok(())
+}
+
+/// How long a client of the control socket may take to send a request and read the response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// UID of the process connected to `stream`.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_uid(stream: &UnixStream) -> io::Result<Option<libc::uid_t>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(cred.uid))
+}
+
+/// UID of the process connected to `stream`.
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+))]
+fn peer_uid(stream: &UnixStream) -> io::Result<Option<libc::uid_t>> {
+    use std::os::unix::io::AsRawFd;
+
+    let mut uid = 0;
+    let mut gid = 0;
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(uid))
+}
+
+/// The peer can't be queried here, only the mode of the socket restricts clients.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd",
+    target_os = "netbsd"
+)))]
+fn peer_uid(_stream: &UnixStream) -> io::Result<Option<libc::uid_t>> {
+    Ok(None)
+}
+
+/// Convert the start time of a process in clock ticks since boot to the wall clock time.
+fn started_at(start_time: u64) -> Option<SystemTime> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    let boot_time = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    if ticks_per_second <= 0 {
+        return None;
+    }
+    let since_boot = Duration::from_millis(start_time * 1000 / ticks_per_second as u64);
+    Some(UNIX_EPOCH + Duration::from_secs(boot_time) + since_boot)
+}
+
+fn signal(pid: libc::pid_t, signal: libc::c_int) -> Result<(), ErrorKind> {
+    match unsafe { libc::kill(pid, signal) } {
+        // Exited after the check.
+        -1 if errno() == libc::ESRCH => Ok(()),
+        -1 => Err(ErrorKind::SignalDaemon(errno())),
+        _ => Ok(()),
+    }
+}
+
+/// Poll the lock of the pid file until it's released or `timeout` expires, the daemon may be
+/// not a child of the calling process, so it can't be waited.
+fn wait_exit(pid_file: &Path, timeout: Duration) -> Result<bool, Error> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if is_running(pid_file)?.is_none() {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Wait until nobody holds the lock of `pid_file` or it's removed, return false on timeout.
+fn wait_unlocked(pid_file: &Path, timeout: Duration) -> Result<bool, Error> {
+    let deadline = Instant::now() + timeout;
+    let pid_file = PidFile::new(pid_file);
+    loop {
+        match pid_file.is_stale().map_err(Error::into_kind) {
+            Ok(true) | Err(ErrorKind::ReadPidfile(libc::ENOENT)) => return Ok(true),
+            Ok(false) => {}
+            Err(err) => return Err(err.into()),
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
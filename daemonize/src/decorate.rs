@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::{BitOr, BitOrAssign};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Set of decorations prepended to every line of a stream, see `Stdio::decorated`. Prefixes can be
+/// combined with `|`, e.g. `2024-01-31T12:00:00.000Z stdout[1234]: message`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct LinePrefix {
+    inner: u8,
+}
+
+impl LinePrefix {
+    /// UTC timestamp in RFC 3339 format with milliseconds.
+    pub const TIMESTAMP: LinePrefix = LinePrefix { inner: 1 << 0 };
+    /// Name of the stream, `stdout` or `stderr`.
+    pub const STREAM: LinePrefix = LinePrefix { inner: 1 << 1 };
+    /// PID of the daemon.
+    pub const PID: LinePrefix = LinePrefix { inner: 1 << 2 };
+
+    /// Check that all prefixes from `other` are in this set.
+    pub fn contains(&self, other: LinePrefix) -> bool {
+        self.inner & other.inner == other.inner
+    }
+}
+
+impl BitOr for LinePrefix {
+    type Output = LinePrefix;
+
+    fn bitor(self, other: LinePrefix) -> LinePrefix {
+        LinePrefix {
+            inner: self.inner | other.inner,
+        }
+    }
+}
+
+impl BitOrAssign for LinePrefix {
+    fn bitor_assign(&mut self, other: LinePrefix) {
+        self.inner |= other.inner;
+    }
+}
+
+/// Copy everything read from `pipe` to `file` prepending `prefix` to every line. Partial lines
+/// are written as soon as they are read, so nothing is held back in the forwarder.
+pub fn forward(mut pipe: File, mut file: File, prefix: LinePrefix, stream: &str) {
+    let mut buffer = [0; 4096];
+    let mut line_start = true;
+    loop {
+        let len = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+
+        let mut output = Vec::with_capacity(len);
+        for chunk in buffer[..len].split_inclusive(|&byte| byte == b'\n') {
+            if line_start {
+                output.extend_from_slice(format_prefix(prefix, stream).as_bytes());
+            }
+            output.extend_from_slice(chunk);
+            line_start = chunk.last() == Some(&b'\n');
+        }
+
+        if file.write_all(&output).is_err() {
+            break;
+        }
+    }
+}
+
+fn format_prefix(prefix: LinePrefix, stream: &str) -> String {
+    let mut result = String::new();
+    if prefix.contains(LinePrefix::TIMESTAMP) {
+        result.push_str(&timestamp());
+        result.push(' ');
+    }
+
+    let mut source = String::new();
+    if prefix.contains(LinePrefix::STREAM) {
+        source.push_str(stream);
+    }
+    if prefix.contains(LinePrefix::PID) {
+        source.push_str(&format!("[{}]", std::process::id()));
+    }
+    if !source.is_empty() {
+        result.push_str(&source);
+        result.push_str(": ");
+    }
+    result
+}
+
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs() as libc::time_t;
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::gmtime_r(&secs, &mut tm) };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        now.subsec_millis()
+    )
+}
@@ -1,14 +1,67 @@
+use std::cmp::Ordering;
+use std::mem::discriminant;
+use std::sync::Arc;
+
 pub type Errno = libc::c_int;
 
+/// Error of a user callback, see `Error::source`.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 /// This error type for `Daemonize` `start` method.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(Debug, Clone)]
 pub struct Error {
     kind: ErrorKind,
+    source: Option<Arc<dyn std::error::Error + Send + Sync>>,
+}
+
+/// Daemonization stage, see `Daemonize::on_error` and `Daemonize::plan`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[non_exhaustive]
+pub enum Stage {
+    /// Configuration check, e.g. a path contains NUL.
+    Configuration,
+    /// Init policy check when running as PID 1.
+    Init,
+    Fork,
+    DetachSession,
+    ChangeDirectory,
+    Umask,
+    /// Setting up the environment, see `Daemonize::clear_environment` and `Daemonize::env`.
+    Environment,
+    NoNewPrivileges,
+    /// Becoming a subreaper, see `Daemonize::subreaper`.
+    Subreaper,
+    /// Setting the process name, see `Daemonize::process_name`.
+    ProcessName,
+    PidFile,
+    /// Acquiring the single instance guard, see `Daemonize::single_instance`.
+    SingleInstance,
+    RedirectStreams,
+    PrivilegedAction,
+    /// Setting the scheduling priority and the OOM score adjustment, see `Daemonize::nice`.
+    Priority,
+    /// Setting resource limits and locking memory, see `Daemonize::rlimit`,
+    /// `Daemonize::disable_core_dumps` and `Daemonize::lock_memory`.
+    ResourceLimits,
+    Namespaces,
+    Chroot,
+    SetGroup,
+    SetUser,
+    PrivilegeDrop,
+    Signals,
+    Landlock,
+    /// Waiting for the daemon in the parent process.
+    Readiness,
+    /// Custom step, see `Daemonize::insert_step`.
+    Custom,
+    /// Controlling a running daemon, see `control`.
+    Control,
 }
 
 /// This error type for `Daemonize` `start` method.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum ErrorKind {
+    RunningAsInit,
     Fork(Errno),
     Wait(Errno),
     DetachSession(Errno),
@@ -18,25 +71,250 @@ pub enum ErrorKind {
     UserNotFound,
     UserContainsNul,
     SetUser(Errno),
+    PrivilegeDropIncomplete,
+    NoNewPrivileges(Errno),
+    #[cfg(target_os = "linux")]
+    CreateUserNamespace(Errno),
+    #[cfg(target_os = "linux")]
+    WriteIdMap(Errno),
+    #[cfg(target_os = "linux")]
+    CreateNamespaces(Errno),
+    #[cfg(target_os = "linux")]
+    MakeMountsPrivate(Errno),
+    #[cfg(target_os = "linux")]
+    BindMount(Errno),
+    #[cfg(target_os = "linux")]
+    PivotRoot(Errno),
+    #[cfg(target_os = "linux")]
+    UnmountOldRoot(Errno),
+    #[cfg(target_os = "linux")]
+    LandlockUnsupported(Errno),
+    #[cfg(target_os = "linux")]
+    LandlockOpenPath(Errno),
+    #[cfg(target_os = "linux")]
+    LandlockAddRule(Errno),
+    #[cfg(target_os = "linux")]
+    LandlockRestrict(Errno),
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    PrivilegesContainNul,
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    ParsePrivileges(Errno),
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    SetPrivileges(Errno),
+    SetEffectiveGroup(Errno),
+    SetEffectiveUser(Errno),
     ChangeDirectory(Errno),
     PathContainsNul,
     OpenPidfile(Errno),
     GetPidfileFlags(Errno),
     SetPidfileFlags(Errno),
     LockPidfile(Errno),
+    #[cfg(target_os = "linux")]
+    BindInstanceSocket(Errno),
+    #[cfg(target_os = "linux")]
+    ParentDeathSignal(Errno),
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    SetSubreaper(Errno),
+    ProcessNameContainsNul,
+    SetPriority(Errno),
+    SetResourceLimit(Errno),
+    LockMemory(Errno),
+    #[cfg(target_os = "linux")]
+    SetDumpable(Errno),
+    #[cfg(target_os = "linux")]
+    SetIoPriority(Errno),
+    #[cfg(target_os = "linux")]
+    SetOomScoreAdj(Errno),
+    #[cfg(target_os = "linux")]
+    SetProcessName(Errno),
+    #[cfg(target_os = "linux")]
+    SetProcessTitle(Errno),
+    #[cfg(target_os = "linux")]
+    InstanceRunning,
+    RenamePidfile(Errno),
     ChownPidfile(Errno),
+    ChownStreams(Errno),
     OpenDevnull(Errno),
     RedirectStreams(Errno),
+    OpenStreamPath(Errno),
     CloseDevnull(Errno),
+    InheritFd(Errno),
+    InvalidEnvironmentVariable,
+    #[cfg(target_os = "linux")]
+    ConnectJournal(Errno),
+    #[cfg(target_os = "linux")]
+    JournalIdentifierContainsNewline,
+    CreatePipe(Errno),
+    #[cfg(feature = "syslog")]
+    SyslogIdentContainsNul,
+    CreateDevnull(Errno),
+    SetSignalHandler(Errno),
+    StartLogger(Errno),
+    WaitReady(Errno),
+    ReadyTimeout,
+    NotReady,
+    Timeout,
     TruncatePidfile(Errno),
     WritePid(Errno),
     WritePidUnspecifiedError,
+    SyncPidfile(Errno),
+    ReadPidfile(Errno),
+    InvalidPidfile,
+    StalePidfile,
+    SignalDaemon(Errno),
+    StopTimeout,
+    PidFileNotConfigured,
+    ControlSocket(Errno),
+    RemovePidfile(Errno),
     Chroot(Errno),
+    Step(Errno),
+    PrivilegedAction(Errno),
+    PreForkAction(Errno),
+    ConflictingSignals,
+    DiscardedPrivilegeToken,
+    CreateWorkingDirectory(Errno),
+    ChownWorkingDirectory(Errno),
 }
 
+/// Constructors of all error kinds, the position is the code of a kind sent from the daemon to the
+/// parent process.
+static KINDS: &[fn(Errno) -> ErrorKind] = &[
+    |_| ErrorKind::RunningAsInit,
+    ErrorKind::Fork,
+    ErrorKind::Wait,
+    ErrorKind::DetachSession,
+    |_| ErrorKind::GroupNotFound,
+    |_| ErrorKind::GroupContainsNul,
+    ErrorKind::SetGroup,
+    |_| ErrorKind::UserNotFound,
+    |_| ErrorKind::UserContainsNul,
+    ErrorKind::SetUser,
+    |_| ErrorKind::PrivilegeDropIncomplete,
+    ErrorKind::NoNewPrivileges,
+    #[cfg(target_os = "linux")]
+    ErrorKind::CreateUserNamespace,
+    #[cfg(target_os = "linux")]
+    ErrorKind::WriteIdMap,
+    #[cfg(target_os = "linux")]
+    ErrorKind::CreateNamespaces,
+    #[cfg(target_os = "linux")]
+    ErrorKind::MakeMountsPrivate,
+    #[cfg(target_os = "linux")]
+    ErrorKind::BindMount,
+    #[cfg(target_os = "linux")]
+    ErrorKind::PivotRoot,
+    #[cfg(target_os = "linux")]
+    ErrorKind::UnmountOldRoot,
+    #[cfg(target_os = "linux")]
+    ErrorKind::LandlockUnsupported,
+    #[cfg(target_os = "linux")]
+    ErrorKind::LandlockOpenPath,
+    #[cfg(target_os = "linux")]
+    ErrorKind::LandlockAddRule,
+    #[cfg(target_os = "linux")]
+    ErrorKind::LandlockRestrict,
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    |_| ErrorKind::PrivilegesContainNul,
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    ErrorKind::ParsePrivileges,
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    ErrorKind::SetPrivileges,
+    ErrorKind::SetEffectiveGroup,
+    ErrorKind::SetEffectiveUser,
+    ErrorKind::ChangeDirectory,
+    |_| ErrorKind::PathContainsNul,
+    ErrorKind::OpenPidfile,
+    ErrorKind::GetPidfileFlags,
+    ErrorKind::SetPidfileFlags,
+    ErrorKind::LockPidfile,
+    #[cfg(target_os = "linux")]
+    ErrorKind::BindInstanceSocket,
+    #[cfg(target_os = "linux")]
+    ErrorKind::ParentDeathSignal,
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    ErrorKind::SetSubreaper,
+    |_| ErrorKind::ProcessNameContainsNul,
+    ErrorKind::SetPriority,
+    ErrorKind::SetResourceLimit,
+    ErrorKind::LockMemory,
+    #[cfg(target_os = "linux")]
+    ErrorKind::SetDumpable,
+    #[cfg(target_os = "linux")]
+    ErrorKind::SetIoPriority,
+    #[cfg(target_os = "linux")]
+    ErrorKind::SetOomScoreAdj,
+    #[cfg(target_os = "linux")]
+    ErrorKind::SetProcessName,
+    #[cfg(target_os = "linux")]
+    ErrorKind::SetProcessTitle,
+    #[cfg(target_os = "linux")]
+    |_| ErrorKind::InstanceRunning,
+    ErrorKind::RenamePidfile,
+    ErrorKind::ChownPidfile,
+    ErrorKind::ChownStreams,
+    ErrorKind::OpenDevnull,
+    ErrorKind::RedirectStreams,
+    ErrorKind::OpenStreamPath,
+    ErrorKind::CloseDevnull,
+    ErrorKind::InheritFd,
+    |_| ErrorKind::InvalidEnvironmentVariable,
+    #[cfg(target_os = "linux")]
+    ErrorKind::ConnectJournal,
+    #[cfg(target_os = "linux")]
+    |_| ErrorKind::JournalIdentifierContainsNewline,
+    ErrorKind::CreatePipe,
+    #[cfg(feature = "syslog")]
+    |_| ErrorKind::SyslogIdentContainsNul,
+    ErrorKind::CreateDevnull,
+    ErrorKind::SetSignalHandler,
+    ErrorKind::StartLogger,
+    ErrorKind::WaitReady,
+    |_| ErrorKind::ReadyTimeout,
+    |_| ErrorKind::NotReady,
+    |_| ErrorKind::Timeout,
+    ErrorKind::TruncatePidfile,
+    ErrorKind::WritePid,
+    |_| ErrorKind::WritePidUnspecifiedError,
+    ErrorKind::SyncPidfile,
+    ErrorKind::ReadPidfile,
+    |_| ErrorKind::InvalidPidfile,
+    |_| ErrorKind::StalePidfile,
+    ErrorKind::SignalDaemon,
+    |_| ErrorKind::StopTimeout,
+    |_| ErrorKind::PidFileNotConfigured,
+    ErrorKind::ControlSocket,
+    ErrorKind::RemovePidfile,
+    ErrorKind::Chroot,
+    ErrorKind::Step,
+    ErrorKind::PrivilegedAction,
+    ErrorKind::PreForkAction,
+    |_| ErrorKind::ConflictingSignals,
+    |_| ErrorKind::DiscardedPrivilegeToken,
+    ErrorKind::CreateWorkingDirectory,
+    ErrorKind::ChownWorkingDirectory,
+];
+
 impl ErrorKind {
+    /// Encode the kind as a code and an errno, see `ErrorKind::decode`.
+    pub(crate) fn encode(&self) -> (u64, Errno) {
+        let code = KINDS
+            .iter()
+            .position(|kind| discriminant(&kind(0)) == discriminant(self))
+            .expect("all error kinds are listed");
+        (code as u64, self.errno().unwrap_or(0))
+    }
+
+    /// Decode the kind encoded with `ErrorKind::encode`, `None` if the code is unknown.
+    pub(crate) fn decode(code: u64, errno: Errno) -> Option<ErrorKind> {
+        if code >= KINDS.len() as u64 {
+            return None;
+        }
+        Some(KINDS[code as usize](errno))
+    }
+
     fn description(&self) -> &str {
         match self {
+            ErrorKind::RunningAsInit => "unable to daemonize init process",
             ErrorKind::Fork(_) => "unable to fork",
             ErrorKind::Wait(_) => "wait failed",
             ErrorKind::DetachSession(_) => "unable to create new session",
@@ -46,27 +324,237 @@ impl ErrorKind {
             ErrorKind::UserNotFound => "unable to resolve user name to user id",
             ErrorKind::UserContainsNul => "user option contains NUL",
             ErrorKind::SetUser(_) => "unable to set user",
+            ErrorKind::PrivilegeDropIncomplete => "root privileges can be regained after drop",
+            ErrorKind::NoNewPrivileges(_) => "unable to set no_new_privs",
+            #[cfg(target_os = "linux")]
+            ErrorKind::CreateUserNamespace(_) => "unable to create user namespace",
+            #[cfg(target_os = "linux")]
+            ErrorKind::WriteIdMap(_) => "unable to write user namespace id map",
+            #[cfg(target_os = "linux")]
+            ErrorKind::CreateNamespaces(_) => "unable to create namespaces",
+            #[cfg(target_os = "linux")]
+            ErrorKind::MakeMountsPrivate(_) => "unable to make mounts private",
+            #[cfg(target_os = "linux")]
+            ErrorKind::BindMount(_) => "unable to bind mount into jail",
+            #[cfg(target_os = "linux")]
+            ErrorKind::PivotRoot(_) => "unable to pivot root",
+            #[cfg(target_os = "linux")]
+            ErrorKind::UnmountOldRoot(_) => "unable to unmount old root",
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockUnsupported(_) => "unable to create landlock ruleset",
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockOpenPath(_) => "unable to open landlock path",
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockAddRule(_) => "unable to add landlock rule",
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockRestrict(_) => "unable to enforce landlock ruleset",
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::PrivilegesContainNul => "privileges option contains NUL",
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::ParsePrivileges(_) => "unable to parse privileges",
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::SetPrivileges(_) => "unable to set privileges",
+            ErrorKind::SetEffectiveGroup(_) => "unable to set effective group",
+            ErrorKind::SetEffectiveUser(_) => "unable to set effective user",
             ErrorKind::ChangeDirectory(_) => "unable to change directory",
             ErrorKind::PathContainsNul => "pid_file option contains NUL",
             ErrorKind::OpenPidfile(_) => "unable to open pid file",
             ErrorKind::GetPidfileFlags(_) => "unable get pid file flags",
             ErrorKind::SetPidfileFlags(_) => "unable set pid file flags",
             ErrorKind::LockPidfile(_) => "unable to lock pid file",
+            #[cfg(target_os = "linux")]
+            ErrorKind::BindInstanceSocket(_) => "unable to bind single instance socket",
+            #[cfg(target_os = "linux")]
+            ErrorKind::ParentDeathSignal(_) => "unable to set parent death signal",
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            ErrorKind::SetSubreaper(_) => "unable to become a subreaper",
+            ErrorKind::ProcessNameContainsNul => "process name contains NUL",
+            ErrorKind::SetPriority(_) => "unable to set priority",
+            ErrorKind::SetResourceLimit(_) => "unable to set resource limit",
+            ErrorKind::LockMemory(_) => "unable to lock memory",
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetDumpable(_) => "unable to set dumpable flag",
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetIoPriority(_) => "unable to set I/O priority",
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetOomScoreAdj(_) => "unable to set OOM score adjustment",
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetProcessName(_) => "unable to set process name",
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetProcessTitle(_) => "unable to set process title",
+            #[cfg(target_os = "linux")]
+            ErrorKind::InstanceRunning => "another instance of the daemon is running",
+            ErrorKind::RenamePidfile(_) => "unable to rename temporary pid file",
             ErrorKind::ChownPidfile(_) => "unable to chown pid file",
+            ErrorKind::ChownStreams(_) => "unable to chown standard stream file",
             ErrorKind::OpenDevnull(_) => "unable to open /dev/null",
             ErrorKind::RedirectStreams(_) => "unable to redirect standard streams to /dev/null",
+            ErrorKind::OpenStreamPath(_) => "unable to open standard stream file",
             ErrorKind::CloseDevnull(_) => "unable to close /dev/null",
+            ErrorKind::InheritFd(_) => "unable to pass descriptor to the daemon",
+            ErrorKind::InvalidEnvironmentVariable => "invalid environment variable",
+            #[cfg(target_os = "linux")]
+            ErrorKind::ConnectJournal(_) => "unable to connect to journald",
+            #[cfg(target_os = "linux")]
+            ErrorKind::JournalIdentifierContainsNewline => "journald identifier contains newline",
+            ErrorKind::CreatePipe(_) => "unable to create pipe for standard stream",
+            #[cfg(feature = "syslog")]
+            ErrorKind::SyslogIdentContainsNul => "syslog ident contains NUL",
+            ErrorKind::CreateDevnull(_) => "unable to create /dev/null in new root",
+            ErrorKind::SetSignalHandler(_) => "unable to set signal handler",
+            ErrorKind::StartLogger(_) => "unable to start logger process",
+            ErrorKind::WaitReady(_) => "unable to wait for daemon readiness",
+            ErrorKind::ReadyTimeout => "daemon is not ready in time",
+            ErrorKind::NotReady => "daemon exited or failed before readiness notification",
+            ErrorKind::Timeout => "daemonization is not finished in time",
             ErrorKind::TruncatePidfile(_) => "unable to truncate pid file",
             ErrorKind::WritePid(_) => "unable to write self pid to pid file",
             ErrorKind::WritePidUnspecifiedError => {
                 "unable to write self pid to pid file due to unknown reason"
             }
+            ErrorKind::SyncPidfile(_) => "unable to sync pid file",
+            ErrorKind::ReadPidfile(_) => "unable to read pid file",
+            ErrorKind::InvalidPidfile => "invalid pid file contents",
+            ErrorKind::StalePidfile => "pid file is not locked by a running daemon",
+            ErrorKind::SignalDaemon(_) => "unable to send signal to daemon",
+            ErrorKind::StopTimeout => "daemon didn't exit in time",
+            ErrorKind::PidFileNotConfigured => "pid file is required to control the daemon",
+            ErrorKind::ControlSocket(_) => "control socket failed",
+            ErrorKind::RemovePidfile(_) => "unable to remove pid file",
             ErrorKind::Chroot(_) => "unable to chroot into directory",
+            ErrorKind::Step(_) => "custom step failed",
+            ErrorKind::PrivilegedAction(_) => "privileged action failed",
+            ErrorKind::PreForkAction(_) => "pre-fork action failed",
+            ErrorKind::ConflictingSignals => "signals are both handled and remove the pid file",
+            ErrorKind::DiscardedPrivilegeToken => {
+                "temporary privilege drop requires start_extended or execute"
+            }
+            ErrorKind::CreateWorkingDirectory(_) => "unable to create working directory",
+            ErrorKind::ChownWorkingDirectory(_) => "unable to change owner of working directory",
+        }
+    }
+
+    pub fn stage(&self) -> Stage {
+        match self {
+            ErrorKind::RunningAsInit => Stage::Init,
+            ErrorKind::Fork(_) => Stage::Fork,
+            ErrorKind::Wait(_) => Stage::Fork,
+            ErrorKind::DetachSession(_) => Stage::DetachSession,
+            ErrorKind::GroupNotFound => Stage::SetGroup,
+            ErrorKind::GroupContainsNul => Stage::SetGroup,
+            ErrorKind::SetGroup(_) => Stage::SetGroup,
+            ErrorKind::UserNotFound => Stage::SetUser,
+            ErrorKind::UserContainsNul => Stage::SetUser,
+            ErrorKind::SetUser(_) => Stage::SetUser,
+            ErrorKind::PrivilegeDropIncomplete => Stage::PrivilegeDrop,
+            ErrorKind::NoNewPrivileges(_) => Stage::NoNewPrivileges,
+            #[cfg(target_os = "linux")]
+            ErrorKind::CreateUserNamespace(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::WriteIdMap(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::CreateNamespaces(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::MakeMountsPrivate(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::BindMount(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::PivotRoot(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::UnmountOldRoot(_) => Stage::Namespaces,
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockUnsupported(_) => Stage::Landlock,
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockOpenPath(_) => Stage::Landlock,
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockAddRule(_) => Stage::Landlock,
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockRestrict(_) => Stage::Landlock,
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::PrivilegesContainNul => Stage::PrivilegeDrop,
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::ParsePrivileges(_) => Stage::PrivilegeDrop,
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::SetPrivileges(_) => Stage::PrivilegeDrop,
+            ErrorKind::SetEffectiveGroup(_) => Stage::SetGroup,
+            ErrorKind::SetEffectiveUser(_) => Stage::SetUser,
+            ErrorKind::ChangeDirectory(_) => Stage::ChangeDirectory,
+            ErrorKind::PathContainsNul => Stage::Configuration,
+            ErrorKind::OpenPidfile(_) => Stage::PidFile,
+            ErrorKind::GetPidfileFlags(_) => Stage::PidFile,
+            ErrorKind::SetPidfileFlags(_) => Stage::PidFile,
+            ErrorKind::LockPidfile(_) => Stage::PidFile,
+            #[cfg(target_os = "linux")]
+            ErrorKind::BindInstanceSocket(_) => Stage::SingleInstance,
+            #[cfg(target_os = "linux")]
+            ErrorKind::ParentDeathSignal(_) => Stage::Signals,
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            ErrorKind::SetSubreaper(_) => Stage::Subreaper,
+            ErrorKind::ProcessNameContainsNul => Stage::ProcessName,
+            ErrorKind::SetPriority(_) => Stage::Priority,
+            ErrorKind::SetResourceLimit(_) => Stage::ResourceLimits,
+            ErrorKind::LockMemory(_) => Stage::ResourceLimits,
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetDumpable(_) => Stage::ResourceLimits,
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetIoPriority(_) => Stage::Priority,
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetOomScoreAdj(_) => Stage::Priority,
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetProcessName(_) => Stage::ProcessName,
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetProcessTitle(_) => Stage::ProcessName,
+            #[cfg(target_os = "linux")]
+            ErrorKind::InstanceRunning => Stage::SingleInstance,
+            ErrorKind::RenamePidfile(_) => Stage::PidFile,
+            ErrorKind::ChownPidfile(_) => Stage::PidFile,
+            ErrorKind::ChownStreams(_) => Stage::RedirectStreams,
+            ErrorKind::OpenDevnull(_) => Stage::RedirectStreams,
+            ErrorKind::RedirectStreams(_) => Stage::RedirectStreams,
+            ErrorKind::OpenStreamPath(_) => Stage::RedirectStreams,
+            ErrorKind::CloseDevnull(_) => Stage::RedirectStreams,
+            ErrorKind::InheritFd(_) => Stage::RedirectStreams,
+            ErrorKind::InvalidEnvironmentVariable => Stage::Environment,
+            #[cfg(target_os = "linux")]
+            ErrorKind::ConnectJournal(_) => Stage::RedirectStreams,
+            #[cfg(target_os = "linux")]
+            ErrorKind::JournalIdentifierContainsNewline => Stage::RedirectStreams,
+            ErrorKind::CreatePipe(_) => Stage::RedirectStreams,
+            #[cfg(feature = "syslog")]
+            ErrorKind::SyslogIdentContainsNul => Stage::RedirectStreams,
+            ErrorKind::CreateDevnull(_) => Stage::Chroot,
+            ErrorKind::SetSignalHandler(_) => Stage::Signals,
+            ErrorKind::StartLogger(_) => Stage::RedirectStreams,
+            ErrorKind::WaitReady(_) => Stage::Readiness,
+            ErrorKind::ReadyTimeout => Stage::Readiness,
+            ErrorKind::NotReady => Stage::Readiness,
+            ErrorKind::Timeout => Stage::Readiness,
+            ErrorKind::TruncatePidfile(_) => Stage::PidFile,
+            ErrorKind::WritePid(_) => Stage::PidFile,
+            ErrorKind::WritePidUnspecifiedError => Stage::PidFile,
+            ErrorKind::SyncPidfile(_) => Stage::PidFile,
+            ErrorKind::ReadPidfile(_) => Stage::PidFile,
+            ErrorKind::InvalidPidfile => Stage::PidFile,
+            ErrorKind::StalePidfile => Stage::PidFile,
+            ErrorKind::SignalDaemon(_) => Stage::Control,
+            ErrorKind::StopTimeout => Stage::Control,
+            ErrorKind::PidFileNotConfigured => Stage::Configuration,
+            ErrorKind::ControlSocket(_) => Stage::Control,
+            ErrorKind::RemovePidfile(_) => Stage::PidFile,
+            ErrorKind::Chroot(_) => Stage::Chroot,
+            ErrorKind::Step(_) => Stage::Custom,
+            ErrorKind::PrivilegedAction(_) => Stage::PrivilegedAction,
+            ErrorKind::PreForkAction(_) => Stage::Configuration,
+            ErrorKind::ConflictingSignals => Stage::Configuration,
+            ErrorKind::DiscardedPrivilegeToken => Stage::Configuration,
+            ErrorKind::CreateWorkingDirectory(_) => Stage::ChangeDirectory,
+            ErrorKind::ChownWorkingDirectory(_) => Stage::ChangeDirectory,
         }
     }
 
-    fn errno(&self) -> Option<Errno> {
+    pub fn errno(&self) -> Option<Errno> {
         match self {
+            ErrorKind::RunningAsInit => None,
             ErrorKind::Fork(errno) => Some(*errno),
             ErrorKind::Wait(errno) => Some(*errno),
             ErrorKind::DetachSession(errno) => Some(*errno),
@@ -76,22 +564,140 @@ impl ErrorKind {
             ErrorKind::UserNotFound => None,
             ErrorKind::UserContainsNul => None,
             ErrorKind::SetUser(errno) => Some(*errno),
+            ErrorKind::PrivilegeDropIncomplete => None,
+            ErrorKind::NoNewPrivileges(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::CreateUserNamespace(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::WriteIdMap(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::CreateNamespaces(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::MakeMountsPrivate(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::BindMount(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::PivotRoot(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::UnmountOldRoot(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockUnsupported(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockOpenPath(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockAddRule(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::LandlockRestrict(errno) => Some(*errno),
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::PrivilegesContainNul => None,
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::ParsePrivileges(errno) => Some(*errno),
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            ErrorKind::SetPrivileges(errno) => Some(*errno),
+            ErrorKind::SetEffectiveGroup(errno) => Some(*errno),
+            ErrorKind::SetEffectiveUser(errno) => Some(*errno),
             ErrorKind::ChangeDirectory(errno) => Some(*errno),
             ErrorKind::PathContainsNul => None,
             ErrorKind::OpenPidfile(errno) => Some(*errno),
             ErrorKind::GetPidfileFlags(errno) => Some(*errno),
             ErrorKind::SetPidfileFlags(errno) => Some(*errno),
             ErrorKind::LockPidfile(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::BindInstanceSocket(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::ParentDeathSignal(errno) => Some(*errno),
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            ErrorKind::SetSubreaper(errno) => Some(*errno),
+            ErrorKind::ProcessNameContainsNul => None,
+            ErrorKind::SetPriority(errno) => Some(*errno),
+            ErrorKind::SetResourceLimit(errno) => Some(*errno),
+            ErrorKind::LockMemory(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetDumpable(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetIoPriority(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetOomScoreAdj(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetProcessName(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::SetProcessTitle(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::InstanceRunning => None,
+            ErrorKind::RenamePidfile(errno) => Some(*errno),
             ErrorKind::ChownPidfile(errno) => Some(*errno),
+            ErrorKind::ChownStreams(errno) => Some(*errno),
             ErrorKind::OpenDevnull(errno) => Some(*errno),
             ErrorKind::RedirectStreams(errno) => Some(*errno),
+            ErrorKind::OpenStreamPath(errno) => Some(*errno),
             ErrorKind::CloseDevnull(errno) => Some(*errno),
+            ErrorKind::InheritFd(errno) => Some(*errno),
+            ErrorKind::InvalidEnvironmentVariable => None,
+            #[cfg(target_os = "linux")]
+            ErrorKind::ConnectJournal(errno) => Some(*errno),
+            #[cfg(target_os = "linux")]
+            ErrorKind::JournalIdentifierContainsNewline => None,
+            ErrorKind::CreatePipe(errno) => Some(*errno),
+            #[cfg(feature = "syslog")]
+            ErrorKind::SyslogIdentContainsNul => None,
+            ErrorKind::CreateDevnull(errno) => Some(*errno),
+            ErrorKind::SetSignalHandler(errno) => Some(*errno),
+            ErrorKind::StartLogger(errno) => Some(*errno),
+            ErrorKind::WaitReady(errno) => Some(*errno),
+            ErrorKind::ReadyTimeout => None,
+            ErrorKind::NotReady => None,
+            ErrorKind::Timeout => None,
             ErrorKind::TruncatePidfile(errno) => Some(*errno),
             ErrorKind::WritePid(errno) => Some(*errno),
             ErrorKind::WritePidUnspecifiedError => None,
+            ErrorKind::SyncPidfile(errno) => Some(*errno),
+            ErrorKind::ReadPidfile(errno) => Some(*errno),
+            ErrorKind::InvalidPidfile => None,
+            ErrorKind::StalePidfile => None,
+            ErrorKind::SignalDaemon(errno) => Some(*errno),
+            ErrorKind::StopTimeout => None,
+            ErrorKind::PidFileNotConfigured => None,
+            ErrorKind::ControlSocket(errno) => Some(*errno),
+            ErrorKind::RemovePidfile(errno) => Some(*errno),
             ErrorKind::Chroot(errno) => Some(*errno),
+            ErrorKind::Step(errno) => Some(*errno),
+            ErrorKind::PrivilegedAction(errno) => Some(*errno),
+            ErrorKind::PreForkAction(errno) => Some(*errno),
+            ErrorKind::ConflictingSignals => None,
+            ErrorKind::DiscardedPrivilegeToken => None,
+            ErrorKind::CreateWorkingDirectory(errno) => Some(*errno),
+            ErrorKind::ChownWorkingDirectory(errno) => Some(*errno),
+        }
+    }
+}
+
+impl Error {
+    /// Error of a user callback, e.g. a custom step. The kind gets the OS error code of `source`
+    /// or `EIO`, since only the kind can be relayed to the parent process.
+    pub(crate) fn from_source<E: Into<BoxError>>(kind: fn(Errno) -> ErrorKind, source: E) -> Self {
+        let source = source.into();
+        let errno = source
+            .downcast_ref::<std::io::Error>()
+            .and_then(std::io::Error::raw_os_error)
+            .unwrap_or(libc::EIO);
+        Error {
+            kind: kind(errno),
+            source: Some(Arc::from(source)),
         }
     }
+
+    pub(crate) fn kind(&self) -> &ErrorKind {
+        &self.kind
+    }
+
+    pub(crate) fn into_kind(self) -> ErrorKind {
+        self.kind
+    }
+
+    /// Daemonization stage which failed.
+    pub fn stage(&self) -> Stage {
+        self.kind.stage()
+    }
 }
 
 impl std::fmt::Display for ErrorKind {
@@ -112,11 +718,40 @@ impl std::fmt::Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+/// Errors are compared by their kinds, sources can't be compared.
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl Eq for Error {}
+
+impl PartialOrd for Error {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Error {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.kind.cmp(&other.kind)
+    }
+}
+
+/// The source is the error of a user callback, it's present only in the process which ran it.
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.source {
+            Some(source) => Some(source.as_ref()),
+            None => None,
+        }
+    }
+}
 
 impl From<ErrorKind> for Error {
     fn from(kind: ErrorKind) -> Self {
-        Self { kind }
+        Self { kind, source: None }
     }
 }
 
@@ -156,7 +791,9 @@ impl Num for isize {
 
 pub fn check_err<N: Num, F: FnOnce(Errno) -> ErrorKind>(ret: N, f: F) -> Result<N, ErrorKind> {
     if ret.is_err() {
-        Err(f(errno()))
+        let err = f(errno());
+        debug!("{:?} failed: {}", err, err.description());
+        Err(err)
     } else {
         Ok(ret)
     }
@@ -167,3 +804,48 @@ pub fn errno() -> Errno {
         .raw_os_error()
         .expect("errno")
 }
+
+/// Restore `errno` saved with `errno` at the start of a signal handler, so the interrupted code
+/// doesn't observe errors of the handler.
+pub unsafe fn restore_errno(saved: Errno) {
+    if let Some(location) = errno_location() {
+        *location = saved;
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "dragonfly"))]
+unsafe fn errno_location() -> Option<*mut libc::c_int> {
+    Some(libc::__errno_location())
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno_location() -> Option<*mut libc::c_int> {
+    Some(libc::__error())
+}
+
+#[cfg(any(target_os = "android", target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno_location() -> Option<*mut libc::c_int> {
+    Some(libc::__errno())
+}
+
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+unsafe fn errno_location() -> Option<*mut libc::c_int> {
+    Some(libc::___errno())
+}
+
+/// `errno` can't be set on other platforms, it may be clobbered by signal handlers.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "dragonfly",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "android",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "illumos",
+    target_os = "solaris"
+)))]
+unsafe fn errno_location() -> Option<*mut libc::c_int> {
+    None
+}
@@ -0,0 +1,169 @@
+use std::cmp::Ordering as CmpOrdering;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use super::error::{check_err, errno, restore_errno, ErrorKind};
+use super::{register_handler, unregister_handler, Registration, Signal};
+
+static WRITE_END: AtomicI32 = AtomicI32::new(-1);
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Handle to signals received by the daemon, see `Daemonize::handle_signals`. `Signal::Term` and
+/// `Signal::Int` request shutdown. Dropping the handle restores the previous handlers.
+#[derive(Debug)]
+pub struct DaemonHandle {
+    fd: RawFd,
+    registration: Registration,
+}
+
+impl PartialEq for DaemonHandle {
+    fn eq(&self, other: &Self) -> bool {
+        self.fd == other.fd
+    }
+}
+
+impl Eq for DaemonHandle {}
+
+impl PartialOrd for DaemonHandle {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DaemonHandle {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.fd.cmp(&other.fd)
+    }
+}
+
+impl DaemonHandle {
+    /// Check whether shutdown was requested, never blocks.
+    pub fn should_shutdown(&self) -> bool {
+        SHUTDOWN.load(Ordering::Acquire)
+    }
+
+    /// Block until shutdown is requested, other signals are discarded.
+    pub fn wait_for_shutdown(&self) {
+        let mut signals = self.signals();
+        while !self.should_shutdown() {
+            if signals.next().is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Iterator over received signals, blocks until the next signal arrives. Each signal is
+    /// yielded once, even if several iterators exist.
+    pub fn signals(&self) -> Signals<'_> {
+        Signals { handle: self }
+    }
+}
+
+impl AsRawFd for DaemonHandle {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for DaemonHandle {
+    fn drop(&mut self) {
+        unsafe {
+            unregister_handler(&self.registration);
+            // A handler still running sees -1 and its write fails instead of hitting a reused
+            // descriptor.
+            let write_end = WRITE_END.swap(-1, Ordering::AcqRel);
+            if write_end != -1 {
+                libc::close(write_end);
+            }
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Iterator over signals received by the daemon, see `DaemonHandle::signals`.
+#[derive(Debug)]
+pub struct Signals<'a> {
+    handle: &'a DaemonHandle,
+}
+
+impl Iterator for Signals<'_> {
+    type Item = Signal;
+
+    fn next(&mut self) -> Option<Signal> {
+        loop {
+            let mut number = 0u8;
+            let ret = unsafe {
+                libc::read(
+                    self.handle.fd,
+                    &mut number as *mut u8 as *mut libc::c_void,
+                    1,
+                )
+            };
+            match ret {
+                -1 if errno() == libc::EINTR => continue,
+                1 => {
+                    if let Some(signal) = Signal::from_number(number as libc::c_int) {
+                        return Some(signal);
+                    }
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Install handlers for `signals` which forward them to the returned handle through a pipe.
+pub unsafe fn install(signals: &[Signal]) -> Result<DaemonHandle, ErrorKind> {
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::CreatePipe)?;
+    let result = (|| {
+        for fd in &fds {
+            check_err(
+                libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC),
+                ErrorKind::CreatePipe,
+            )?;
+        }
+        // The handler drops signals if the pipe is full instead of blocking.
+        check_err(
+            libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK),
+            ErrorKind::CreatePipe,
+        )
+    })();
+    if let Err(err) = result {
+        libc::close(fds[0]);
+        libc::close(fds[1]);
+        return Err(err);
+    }
+    // Kept open until the handle is dropped, the handler can be invoked at any moment.
+    WRITE_END.store(fds[1], Ordering::Release);
+
+    let registration = match register_handler(signals, handler) {
+        Ok(registration) => registration,
+        Err(err) => {
+            WRITE_END.store(-1, Ordering::Release);
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            return Err(err);
+        }
+    };
+    Ok(DaemonHandle {
+        fd: fds[0],
+        registration,
+    })
+}
+
+extern "C" fn handler(signal: libc::c_int) {
+    unsafe {
+        let saved_errno = errno();
+        if signal == libc::SIGTERM || signal == libc::SIGINT {
+            SHUTDOWN.store(true, Ordering::Release);
+        }
+        let number = signal as u8;
+        libc::write(
+            WRITE_END.load(Ordering::Acquire),
+            &number as *const u8 as *const libc::c_void,
+            1,
+        );
+        restore_errno(saved_errno);
+    }
+}
@@ -0,0 +1,52 @@
+use std::mem::{size_of, zeroed};
+
+use super::error::{check_err, errno, ErrorKind};
+
+/// Mechanism which guarantees that only one instance of the daemon runs, see
+/// `Daemonize::single_instance`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum InstanceGuard {
+    /// Bind a unix socket with the name in the abstract namespace. The socket disappears with the
+    /// daemon, so there is nothing stale to clean up, and it doesn't depend on the file system
+    /// view of the daemon. The namespace is shared by processes of the same network namespace.
+    AbstractSocket(String),
+}
+
+/// Acquire the guard, the returned descriptor must stay open while the daemon runs.
+pub unsafe fn acquire(guard: &InstanceGuard) -> Result<libc::c_int, ErrorKind> {
+    match guard {
+        InstanceGuard::AbstractSocket(name) => bind_abstract(name),
+    }
+}
+
+unsafe fn bind_abstract(name: &str) -> Result<libc::c_int, ErrorKind> {
+    let mut addr: libc::sockaddr_un = zeroed();
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    // The leading NUL places the name in the abstract namespace.
+    if name.len() >= addr.sun_path.len() {
+        return Err(ErrorKind::BindInstanceSocket(libc::ENAMETOOLONG));
+    }
+    for (dst, src) in addr.sun_path[1..].iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    let len = size_of::<libc::sa_family_t>() + 1 + name.len();
+
+    let fd = check_err(
+        libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0),
+        ErrorKind::BindInstanceSocket,
+    )?;
+    let ret = libc::bind(
+        fd,
+        &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+        len as libc::socklen_t,
+    );
+    if ret == -1 {
+        let err = errno();
+        libc::close(fd);
+        return Err(match err {
+            libc::EADDRINUSE => ErrorKind::InstanceRunning,
+            err => ErrorKind::BindInstanceSocket(err),
+        });
+    }
+    Ok(fd)
+}
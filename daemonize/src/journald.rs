@@ -0,0 +1,37 @@
+use std::io::Write;
+use std::net::Shutdown;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixStream;
+
+use super::error::{check_err, errno, ErrorKind};
+
+const STDOUT_SOCKET: &str = "/run/systemd/journal/stdout";
+
+/// Connect `fd` to the journal stream socket, lines are logged with `identifier` and `priority`
+/// unless prefixed with `<N>` by the daemon.
+pub unsafe fn connect(
+    fd: libc::c_int,
+    identifier: &str,
+    priority: libc::c_int,
+) -> Result<(), ErrorKind> {
+    if identifier.contains('\n') {
+        return Err(ErrorKind::JournalIdentifierContainsNewline);
+    }
+
+    let mut stream =
+        UnixStream::connect(STDOUT_SOCKET).map_err(|_| ErrorKind::ConnectJournal(errno()))?;
+
+    // Identifier, unit id, priority, level prefix, forward to syslog, kmsg and console.
+    let header = format!("{}\n\n{}\n1\n0\n0\n0\n", identifier, priority);
+    stream
+        .write_all(header.as_bytes())
+        .map_err(|_| ErrorKind::ConnectJournal(errno()))?;
+    stream
+        .shutdown(Shutdown::Read)
+        .map_err(|_| ErrorKind::ConnectJournal(errno()))?;
+
+    let raw_fd = stream.into_raw_fd();
+    check_err(libc::dup2(raw_fd, fd), ErrorKind::RedirectStreams)?;
+    check_err(libc::close(raw_fd), ErrorKind::RedirectStreams)?;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::ptr;
+
+use super::error::{check_err, ErrorKind};
+use super::pathbuf_into_cstring;
+
+const CREATE_RULESET_VERSION: u32 = 1;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+// Available since Landlock ABI version 3.
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+const ACCESS_WRITE: u64 = ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_REMOVE_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_CHAR
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_BLOCK
+    | ACCESS_FS_MAKE_SYM
+    | ACCESS_FS_TRUNCATE;
+// Rights which make sense for files, the rest are applicable to directories only.
+const ACCESS_FILE: u64 =
+    ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: libc::c_int,
+}
+
+/// Restrict filesystem access of the calling process to `read` and `write` paths, paths allowed
+/// for writing are allowed for reading as well.
+pub unsafe fn restrict(read: &[PathBuf], write: &[PathBuf]) -> Result<(), ErrorKind> {
+    let abi = check_err(
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            ptr::null::<RulesetAttr>(),
+            0,
+            CREATE_RULESET_VERSION,
+        ),
+        ErrorKind::LandlockUnsupported,
+    )?;
+
+    let mut handled = (ACCESS_READ | ACCESS_WRITE) & !ACCESS_FS_TRUNCATE;
+    if abi >= 3 {
+        handled |= ACCESS_FS_TRUNCATE;
+    }
+
+    let attr = RulesetAttr {
+        handled_access_fs: handled,
+    };
+    let ruleset_fd = check_err(
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr as *const RulesetAttr,
+            size_of::<RulesetAttr>(),
+            0,
+        ),
+        ErrorKind::LandlockUnsupported,
+    )? as libc::c_int;
+
+    let result = add_rules(ruleset_fd, read, ACCESS_READ & handled)
+        .and_then(|_| add_rules(ruleset_fd, write, (ACCESS_READ | ACCESS_WRITE) & handled))
+        .and_then(|_| restrict_self(ruleset_fd));
+
+    libc::close(ruleset_fd);
+
+    result
+}
+
+unsafe fn add_rules(
+    ruleset_fd: libc::c_int,
+    paths: &[PathBuf],
+    access: u64,
+) -> Result<(), ErrorKind> {
+    for path in paths {
+        let path_c = pathbuf_into_cstring(path.clone())?;
+        let fd = check_err(
+            libc::open(path_c.as_ptr(), libc::O_PATH | libc::O_CLOEXEC),
+            ErrorKind::LandlockOpenPath,
+        )?;
+
+        let result = add_rule(ruleset_fd, fd, access);
+        libc::close(fd);
+        result?;
+    }
+    Ok(())
+}
+
+unsafe fn add_rule(ruleset_fd: libc::c_int, fd: libc::c_int, access: u64) -> Result<(), ErrorKind> {
+    let mut stat: libc::stat = std::mem::zeroed();
+    check_err(libc::fstat(fd, &mut stat), ErrorKind::LandlockOpenPath)?;
+
+    let allowed_access = if stat.st_mode & libc::S_IFMT == libc::S_IFDIR {
+        access
+    } else {
+        access & ACCESS_FILE
+    };
+
+    let attr = PathBeneathAttr {
+        allowed_access,
+        parent_fd: fd,
+    };
+    check_err(
+        libc::syscall(
+            libc::SYS_landlock_add_rule,
+            ruleset_fd,
+            RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        ),
+        ErrorKind::LandlockAddRule,
+    )?;
+    Ok(())
+}
+
+unsafe fn restrict_self(ruleset_fd: libc::c_int) -> Result<(), ErrorKind> {
+    // Unprivileged processes are permitted to restrict themselves only with no_new_privs set.
+    check_err(
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0),
+        ErrorKind::NoNewPrivileges,
+    )?;
+    check_err(
+        libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0),
+        ErrorKind::LandlockRestrict,
+    )?;
+    Ok(())
+}
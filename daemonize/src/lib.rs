@@ -43,23 +43,112 @@
 //! }
 //! ```
 
+// Diagnostics of daemonization steps, compiled out without the `tracing` and `log` features. With
+// both features records go to `tracing` only, it forwards them to `log` without a subscriber, so
+// they're not emitted twice.
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::debug!($($arg)+);
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        ::log::debug!($($arg)+);
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        {
+            if false {
+                let _ = format_args!($($arg)+);
+            }
+        }
+    }};
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        ::tracing::warn!($($arg)+);
+        #[cfg(all(feature = "log", not(feature = "tracing")))]
+        ::log::warn!($($arg)+);
+        #[cfg(not(any(feature = "tracing", feature = "log")))]
+        {
+            if false {
+                let _ = format_args!($($arg)+);
+            }
+        }
+    }};
+}
+
+#[cfg(feature = "cli")]
+pub mod cli;
+pub mod control;
+mod decorate;
+mod handle;
 mod error;
+#[cfg(target_os = "linux")]
+mod journald;
+#[cfg(target_os = "linux")]
+mod landlock;
+mod logger;
+#[cfg(target_os = "linux")]
+mod instance;
+#[cfg(target_os = "linux")]
+mod namespaces;
+mod pidfile;
+pub mod proc_title;
+mod reaper;
+mod ready;
+mod reopen;
+mod step;
+mod tee;
+#[cfg(any(target_os = "illumos", target_os = "solaris"))]
+mod solaris;
+#[cfg(feature = "syslog")]
+mod syslog;
+#[cfg(feature = "channel")]
+mod channel;
 
 extern crate libc;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "tracing")]
+extern crate tracing;
+#[cfg(feature = "cli")]
+extern crate clap;
+#[cfg(feature = "signal-hook")]
+extern crate signal_hook;
+#[cfg(feature = "channel")]
+extern crate serde;
+#[cfg(any(feature = "channel", feature = "json"))]
+extern crate serde_json;
 
 use std::env::set_current_dir;
-use std::ffi::CString;
+use std::ffi::{CStr, CString, OsStr, OsString};
 use std::fmt;
-use std::fs::File;
-use std::mem::transmute;
-use std::os::unix::ffi::OsStringExt;
-use std::os::unix::io::AsRawFd;
+use std::fs::{create_dir_all, File, OpenOptions};
+use std::marker::PhantomData;
+use std::mem::{transmute, zeroed};
+use std::ops::{BitOr, BitOrAssign};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
 use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{Duration, Instant};
 
-use self::error::{check_err, errno, ErrorKind};
+use self::error::{check_err, errno, Errno, ErrorKind};
 
-pub use self::error::Error;
+pub use self::decorate::LinePrefix;
+pub use self::error::{BoxError, Error, Stage};
+pub use self::handle::{DaemonHandle, Signals};
+pub use self::pidfile::{
+    is_process_running, process_start_time, read_pid_file, LockStyle, PidFile, PidFileFormat,
+    PidFileGuard, PidFileInfo,
+};
+pub use self::ready::Ready;
+pub use self::step::{After, Step};
+#[cfg(feature = "channel")]
+pub use self::channel::{Receiver, Sender};
+#[cfg(target_os = "linux")]
+pub use self::instance::InstanceGuard;
+#[cfg(target_os = "linux")]
+pub use self::namespaces::Namespaces;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 enum UserImpl {
@@ -117,12 +206,67 @@ impl From<u32> for Group {
     }
 }
 
-/// File mode creation mask.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(Debug)]
+enum RootImpl {
+    Chroot(PathBuf),
+    #[cfg(target_os = "linux")]
+    PivotRoot(PathBuf, PathBuf),
+}
+
+/// File mode creation mask, see `Daemonize::umask`. Masks can be combined with `|`, e.g.
+/// `Mask::GROUP_WRITE | Mask::OTHERS_ALL` is `0o027`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub struct Mask {
     inner: libc::mode_t,
 }
 
+impl Mask {
+    /// Keep all permissions.
+    pub const NONE: Mask = Mask::from_octal(0);
+    /// Remove write permission of the group.
+    pub const GROUP_WRITE: Mask = Mask::from_octal(0o020);
+    /// Remove all permissions of the group.
+    pub const GROUP_ALL: Mask = Mask::from_octal(0o070);
+    /// Remove write permission of others.
+    pub const OTHERS_WRITE: Mask = Mask::from_octal(0o002);
+    /// Remove all permissions of others.
+    pub const OTHERS_ALL: Mask = Mask::from_octal(0o007);
+
+    /// Mask of permission `bits`, e.g. `0o027`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` has bits other than `0o777`, e.g. when given a decimal literal like
+    /// `777` instead of `0o777`.
+    pub const fn from_octal(bits: u32) -> Mask {
+        assert!(bits & !0o777 == 0, "umask has bits other than 0o777");
+        Mask {
+            inner: bits as libc::mode_t,
+        }
+    }
+
+    /// Permission bits of the mask.
+    pub fn bits(&self) -> libc::mode_t {
+        self.inner
+    }
+}
+
+impl BitOr for Mask {
+    type Output = Mask;
+
+    fn bitor(self, other: Mask) -> Mask {
+        Mask {
+            inner: self.inner | other.inner,
+        }
+    }
+}
+
+impl BitOrAssign for Mask {
+    fn bitor_assign(&mut self, other: Mask) {
+        self.inner |= other.inner;
+    }
+}
+
 impl From<u32> for Mask {
     fn from(inner: u32) -> Mask {
         Mask {
@@ -131,10 +275,30 @@ impl From<u32> for Mask {
     }
 }
 
+#[derive(Debug)]
+enum CoreDumps {
+    Disabled,
+    Allowed(PathBuf),
+}
+
 #[derive(Debug)]
 enum StdioImpl {
     Devnull,
     RedirectToFile(File),
+    RedirectToPath(PathBuf),
+    AppendToPath(PathBuf),
+    MergeWithStdout,
+    Decorated(File, LinePrefix),
+    Tee(File),
+    Piped,
+    Logger(PathBuf),
+    #[cfg(target_os = "linux")]
+    Journald(String),
+    #[cfg(feature = "syslog")]
+    Syslog {
+        facility: libc::c_int,
+        ident: String,
+    },
     Keep,
 }
 
@@ -145,17 +309,128 @@ pub struct Stdio {
 }
 
 impl Stdio {
+    /// Descriptor opened before the forks, if any.
+    fn as_raw_fd(&self) -> Option<RawFd> {
+        match &self.inner {
+            StdioImpl::RedirectToFile(file)
+            | StdioImpl::Decorated(file, _)
+            | StdioImpl::Tee(file) => Some(file.as_raw_fd()),
+            _ => None,
+        }
+    }
+
+    /// Redirect the stream to `/dev/null`, default for all standard streams.
     pub fn devnull() -> Self {
         Self {
             inner: StdioImpl::Devnull,
         }
     }
 
+    /// Keep the stream inherited from the parent process as is, e.g. to preserve output to the
+    /// terminal for debugging or to the pipe of a supervisor like runit.
     pub fn keep() -> Self {
         Self {
             inner: StdioImpl::Keep,
         }
     }
+
+    /// Redirect the stream to the file at `path`. Unlike `From<File>`, the file is opened by the
+    /// daemon itself after root change and privileges drop, so it's created with the daemon's
+    /// umask and ownership and `path` is interpreted relative to the new root. Output files are
+    /// truncated.
+    pub fn from_path<F: AsRef<Path>>(path: F) -> Self {
+        Self {
+            inner: StdioImpl::RedirectToPath(path.as_ref().to_owned()),
+        }
+    }
+
+    /// Append the stream output to the file at `path`, so restarts don't wipe previous logs. The
+    /// file is opened by the daemon itself, see `from_path`. Used for stdin, the file is opened
+    /// for reading as with `from_path`.
+    pub fn append<F: AsRef<Path>>(path: F) -> Self {
+        Self {
+            inner: StdioImpl::AppendToPath(path.as_ref().to_owned()),
+        }
+    }
+
+    /// Redirect the stream to any file descriptor, e.g. a socket, a pipe or a descriptor received
+    /// from a supervisor.
+    pub fn from_fd<F: Into<OwnedFd>>(fd: F) -> Self {
+        Self::from(fd.into())
+    }
+
+    /// Write the stream output to `file` prepending `prefix` to every line, e.g. a timestamp.
+    /// Lines are decorated by a thread started in the daemon, so the output written right before
+    /// the process exits may be lost. stdin is redirected to `/dev/null`.
+    pub fn decorated(file: File, prefix: LinePrefix) -> Self {
+        Self {
+            inner: StdioImpl::Decorated(file, prefix),
+        }
+    }
+
+    /// Write the stream output both to `file` and to the stream inherited from the parent
+    /// process, e.g. to watch a real daemon in the launching terminal while debugging. Output is
+    /// copied by a thread started in the daemon, so the output written right before the process
+    /// exits may be lost. stdin is redirected to `/dev/null`.
+    pub fn tee(file: File) -> Self {
+        Self {
+            inner: StdioImpl::Tee(file),
+        }
+    }
+
+    /// Pass the stream to the parent process through a pipe, the read end is available as
+    /// `Parent::stdout` or `Parent::stderr` in `execute` mode, e.g. to print the startup output
+    /// of the daemon. Once the parent closes the read end, writes of the daemon fail with
+    /// `EPIPE`, so it's not suitable for `start`. stdin is redirected to `/dev/null`.
+    pub fn piped() -> Self {
+        Self {
+            inner: StdioImpl::Piped,
+        }
+    }
+
+    /// Pass the stream through a pipe to a helper process which appends it to the file at `path`,
+    /// in the manner of svlogd. The file is opened before root change and the helper runs with
+    /// the daemon's user and group, so the daemon itself keeps no log files open. The helper exits
+    /// when the daemon and its children close the stream. stdin is redirected to `/dev/null`.
+    pub fn logger<F: AsRef<Path>>(path: F) -> Self {
+        Self {
+            inner: StdioImpl::Logger(path.as_ref().to_owned()),
+        }
+    }
+
+    /// Connect the stream to systemd-journald, so the daemon logs to the journal with
+    /// `identifier` even if it's not started by systemd. stdout is logged with `LOG_INFO`
+    /// priority, stderr with `LOG_ERR`, lines may override it with a `<N>` prefix. stdin is
+    /// redirected to `/dev/null`. The connection is established before root change.
+    #[cfg(target_os = "linux")]
+    pub fn journald<S: Into<String>>(identifier: S) -> Self {
+        Self {
+            inner: StdioImpl::Journald(identifier.into()),
+        }
+    }
+
+    /// Send the stream output line by line to the system logger with `facility`, e.g.
+    /// `libc::LOG_DAEMON`, and `ident`. stdout is logged with `LOG_INFO` priority, stderr with
+    /// `LOG_ERR`, stdin is redirected to `/dev/null`. The connection is opened before root change,
+    /// lines are forwarded by a thread started in the daemon. The identifier is shared by the
+    /// process, so stderr's one wins if both streams use syslog.
+    #[cfg(feature = "syslog")]
+    pub fn syslog<S: Into<String>>(facility: libc::c_int, ident: S) -> Self {
+        Self {
+            inner: StdioImpl::Syslog {
+                facility,
+                ident: ident.into(),
+            },
+        }
+    }
+
+    /// Duplicate the daemon's stdout onto the stream, intended for `stderr`. Both streams share
+    /// a single file description and offset, so the output is properly interleaved.
+    pub fn merge_with_stdout() -> Self {
+        Self {
+            inner: StdioImpl::MergeWithStdout,
+        }
+    }
 }
 
 impl From<File> for Stdio {
@@ -166,29 +441,571 @@ impl From<File> for Stdio {
     }
 }
 
+impl From<OwnedFd> for Stdio {
+    fn from(fd: OwnedFd) -> Self {
+        Self::from(File::from(fd))
+    }
+}
+
+/// Signal which can be handled by the daemon.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub enum Signal {
+    Hup,
+    Int,
+    Term,
+    Usr1,
+    Usr2,
+}
+
+impl Signal {
+    fn number(self) -> libc::c_int {
+        match self {
+            Signal::Hup => libc::SIGHUP,
+            Signal::Int => libc::SIGINT,
+            Signal::Term => libc::SIGTERM,
+            Signal::Usr1 => libc::SIGUSR1,
+            Signal::Usr2 => libc::SIGUSR2,
+        }
+    }
+
+    fn from_number(number: libc::c_int) -> Option<Signal> {
+        match number {
+            libc::SIGHUP => Some(Signal::Hup),
+            libc::SIGINT => Some(Signal::Int),
+            libc::SIGTERM => Some(Signal::Term),
+            libc::SIGUSR1 => Some(Signal::Usr1),
+            libc::SIGUSR2 => Some(Signal::Usr2),
+            _ => None,
+        }
+    }
+}
+
+/// Handlers installed by `register_handler`, see `unregister_handler`.
+#[cfg(not(feature = "signal-hook"))]
+struct Registration {
+    /// Replaced actions of the signals.
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+/// Handlers installed by `register_handler`, see `unregister_handler`.
+#[cfg(feature = "signal-hook")]
+#[derive(Debug)]
+struct Registration {
+    ids: Vec<::signal_hook::SigId>,
+}
+
+#[cfg(not(feature = "signal-hook"))]
+impl fmt::Debug for Registration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let signals = self.previous.iter().map(|&(signal, _)| signal);
+        f.debug_list().entries(signals).finish()
+    }
+}
+
+/// Install `handler` for `signals`, replacing the current handlers.
+#[cfg(not(feature = "signal-hook"))]
+unsafe fn register_handler(
+    signals: &[Signal],
+    handler: extern "C" fn(libc::c_int),
+) -> Result<Registration, ErrorKind> {
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    let mut registration = Registration {
+        previous: Vec::new(),
+    };
+    for signal in signals {
+        let mut previous: libc::sigaction = std::mem::zeroed();
+        check_err(
+            libc::sigaction(signal.number(), &action, &mut previous),
+            ErrorKind::SetSignalHandler,
+        )?;
+        registration.previous.push((signal.number(), previous));
+    }
+    Ok(registration)
+}
+
+/// Register `handler` for `signals` with signal-hook, so it runs along with the handlers the
+/// application registers with signal-hook instead of replacing them.
+#[cfg(feature = "signal-hook")]
+unsafe fn register_handler(
+    signals: &[Signal],
+    handler: extern "C" fn(libc::c_int),
+) -> Result<Registration, ErrorKind> {
+    let mut registration = Registration { ids: Vec::new() };
+    for signal in signals {
+        let number = signal.number();
+        let id =
+            ::signal_hook::low_level::register(number, move || handler(number)).map_err(|err| {
+                ErrorKind::SetSignalHandler(err.raw_os_error().unwrap_or(libc::EINVAL))
+            })?;
+        registration.ids.push(id);
+    }
+    Ok(registration)
+}
+
+/// Restore the actions replaced by `register_handler`, registered first are restored last.
+#[cfg(not(feature = "signal-hook"))]
+unsafe fn unregister_handler(registration: &Registration) {
+    for (signal, previous) in registration.previous.iter().rev() {
+        libc::sigaction(*signal, previous, std::ptr::null_mut());
+    }
+}
+
+/// Unregister the handlers registered with signal-hook by `register_handler`.
+#[cfg(feature = "signal-hook")]
+unsafe fn unregister_handler(registration: &Registration) {
+    for id in &registration.ids {
+        ::signal_hook::low_level::unregister(*id);
+    }
+}
+
+/// Restore default dispositions of all signals except ignored signals in `keep_ignored` and the
+/// stack overflow handlers of the Rust runtime, and unblock all signals.
+unsafe fn reset_signals(keep_ignored: &[libc::c_int]) -> Result<(), ErrorKind> {
+    let mut action: libc::sigaction = std::mem::zeroed();
+    action.sa_sigaction = libc::SIG_DFL;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    // Covers real-time signals on all supported platforms, invalid numbers are rejected by
+    // `sigaction`, as well as `SIGKILL` and `SIGSTOP`.
+    for signal in 1..128 {
+        // The Rust runtime reports stack overflows from its `SIGSEGV` and `SIGBUS` handlers.
+        if signal == libc::SIGSEGV || signal == libc::SIGBUS {
+            continue;
+        }
+        let mut current: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(signal, std::ptr::null(), &mut current) == -1 {
+            continue;
+        }
+        if current.sa_sigaction == libc::SIG_IGN && keep_ignored.contains(&signal) {
+            continue;
+        }
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+
+    let mut mask: libc::sigset_t = std::mem::zeroed();
+    libc::sigemptyset(&mut mask);
+    check_err(
+        libc::sigprocmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut()),
+        ErrorKind::SetSignalHandler,
+    )?;
+    Ok(())
+}
+
+/// What to do if the process runs as PID 1, e.g. as the entrypoint of a container. Forking
+/// would terminate the container, since the init process exits.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum InitPolicy {
+    /// Fail with an error, default.
+    Refuse,
+    /// Switch to the foreground mode.
+    Foreground,
+    /// Switch to the foreground mode and reap all terminated children, including orphans
+    /// reparented to the daemon. Children can't be waited with `waitpid` in this mode.
+    ForegroundReaper,
+}
+
+/// When the pid file is created and written, see `Daemonize::pid_file_write`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum WriteStage {
+    /// Create and lock the pid file before the root change and privileges drop, write the pid
+    /// when daemonization is finished, default.
+    Last,
+    /// Create, lock and write the pid file before the root change.
+    BeforeChroot,
+    /// Create, lock and write the pid file after the root change and privileges drop, so it's
+    /// owned by the daemon user without `chown_pid_file`. The path is interpreted relative to
+    /// the new root.
+    AfterPrivilegeDrop,
+}
+
+/// Process created by a fork, see `Daemonize::post_fork_action`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Forked {
+    /// Intermediate process, which creates a new session and exits after the second fork.
+    FirstChild,
+    Daemon,
+}
+
+/// Privileges dropping strategy.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum PrivilegeDrop {
+    /// Change real, effective and saved ids with `setgid`/`setuid`, privileges can't be regained.
+    Permanent,
+    /// Change only effective ids with `setegid`/`seteuid`, privileges can be regained with
+    /// `PrivilegeToken` returned in `Child` and `Daemonized`. Rejected by `Daemonize::start` and
+    /// `Daemonize::start_sync`, which have no way to return the token.
+    Temporary,
+}
+
+/// Resource limited with `setrlimit`, see `Daemonize::rlimit`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Resource {
+    /// Size of the address space in bytes, `RLIMIT_AS`.
+    AddressSpace,
+    /// Size of core files in bytes, `RLIMIT_CORE`.
+    Core,
+    /// CPU time in seconds, `RLIMIT_CPU`.
+    Cpu,
+    /// Size of the data segment in bytes, `RLIMIT_DATA`.
+    Data,
+    /// Size of created files in bytes, `RLIMIT_FSIZE`.
+    FileSize,
+    /// Locked memory in bytes, `RLIMIT_MEMLOCK`.
+    #[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+    MemLock,
+    /// Number of open file descriptors, `RLIMIT_NOFILE`.
+    NoFile,
+    /// Number of processes of the user, `RLIMIT_NPROC`.
+    #[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+    NProc,
+    /// Size of the stack in bytes, `RLIMIT_STACK`.
+    Stack,
+}
+
+impl Resource {
+    fn number(self) -> libc::c_int {
+        (match self {
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::Data => libc::RLIMIT_DATA,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            #[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+            Resource::MemLock => libc::RLIMIT_MEMLOCK,
+            Resource::NoFile => libc::RLIMIT_NOFILE,
+            #[cfg(not(any(target_os = "illumos", target_os = "solaris")))]
+            Resource::NProc => libc::RLIMIT_NPROC,
+            Resource::Stack => libc::RLIMIT_STACK,
+        }) as libc::c_int
+    }
+}
+
+/// Flags of `mlockall`, see `Daemonize::lock_memory`. Flags can be combined with `|`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct MlockFlags {
+    inner: libc::c_int,
+}
+
+impl MlockFlags {
+    /// Lock pages which are currently mapped.
+    pub const CURRENT: MlockFlags = MlockFlags {
+        inner: libc::MCL_CURRENT,
+    };
+    /// Lock pages which will be mapped in the future.
+    pub const FUTURE: MlockFlags = MlockFlags {
+        inner: libc::MCL_FUTURE,
+    };
+    /// Lock pages when they are faulted in rather than at once, combined with `CURRENT` or
+    /// `FUTURE`.
+    #[cfg(target_os = "linux")]
+    pub const ON_FAULT: MlockFlags = MlockFlags {
+        inner: libc::MCL_ONFAULT,
+    };
+}
+
+impl BitOr for MlockFlags {
+    type Output = MlockFlags;
+
+    fn bitor(self, other: MlockFlags) -> MlockFlags {
+        MlockFlags {
+            inner: self.inner | other.inner,
+        }
+    }
+}
+
+impl BitOrAssign for MlockFlags {
+    fn bitor_assign(&mut self, other: MlockFlags) {
+        self.inner |= other.inner;
+    }
+}
+
+/// I/O scheduling class, see `Daemonize::io_priority`.
+#[cfg(target_os = "linux")]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum IoClass {
+    /// Served first, requires root.
+    RealTime,
+    /// Default class of processes.
+    BestEffort,
+    /// Served only when no other process needs the disk, the level is ignored.
+    Idle,
+}
+
+/// Handle to switch effective user and group ids after a temporary privilege drop.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct PrivilegeToken {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    saved_uid: libc::uid_t,
+    saved_gid: libc::gid_t,
+}
+
+impl PrivilegeToken {
+    /// Restore effective user and group ids which process had before privileges were dropped.
+    pub fn escalate(&self) -> Result<(), Error> {
+        unsafe {
+            if self.uid.is_some() {
+                set_effective_user(self.saved_uid)?;
+            }
+            if self.gid.is_some() {
+                set_effective_group(self.saved_gid)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drop effective user and group ids to the configured `user` and `group` again.
+    pub fn relinquish(&self) -> Result<(), Error> {
+        unsafe {
+            if let Some(gid) = self.gid {
+                set_effective_group(gid)?;
+            }
+            if let Some(uid) = self.uid {
+                set_effective_user(uid)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 /// Parent process execution outcome.
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug)]
 #[non_exhaustive]
-pub struct Parent {
+pub struct Parent<M = ()> {
+    /// PID of the first forked child, it has already exited.
+    pub first_child_pid: libc::pid_t,
     pub first_child_exit_code: i32,
+    /// PID of the daemon, absent if daemonization failed before the second fork and errors are
+    /// not relayed.
+    pub daemon_pid: Option<libc::pid_t>,
+    /// Process file descriptor of the daemon, absent without `daemon_pid`, if the daemon has
+    /// already exited, if its start time is unknown since `/proc` is not mounted or if the kernel
+    /// doesn't support `pidfd_open`.
+    #[cfg(target_os = "linux")]
+    pub daemon_pidfd: Option<OwnedFd>,
+    /// Read end of the daemon's stdout, present only with `Stdio::piped`.
+    pub stdout: Option<File>,
+    /// Read end of the daemon's stderr, present only with `Stdio::piped`.
+    pub stderr: Option<File>,
+    #[cfg(feature = "channel")]
+    channel: Option<File>,
+    message: PhantomData<fn() -> M>,
+}
+
+impl<M> Parent<M> {
+    /// Descriptors are compared by their numbers, as `OwnedFd` and `File` can't be compared.
+    fn key(&self) -> (libc::pid_t, i32, Option<libc::pid_t>, Vec<Option<RawFd>>) {
+        let mut fds = vec![
+            self.stdout.as_ref().map(AsRawFd::as_raw_fd),
+            self.stderr.as_ref().map(AsRawFd::as_raw_fd),
+        ];
+        #[cfg(target_os = "linux")]
+        fds.push(self.daemon_pidfd.as_ref().map(AsRawFd::as_raw_fd));
+        #[cfg(feature = "channel")]
+        fds.push(self.channel.as_ref().map(AsRawFd::as_raw_fd));
+        (
+            self.first_child_pid,
+            self.first_child_exit_code,
+            self.daemon_pid,
+            fds,
+        )
+    }
+
+    /// Take the receiving half of the channel from the daemon, present only with
+    /// `Daemonize::with_channel`.
+    #[cfg(feature = "channel")]
+    pub fn receiver(&mut self) -> Option<Receiver<M>> {
+        self.channel.take().map(channel::receiver)
+    }
+}
+
+impl<M> PartialEq for Parent<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<M> Eq for Parent<M> {}
+
+impl<M> PartialOrd for Parent<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for Parent<M> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
 }
 
 /// Child process execution outcome.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 #[non_exhaustive]
-pub struct Child<T> {
+pub struct Child<T, M = ()> {
+    pub privileged_action_result: T,
+    /// Resolved user id, absent if the user is not changed.
+    pub uid: Option<libc::uid_t>,
+    /// Resolved group id, absent if the group is not changed.
+    pub gid: Option<libc::gid_t>,
+    /// Locked pid file, it's kept open to hold the lock.
+    pub pid_file_fd: Option<RawFd>,
+    pid_file: Option<PathBuf>,
+    /// Present only if privileges were dropped with `PrivilegeDrop::Temporary`.
+    pub privilege_token: Option<PrivilegeToken>,
+    /// Present only if the parent waits for readiness, see `Daemonize::wait_ready`.
+    pub ready: Option<Ready>,
+    /// Present only if signals are handled, see `Daemonize::handle_signals`.
+    pub handle: Option<DaemonHandle>,
+    /// Descriptors passed with `Daemonize::inherit_fd` in the same order, see there.
+    pub inherited_fds: Vec<RawFd>,
+    #[cfg(feature = "channel")]
+    channel: Option<channel::WriteEnd>,
+    message: PhantomData<fn(M)>,
+}
+
+impl<T, M> Child<T, M> {
+    /// Take the locked pid file as a guard which removes it when dropped. The daemon must be able
+    /// to remove the file, e.g. it's created with `WriteStage::AfterPrivilegeDrop`. Absent if the
+    /// pid file created before the root change is outside of the new root.
+    pub fn pid_file_guard(&mut self) -> Option<PidFileGuard> {
+        match (self.pid_file_fd.take(), self.pid_file.take()) {
+            (Some(fd), Some(path)) => {
+                Some(PidFileGuard::new(path, unsafe { File::from_raw_fd(fd) }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Take the sending half of the channel to the parent process, present only with
+    /// `Daemonize::with_channel`.
+    #[cfg(feature = "channel")]
+    pub fn sender(&mut self) -> Option<Sender<M>> {
+        self.channel.take().map(channel::WriteEnd::into_sender)
+    }
+}
+
+/// Result of `Daemonize::start_extended`.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Daemonized<T> {
     pub privileged_action_result: T,
+    pub pid: libc::pid_t,
+    /// Resolved user id, absent if the user is not changed.
+    pub uid: Option<libc::uid_t>,
+    /// Resolved group id, absent if the group is not changed.
+    pub gid: Option<libc::gid_t>,
+    /// Locked pid file, dropping it removes the file, see `PidFileGuard::keep`.
+    pub pid_file: Option<PidFileGuard>,
+    /// Present only if signals are handled, see `Daemonize::handle_signals`.
+    pub handle: Option<DaemonHandle>,
+    /// Descriptors passed with `Daemonize::inherit_fd` in the same order, see there.
+    pub inherited_fds: Vec<RawFd>,
+    /// Present only if privileges were dropped with `PrivilegeDrop::Temporary`.
+    pub privilege_token: Option<PrivilegeToken>,
+}
+
+/// State of the daemon passed to the privileged action, see
+/// `Daemonize::privileged_action_with_context`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct PrivilegedContext {
+    /// Resolved user id, absent if the user is not changed.
+    pub uid: Option<libc::uid_t>,
+    /// Resolved group id, absent if the group is not changed.
+    pub gid: Option<libc::gid_t>,
+    /// Locked pid file, absent if it's not created yet.
+    pub pid_file_fd: Option<RawFd>,
+    /// Working directory of the daemon after daemonization, inside the new root if it's changed.
+    pub working_directory: PathBuf,
+    pub pid: libc::pid_t,
+}
+
+/// Target of a standard stream in `Plan`, descriptors opened before the forks are given by their
+/// numbers.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub enum StreamTarget {
+    Devnull,
+    File(RawFd),
+    Path(PathBuf),
+    AppendToPath(PathBuf),
+    /// Merged with stdout, see `Stdio::merge_with_stdout`.
+    Stdout,
+    Decorated(RawFd, LinePrefix),
+    Tee(RawFd),
+    Piped,
+    Logger(PathBuf),
+    #[cfg(target_os = "linux")]
+    Journald(String),
+    #[cfg(feature = "syslog")]
+    Syslog {
+        facility: libc::c_int,
+        ident: String,
+    },
+    Keep,
+}
+
+impl StdioImpl {
+    fn target(&self) -> StreamTarget {
+        match self {
+            StdioImpl::Devnull => StreamTarget::Devnull,
+            StdioImpl::RedirectToFile(file) => StreamTarget::File(file.as_raw_fd()),
+            StdioImpl::RedirectToPath(path) => StreamTarget::Path(path.clone()),
+            StdioImpl::AppendToPath(path) => StreamTarget::AppendToPath(path.clone()),
+            StdioImpl::MergeWithStdout => StreamTarget::Stdout,
+            StdioImpl::Decorated(file, prefix) => {
+                StreamTarget::Decorated(file.as_raw_fd(), *prefix)
+            }
+            StdioImpl::Tee(file) => StreamTarget::Tee(file.as_raw_fd()),
+            StdioImpl::Piped => StreamTarget::Piped,
+            StdioImpl::Logger(path) => StreamTarget::Logger(path.clone()),
+            #[cfg(target_os = "linux")]
+            StdioImpl::Journald(identifier) => StreamTarget::Journald(identifier.clone()),
+            #[cfg(feature = "syslog")]
+            StdioImpl::Syslog { facility, ident } => StreamTarget::Syslog {
+                facility: *facility,
+                ident: ident.clone(),
+            },
+            StdioImpl::Keep => StreamTarget::Keep,
+        }
+    }
+}
+
+/// Description of the daemonization steps, see `Daemonize::plan`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct Plan {
+    /// Resolved user id, absent if the user is not changed.
+    pub uid: Option<libc::uid_t>,
+    /// Resolved group id, absent if the group is not changed.
+    pub gid: Option<libc::gid_t>,
+    /// Working directory of the daemon, absent if it's kept.
+    pub working_directory: Option<PathBuf>,
+    /// Absolute path of the pid file.
+    pub pid_file: Option<PathBuf>,
+    /// New root directory.
+    pub root: Option<PathBuf>,
+    pub stdin: StreamTarget,
+    pub stdout: StreamTarget,
+    pub stderr: StreamTarget,
+    /// Daemonization steps in order of execution, some stages may appear twice.
+    pub steps: Vec<Stage>,
 }
 
 /// Daemonization process outcome. Can be matched to check is it a parent process or a child
 /// process.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Outcome<T> {
-    Parent(Result<Parent, Error>),
-    Child(Result<Child<T>, Error>),
+pub enum Outcome<T, M = ()> {
+    Parent(Result<Parent<M>, Error>),
+    Child(Result<Child<T, M>, Error>),
 }
 
-impl<T> Outcome<T> {
+impl<T, M> Outcome<T, M> {
     pub fn is_parent(&self) -> bool {
         match self {
             Outcome::Parent(_) => true,
@@ -204,6 +1021,10 @@ impl<T> Outcome<T> {
     }
 }
 
+type ErrorHook = Box<dyn FnOnce(Stage, &Error)>;
+type ReapHook = Box<dyn FnMut(libc::pid_t, std::process::ExitStatus) + Send>;
+type PrivilegedAction<T> = Box<dyn FnOnce(&PrivilegedContext) -> Result<T, Error>>;
+
 /// Daemonization options.
 ///
 /// Fork the process in the background, disassociate from its process group and the control terminal.
@@ -219,34 +1040,173 @@ impl<T> Outcome<T> {
 ///   * change the pid-file ownership to provided user (and/or) group;
 ///   * execute any provided action just before dropping privileges.
 ///
-pub struct Daemonize<T> {
-    directory: PathBuf,
+pub struct Daemonize<T, M = ()> {
+    directory: Option<PathBuf>,
+    directory_user_home: bool,
+    create_directory: Option<u32>,
+    chown_directory: bool,
     pid_file: Option<PathBuf>,
+    pid_file_fd: Option<OwnedFd>,
+    pid_file_write: WriteStage,
+    pid_file_start_time: bool,
+    pid_file_format: PidFileFormat,
+    pid_file_contents: Option<Box<dyn Fn(libc::pid_t) -> String>>,
+    atomic_pid_file: bool,
+    pid_file_lock: LockStyle,
+    pid_file_directory: bool,
     chown_pid_file: bool,
+    chown_streams: bool,
+    instance: Option<String>,
     user: Option<User>,
     group: Option<Group>,
-    umask: Mask,
-    root: Option<PathBuf>,
-    privileged_action: Box<dyn FnOnce() -> T>,
+    privilege_drop: PrivilegeDrop,
+    verify_privilege_drop: bool,
+    no_new_privileges: bool,
+    process_name: Option<String>,
+    nice: Option<libc::c_int>,
+    rlimits: Vec<(Resource, libc::rlim_t, libc::rlim_t)>,
+    core_dumps: Option<CoreDumps>,
+    lock_memory: Option<(MlockFlags, bool)>,
+    reset_signals: bool,
+    close_fds: bool,
+    keep_fds: Vec<RawFd>,
+    inherit_fds: Vec<RawFd>,
+    keep_ignored_signals: Vec<libc::c_int>,
+    ignore_sigpipe: bool,
+    clear_environment: Option<Vec<String>>,
+    envs: Vec<(OsString, OsString)>,
+    set_user_env: bool,
+    #[cfg(target_os = "linux")]
+    single_instance: Option<InstanceGuard>,
+    #[cfg(target_os = "linux")]
+    parent_death_signal: Option<Signal>,
+    #[cfg(target_os = "linux")]
+    io_priority: Option<(IoClass, u8)>,
+    #[cfg(target_os = "linux")]
+    oom_score_adj: Option<i16>,
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    subreaper: bool,
+    #[cfg(target_os = "linux")]
+    namespaces: Namespaces,
+    #[cfg(target_os = "linux")]
+    bind_mounts: Vec<namespaces::BindMount>,
+    #[cfg(target_os = "linux")]
+    uid_map: Vec<namespaces::IdMap>,
+    #[cfg(target_os = "linux")]
+    gid_map: Vec<namespaces::IdMap>,
+    #[cfg(target_os = "linux")]
+    landlock_read: Vec<PathBuf>,
+    #[cfg(target_os = "linux")]
+    landlock_write: Vec<PathBuf>,
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    solaris_privileges: Option<Vec<String>>,
+    umask: Option<Mask>,
+    root: Option<RootImpl>,
+    root_devnull: bool,
+    root_directory: PathBuf,
+    /// Taken when the type of the result changes, so it's always present in a built daemonizer.
+    privileged_action: Option<PrivilegedAction<T>>,
+    on_error: Option<ErrorHook>,
+    reap_children: Option<ReapHook>,
+    pre_fork_action: Option<Box<dyn FnOnce() -> Result<(), BoxError>>>,
+    post_fork_action: Option<Box<dyn FnMut(Forked)>>,
+    steps: Vec<(After, Box<dyn Step>)>,
     stdin: Stdio,
     stdout: Stdio,
     stderr: Stdio,
+    reopen_signals: Vec<Signal>,
+    handle_signals: Vec<Signal>,
+    cleanup_signals: Vec<Signal>,
+    control_socket: Option<PathBuf>,
+    foreground: bool,
+    detach_session: bool,
+    auto_detect_supervisor: bool,
+    init_policy: InitPolicy,
+    ready_timeout: Option<Duration>,
+    relay_errors: bool,
+    timeout: Option<Duration>,
+    preflight: bool,
+    #[cfg(feature = "channel")]
+    channel: bool,
+    message: PhantomData<fn(M) -> M>,
 }
 
-impl<T> fmt::Debug for Daemonize<T> {
+impl<T, M> fmt::Debug for Daemonize<T, M> {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt.debug_struct("Daemonize")
+        let mut debug = fmt.debug_struct("Daemonize");
+        debug
             .field("directory", &self.directory)
+            .field("directory_user_home", &self.directory_user_home)
+            .field("create_directory", &self.create_directory)
+            .field("chown_directory", &self.chown_directory)
             .field("pid_file", &self.pid_file)
+            .field("pid_file_fd", &self.pid_file_fd)
+            .field("pid_file_write", &self.pid_file_write)
+            .field("pid_file_start_time", &self.pid_file_start_time)
+            .field("pid_file_format", &self.pid_file_format)
+            .field("atomic_pid_file", &self.atomic_pid_file)
+            .field("pid_file_lock", &self.pid_file_lock)
+            .field("pid_file_directory", &self.pid_file_directory)
             .field("chown_pid_file", &self.chown_pid_file)
+            .field("chown_streams", &self.chown_streams)
+            .field("instance", &self.instance)
             .field("user", &self.user)
             .field("group", &self.group)
+            .field("privilege_drop", &self.privilege_drop)
+            .field("verify_privilege_drop", &self.verify_privilege_drop)
+            .field("no_new_privileges", &self.no_new_privileges)
+            .field("process_name", &self.process_name)
+            .field("nice", &self.nice)
+            .field("rlimits", &self.rlimits)
+            .field("core_dumps", &self.core_dumps)
+            .field("lock_memory", &self.lock_memory)
+            .field("reset_signals", &self.reset_signals)
+            .field("close_fds", &self.close_fds)
+            .field("keep_fds", &self.keep_fds)
+            .field("inherit_fds", &self.inherit_fds)
+            .field("keep_ignored_signals", &self.keep_ignored_signals)
+            .field("ignore_sigpipe", &self.ignore_sigpipe)
+            .field("clear_environment", &self.clear_environment)
+            .field("envs", &self.envs)
+            .field("set_user_env", &self.set_user_env)
             .field("umask", &self.umask)
             .field("root", &self.root)
+            .field("root_devnull", &self.root_devnull)
+            .field("root_directory", &self.root_directory)
             .field("stdin", &self.stdin)
             .field("stdout", &self.stdout)
             .field("stderr", &self.stderr)
-            .finish()
+            .field("reopen_signals", &self.reopen_signals)
+            .field("handle_signals", &self.handle_signals)
+            .field("cleanup_signals", &self.cleanup_signals)
+            .field("control_socket", &self.control_socket)
+            .field("foreground", &self.foreground)
+            .field("detach_session", &self.detach_session)
+            .field("auto_detect_supervisor", &self.auto_detect_supervisor)
+            .field("init_policy", &self.init_policy)
+            .field("ready_timeout", &self.ready_timeout)
+            .field("relay_errors", &self.relay_errors)
+            .field("timeout", &self.timeout)
+            .field("preflight", &self.preflight);
+        #[cfg(feature = "channel")]
+        debug.field("channel", &self.channel);
+        #[cfg(target_os = "linux")]
+        debug
+            .field("single_instance", &self.single_instance)
+            .field("parent_death_signal", &self.parent_death_signal)
+            .field("io_priority", &self.io_priority)
+            .field("oom_score_adj", &self.oom_score_adj)
+            .field("namespaces", &self.namespaces)
+            .field("bind_mounts", &self.bind_mounts)
+            .field("uid_map", &self.uid_map)
+            .field("gid_map", &self.gid_map)
+            .field("landlock_read", &self.landlock_read)
+            .field("landlock_write", &self.landlock_write);
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        debug.field("subreaper", &self.subreaper);
+        #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+        debug.field("solaris_privileges", &self.solaris_privileges);
+        debug.finish()
     }
 }
 
@@ -259,178 +1219,1975 @@ impl Default for Daemonize<()> {
 impl Daemonize<()> {
     pub fn new() -> Self {
         Daemonize {
-            directory: Path::new("/").to_owned(),
+            directory: Some(Path::new("/").to_owned()),
+            directory_user_home: false,
+            create_directory: None,
+            chown_directory: false,
             pid_file: None,
+            pid_file_fd: None,
+            pid_file_write: WriteStage::Last,
+            pid_file_start_time: false,
+            pid_file_format: PidFileFormat::Plain,
+            pid_file_contents: None,
+            atomic_pid_file: false,
+            pid_file_lock: LockStyle::Flock,
+            pid_file_directory: false,
             chown_pid_file: false,
+            chown_streams: false,
+            instance: None,
             user: None,
             group: None,
-            umask: 0o027.into(),
-            privileged_action: Box::new(|| ()),
+            privilege_drop: PrivilegeDrop::Permanent,
+            verify_privilege_drop: false,
+            no_new_privileges: false,
+            process_name: None,
+            nice: None,
+            rlimits: Vec::new(),
+            core_dumps: None,
+            lock_memory: None,
+            reset_signals: false,
+            close_fds: false,
+            keep_fds: Vec::new(),
+            inherit_fds: Vec::new(),
+            keep_ignored_signals: Vec::new(),
+            ignore_sigpipe: false,
+            clear_environment: None,
+            envs: Vec::new(),
+            set_user_env: false,
+            #[cfg(target_os = "linux")]
+            single_instance: None,
+            #[cfg(target_os = "linux")]
+            parent_death_signal: None,
+            #[cfg(target_os = "linux")]
+            io_priority: None,
+            #[cfg(target_os = "linux")]
+            oom_score_adj: None,
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            subreaper: false,
+            #[cfg(target_os = "linux")]
+            namespaces: Namespaces::empty(),
+            #[cfg(target_os = "linux")]
+            bind_mounts: Vec::new(),
+            #[cfg(target_os = "linux")]
+            uid_map: Vec::new(),
+            #[cfg(target_os = "linux")]
+            gid_map: Vec::new(),
+            #[cfg(target_os = "linux")]
+            landlock_read: Vec::new(),
+            #[cfg(target_os = "linux")]
+            landlock_write: Vec::new(),
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            solaris_privileges: None,
+            umask: Some(Mask::GROUP_WRITE | Mask::OTHERS_ALL),
+            privileged_action: Some(Box::new(|_| Ok(()))),
+            on_error: None,
+            reap_children: None,
+            pre_fork_action: None,
+            post_fork_action: None,
+            steps: Vec::new(),
             root: None,
+            root_devnull: false,
+            root_directory: Path::new("/").to_owned(),
             stdin: Stdio::devnull(),
             stdout: Stdio::devnull(),
             stderr: Stdio::devnull(),
+            reopen_signals: Vec::new(),
+            handle_signals: Vec::new(),
+            cleanup_signals: Vec::new(),
+            control_socket: None,
+            foreground: false,
+            detach_session: true,
+            auto_detect_supervisor: false,
+            init_policy: InitPolicy::Refuse,
+            ready_timeout: None,
+            relay_errors: true,
+            timeout: None,
+            preflight: false,
+            #[cfg(feature = "channel")]
+            channel: false,
+            message: PhantomData,
+        }
+    }
+
+    /// Create a daemonizer with `daemon(3)` semantics: the working directory is changed to `/`
+    /// unless `nochdir` is true, standard streams are redirected to `/dev/null` unless `noclose`
+    /// is true, umask is kept. Can be customized further as any other daemonizer.
+    pub fn simple(nochdir: bool, noclose: bool) -> Self {
+        let mut daemonize = Self::new().keep_umask();
+        if nochdir {
+            daemonize = daemonize.keep_working_directory();
         }
+        if noclose {
+            daemonize = daemonize
+                .stdin(Stdio::keep())
+                .stdout(Stdio::keep())
+                .stderr(Stdio::keep());
+        }
+        daemonize
     }
 }
 
-impl<T> Daemonize<T> {
-    /// Create pid-file at `path`, lock it exclusive and write daemon pid.
+impl<T, M> Daemonize<T, M> {
+    /// Create pid-file at `path`, lock it exclusive and write daemon pid. Overrides
+    /// `pid_file_default`, so missing directories of `path` are not created.
     pub fn pid_file<F: AsRef<Path>>(mut self, path: F) -> Self {
         self.pid_file = Some(path.as_ref().to_owned());
+        self.pid_file_directory = false;
         self
     }
 
-    /// If `chown` is true, daemonize will change the pid-file ownership, if user or group are provided
-    pub fn chown_pid_file(mut self, chown: bool) -> Self {
-        self.chown_pid_file = chown;
+    /// Create pid-file at `$XDG_RUNTIME_DIR/<name>.pid` if the process is unprivileged, or at
+    /// `/run/<name>.pid` if it runs as root. Missing directories are created by the daemon.
+    pub fn pid_file_default(mut self, name: &str) -> Self {
+        let directory = match std::env::var_os("XDG_RUNTIME_DIR") {
+            _ if unsafe { libc::geteuid() } == 0 => PathBuf::from("/run"),
+            Some(directory) => PathBuf::from(directory),
+            None => PathBuf::from(format!("/run/user/{}", unsafe { libc::geteuid() })),
+        };
+        self.pid_file = Some(directory.join(format!("{}.pid", name)));
+        self.pid_file_directory = true;
         self
     }
 
-    /// Change working directory to `path` or `/` by default.
-    pub fn working_directory<F: AsRef<Path>>(mut self, path: F) -> Self {
-        self.directory = path.as_ref().to_owned();
+    /// Adopt an already opened and locked pid file, e.g. opened by a supervisor in a directory
+    /// the daemon can't reach after the root change. Daemonize only truncates it and writes the
+    /// PID of the daemon at `pid_file_write` stage, `atomic_pid_file` and `pid_file_lock` are
+    /// ignored. The path set by `pid_file` is still used to remove the pid file.
+    pub fn pid_file_fd<F: Into<OwnedFd>>(mut self, fd: F) -> Self {
+        self.pid_file_fd = Some(fd.into());
         self
     }
 
-    /// Drop privileges to `user`.
-    pub fn user<U: Into<User>>(mut self, user: U) -> Self {
-        self.user = Some(user.into());
+    /// Choose when the pid file is created and written relative to the root change and
+    /// privileges drop, `WriteStage::Last` by default.
+    pub fn pid_file_write(mut self, stage: WriteStage) -> Self {
+        self.pid_file_write = stage;
         self
     }
 
-    /// Drop privileges to `group`.
-    pub fn group<G: Into<Group>>(mut self, group: G) -> Self {
-        self.group = Some(group.into());
+    /// If `record` is true, write the start time of the daemon on the second line of the pid
+    /// file, so `is_process_running` can tell the daemon from a process which reused its PID.
+    /// Only Linux provides start times, the pid file contains just the PID elsewhere.
+    pub fn pid_file_start_time(mut self, record: bool) -> Self {
+        self.pid_file_start_time = record;
         self
     }
 
-    /// Change umask to `mask` or `0o027` by default.
-    pub fn umask<M: Into<Mask>>(mut self, mask: M) -> Self {
-        self.umask = mask.into();
+    /// Format of the pid file, `PidFileFormat::Plain` by default. Use `PidFileInfo::parse` to
+    /// read it back.
+    pub fn pid_file_format(mut self, format: PidFileFormat) -> Self {
+        self.pid_file_format = format;
         self
     }
 
-    /// Change root to `path`
-    pub fn chroot<F: AsRef<Path>>(mut self, path: F) -> Self {
-        self.root = Some(path.as_ref().to_owned());
+    /// If `atomic` is true, write the pid file to a temporary file which is then renamed, so
+    /// readers never see an empty or partially written pid file. The pid file is written as soon
+    /// as it's created, since the directory may be inaccessible later.
+    pub fn atomic_pid_file(mut self, atomic: bool) -> Self {
+        self.atomic_pid_file = atomic;
         self
     }
 
-    /// Execute `action` just before dropping privileges. Most common use case is to open
-    /// listening socket. Result of `action` execution will be returned by `start` method.
-    pub fn privileged_action<N, F: FnOnce() -> N + 'static>(self, action: F) -> Daemonize<N> {
-        let mut new: Daemonize<N> = unsafe { transmute(self) };
-        new.privileged_action = Box::new(action);
-        new
+    /// How the pid file is locked, `LockStyle::Flock` by default.
+    pub fn pid_file_lock(mut self, style: LockStyle) -> Self {
+        self.pid_file_lock = style;
+        self
     }
 
-    /// Configuration for the child process's standard output stream.
-    pub fn stdout<S: Into<Stdio>>(mut self, stdio: S) -> Self {
-        self.stdout = stdio.into();
+    /// Write the result of `contents` called with the PID of the daemon to the pid file instead
+    /// of `pid_file_format`, e.g. the PID without a newline for a legacy supervisor.
+    pub fn pid_file_contents<F: Fn(libc::pid_t) -> String + 'static>(
+        mut self,
+        contents: F,
+    ) -> Self {
+        self.pid_file_contents = Some(Box::new(contents));
         self
     }
 
-    /// Configuration for the child process's standard error stream.
-    pub fn stderr<S: Into<Stdio>>(mut self, stdio: S) -> Self {
-        self.stderr = stdio.into();
+    /// Make sure only one instance of the daemon runs using `guard`, as an alternative or in
+    /// addition to the pid file lock. The guard is acquired right after the pid file is locked,
+    /// daemonization fails if another instance holds it.
+    #[cfg(target_os = "linux")]
+    pub fn single_instance(mut self, guard: InstanceGuard) -> Self {
+        self.single_instance = Some(guard);
         self
     }
-    /// Start daemonization process, terminate parent after first fork, returns privileged action
-    /// result to the child.
-    pub fn start(self) -> Result<T, Error> {
-        match self.execute() {
-            Outcome::Parent(Ok(Parent { first_child_exit_code })) => exit(first_child_exit_code),
-            Outcome::Parent(Err(err)) => Err(err),
-            Outcome::Child(Ok(child)) => Ok(child.privileged_action_result),
-            Outcome::Child(Err(err)) => Err(err),
-        }
+
+    /// If `chown` is true, daemonize will change the pid-file ownership, if user or group are provided
+    pub fn chown_pid_file(mut self, chown: bool) -> Self {
+        self.chown_pid_file = chown;
+        self
     }
 
-    /// Execute daemonization process, don't terminate parent after first fork.
-    pub fn execute(self) -> Outcome<T> {
-        unsafe {
-            match perform_fork() {
+    /// Name of the daemon instance, substituted for `%i` in the pid file path and the paths of
+    /// standard streams, `%%` stands for `%`. Paths are expanded when daemonization starts.
+    pub fn instance(mut self, instance: &str) -> Self {
+        self.instance = Some(instance.to_owned());
+        self
+    }
+
+    /// If `chown` is true, files stdout and stderr are redirected to are owned by `user` and
+    /// `group`, so the daemon can reopen them after privileges are dropped, e.g. on a signal set
+    /// with `reopen_streams_on`. Paths are created if missing and changed after the root change.
+    /// They must be regular files and are not followed if they're symlinks, the files opened to
+    /// change them are the ones the streams are redirected to.
+    pub fn chown_streams(mut self, chown: bool) -> Self {
+        self.chown_streams = chown;
+        self
+    }
+
+    /// Change working directory to `path` or `/` by default.
+    pub fn working_directory<F: AsRef<Path>>(mut self, path: F) -> Self {
+        self.directory = Some(path.as_ref().to_owned());
+        self.directory_user_home = false;
+        self
+    }
+
+    /// Change working directory to the home directory of `user`, or of the calling user without
+    /// `user`. The home directory is looked up before the fork, so an unknown user is reported
+    /// to the caller.
+    pub fn working_directory_user_home(mut self) -> Self {
+        self.directory_user_home = true;
+        self
+    }
+
+    /// Create the working directory and missing parents with `mode`, masked by the umask of the
+    /// calling process, if it doesn't exist.
+    pub fn create_working_directory(mut self, mode: u32) -> Self {
+        self.create_directory = Some(mode);
+        self
+    }
+
+    /// If `chown` is true, the working directory created by `create_working_directory` is owned
+    /// by `user` and `group`. It's changed before privileges are dropped. Only the working
+    /// directory itself is changed, missing parents stay owned by the calling user.
+    pub fn chown_working_directory(mut self, chown: bool) -> Self {
+        self.chown_directory = chown;
+        self
+    }
+
+    /// Keep the working directory of the calling process instead of changing it.
+    pub fn keep_working_directory(mut self) -> Self {
+        self.directory = None;
+        self.directory_user_home = false;
+        self
+    }
+
+    /// Drop privileges to `user`. Nothing is done if the process already runs as `user`.
+    pub fn user<U: Into<User>>(mut self, user: U) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Drop privileges to `group`. Nothing is done if the process already runs as `group`.
+    pub fn group<G: Into<Group>>(mut self, group: G) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Choose how privileges are dropped, `PrivilegeDrop::Permanent` by default.
+    pub fn privilege_drop_mode(mut self, mode: PrivilegeDrop) -> Self {
+        self.privilege_drop = mode;
+        self
+    }
+
+    /// If `verify` is true, daemonize will check that root privileges can't be regained after they
+    /// were permanently dropped to `user`. Has no effect with `PrivilegeDrop::Temporary`.
+    pub fn verify_privilege_drop(mut self, verify: bool) -> Self {
+        self.verify_privilege_drop = verify;
+        self
+    }
+
+    /// If `enable` is true, close all inherited descriptors except the standard streams right
+    /// after the forks, so the daemon doesn't leak descriptors of the launching process.
+    /// Descriptors used by daemonize itself are kept, others have to be listed in `keep_fds`,
+    /// e.g. sockets passed by a supervisor, a log file opened before daemonization or any
+    /// descriptor owned by a value which is still used or dropped in the daemon.
+    ///
+    /// # Safety
+    ///
+    /// Descriptors are closed behind the back of their owners. Every descriptor owned by a value
+    /// which outlives the forks, e.g. a `File`, an `OwnedFd` or a descriptor kept by a library,
+    /// must be listed in `keep_fds`, otherwise using or dropping the value in the daemon acts on
+    /// a closed or reused descriptor.
+    pub unsafe fn close_fds(mut self, enable: bool) -> Self {
+        self.close_fds = enable;
+        self
+    }
+
+    /// Keep `fds` open when `close_fds` is enabled, can be called several times.
+    pub fn keep_fds(mut self, fds: &[RawFd]) -> Self {
+        self.keep_fds.extend_from_slice(fds);
+        self
+    }
+
+    /// Hand `fd` over to the daemon, e.g. a privileged resource opened by a wrapper: it's kept by
+    /// `close_fds` and `FD_CLOEXEC` is cleared, so it survives `exec` too. A descriptor of a
+    /// standard stream is duplicated before the streams are redirected, the resulting numbers are
+    /// in `Child::inherited_fds` and `Daemonized::inherited_fds`.
+    pub fn inherit_fd(mut self, fd: RawFd) -> Self {
+        self.inherit_fds.push(fd);
+        self
+    }
+
+    /// Remove all environment variables of the daemon except `keep`, e.g. `&["PATH", "TZ"]`, right
+    /// after the forks, so it doesn't depend on the environment of the launching shell. If `PATH`
+    /// isn't kept or isn't set, it's set to a default search path of system directories. In the
+    /// foreground mode there are no forks, so the environment of the calling process is cleared.
+    /// Malformed entries, e.g. with an empty name, can't be removed and are kept.
+    pub fn clear_environment(mut self, keep: &[&str]) -> Self {
+        self.clear_environment = Some(keep.iter().map(|name| name.to_string()).collect());
+        self
+    }
+
+    /// Set the environment variable `key` to `value` in the daemon right after the forks, after
+    /// `clear_environment`, so the environment of the launching process is left intact. In the
+    /// foreground mode there are no forks and the variable is set in the calling process.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, value: V) -> Self {
+        self.envs.push((key.as_ref().to_owned(), value.as_ref().to_owned()));
+        self
+    }
+
+    /// Set several environment variables in the daemon, see `env`.
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, value) in vars {
+            self = self.env(key, value);
+        }
+        self
+    }
+
+    /// If `enable` is true, set `HOME`, `USER`, `LOGNAME` and `SHELL` from the passwd entry of
+    /// `user`, so programs run by the daemon don't use the home of the launching user. Applied
+    /// before the privileged action and the root change, has no effect without `user`. Variables
+    /// set with `env` take precedence.
+    pub fn set_user_env(mut self, enable: bool) -> Self {
+        self.set_user_env = enable;
+        self
+    }
+
+    /// If `reset` is true, restore default dispositions of all signals and unblock them right
+    /// after the forks, so the daemon doesn't inherit the signal state of the launching shell or
+    /// supervisor. Signals set with `keep_ignored_signals` stay ignored. Note that `SIGPIPE`,
+    /// ignored by the Rust runtime, is reset as well, while `SIGSEGV` and `SIGBUS` keep the
+    /// handlers the runtime uses to report stack overflows.
+    pub fn reset_signals(mut self, reset: bool) -> Self {
+        self.reset_signals = reset;
+        self
+    }
+
+    /// Signals which stay ignored if they're ignored when `reset_signals` resets dispositions,
+    /// e.g. `libc::SIGHUP` for a daemon started with `nohup`.
+    pub fn keep_ignored_signals(mut self, signals: &[libc::c_int]) -> Self {
+        self.keep_ignored_signals.extend_from_slice(signals);
+        self
+    }
+
+    /// If `ignore` is true, ignore `SIGPIPE` in the daemon after the forks, so writing to a
+    /// closed socket or pipe fails with `EPIPE` instead of killing the daemon. Applied after
+    /// `reset_signals`.
+    pub fn ignore_sigpipe(mut self, ignore: bool) -> Self {
+        self.ignore_sigpipe = ignore;
+        self
+    }
+
+    /// Send `signal` to the daemon when its parent exits, using `PR_SET_PDEATHSIG`. In the
+    /// foreground mode the parent is the process which started the daemon. Otherwise the daemon
+    /// is reparented when the first child exits, so this is useful only if the process calling
+    /// `execute` is a subreaper (`PR_SET_CHILD_SUBREAPER`) and stays alive, the signal is sent
+    /// when it exits. If the parent exits before the signal is set, it's sent right away. The
+    /// signal is set after the privileges drop, which clears it, and waiting for the first child
+    /// to exit requires `pidfd_open` (Linux 5.3).
+    #[cfg(target_os = "linux")]
+    pub fn parent_death_signal(mut self, signal: Signal) -> Self {
+        self.parent_death_signal = Some(signal);
+        self
+    }
+
+    /// If `enable` is true, the daemon becomes a subreaper (`PR_SET_CHILD_SUBREAPER` on Linux,
+    /// `PROC_REAP_ACQUIRE` on FreeBSD), so orphaned descendants, e.g. workers which daemonize
+    /// themselves, are reparented to the daemon instead of init. The daemon is responsible for
+    /// waiting them.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    pub fn subreaper(mut self, enable: bool) -> Self {
+        self.subreaper = enable;
+        self
+    }
+
+    /// If `enable` is true, daemonize will forbid the daemon and its descendants to gain new
+    /// privileges through `execve`, e.g. with setuid binaries or file capabilities. Only
+    /// supported on Linux, does nothing on other platforms.
+    pub fn no_new_privileges(mut self, enable: bool) -> Self {
+        self.no_new_privileges = enable;
+        self
+    }
+
+    /// Set the name of the daemon shown by `ps` and `top`, with `PR_SET_NAME` on Linux, where it
+    /// is truncated to 15 bytes, and `setproctitle` on the BSDs. Does nothing on other platforms.
+    pub fn process_name(mut self, name: &str) -> Self {
+        self.process_name = Some(name.to_owned());
+        self
+    }
+
+    /// Set the nice value of the daemon with `setpriority`, from -20, the highest priority, to 19.
+    /// Applied just after the privileged action, so negative values are allowed for root.
+    pub fn nice(mut self, nice: libc::c_int) -> Self {
+        self.nice = Some(nice);
+        self
+    }
+
+    /// Set the `soft` and `hard` limits of `resource` with `setrlimit`, `libc::RLIM_INFINITY`
+    /// means no limit. Can be called several times, limits are applied in order along with
+    /// `nice`, so hard limits can be raised by root before privileges are dropped.
+    pub fn rlimit(mut self, resource: Resource, soft: libc::rlim_t, hard: libc::rlim_t) -> Self {
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// If `disable` is true, set the core file size limit to zero and, on Linux, clear the
+    /// dumpable flag after privileges are dropped, so secrets never end up in core files.
+    pub fn disable_core_dumps(mut self, disable: bool) -> Self {
+        self.core_dumps = if disable {
+            Some(CoreDumps::Disabled)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Raise the core file size limit to the hard limit and change the working directory to
+    /// `directory` after privileges are dropped, since core files are written to the working
+    /// directory unless the system sets an absolute core pattern. On Linux the dumpable flag,
+    /// cleared by the user change, is set again.
+    pub fn allow_core_dumps<F: AsRef<Path>>(mut self, directory: F) -> Self {
+        self.core_dumps = Some(CoreDumps::Allowed(directory.as_ref().to_owned()));
+        self
+    }
+
+    /// Lock the memory of the daemon with `mlockall`, so secrets are never swapped out. Applied
+    /// after `rlimit`, before privileges are dropped, since it usually requires
+    /// `CAP_IPC_LOCK` or a large enough `Resource::MemLock` limit. Fails daemonization on error.
+    pub fn lock_memory(mut self, flags: MlockFlags) -> Self {
+        self.lock_memory = Some((flags, true));
+        self
+    }
+
+    /// Same as `lock_memory`, but an error is logged and daemonization continues.
+    pub fn try_lock_memory(mut self, flags: MlockFlags) -> Self {
+        self.lock_memory = Some((flags, false));
+        self
+    }
+
+    /// Set the I/O scheduling class and the priority `level` within it, from 0, the highest, to 7,
+    /// with `ioprio_set`. Applied along with `nice`.
+    #[cfg(target_os = "linux")]
+    pub fn io_priority(mut self, class: IoClass, level: u8) -> Self {
+        self.io_priority = Some((class, level));
+        self
+    }
+
+    /// Write `adjustment` to /proc/self/oom_score_adj, from -1000, which protects the daemon
+    /// from the OOM killer, to 1000, which makes it the first candidate. Applied along with
+    /// `nice`, so negative values are allowed for root.
+    #[cfg(target_os = "linux")]
+    pub fn oom_score_adj(mut self, adjustment: i16) -> Self {
+        self.oom_score_adj = Some(adjustment);
+        self
+    }
+
+    /// If `enable` is true, daemonize will move the daemon into a new user namespace just after
+    /// privileged action execution. Unless `uid_map` or `gid_map` are provided, the current user
+    /// and group are mapped to root inside the namespace, so `chroot` and other privileged
+    /// operations become available to unprivileged users.
+    #[cfg(target_os = "linux")]
+    pub fn user_namespace(mut self, enable: bool) -> Self {
+        self.namespaces = if enable {
+            self.namespaces | Namespaces::USER
+        } else {
+            self.namespaces.without(Namespaces::USER)
+        };
+        self
+    }
+
+    /// Move the daemon into new `namespaces` just after privileged action execution, i.e. before
+    /// `chroot` and privileges drop, replacing namespaces set before. Unless `Namespaces::USER` is
+    /// requested, creating namespaces requires root privileges.
+    #[cfg(target_os = "linux")]
+    pub fn namespaces(mut self, namespaces: Namespaces) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Map `count` user ids starting from `outside` to ids starting from `inside` in the user
+    /// namespace created with `user_namespace`, may be called several times to map several
+    /// ranges. Mappings are written before privileges are dropped, either directly if process
+    /// runs as root, or with `newuidmap` otherwise.
+    #[cfg(target_os = "linux")]
+    pub fn uid_map(mut self, inside: u32, outside: u32, count: u32) -> Self {
+        self.uid_map.push(namespaces::IdMap {
+            inside,
+            outside,
+            count,
+        });
+        self
+    }
+
+    /// Map `count` group ids starting from `outside` to ids starting from `inside` in the user
+    /// namespace created with `user_namespace`, see `uid_map`.
+    #[cfg(target_os = "linux")]
+    pub fn gid_map(mut self, inside: u32, outside: u32, count: u32) -> Self {
+        self.gid_map.push(namespaces::IdMap {
+            inside,
+            outside,
+            count,
+        });
+        self
+    }
+
+    /// Bind mount `host_path` to `jail_path` before root change, e.g. to make `/etc/resolv.conf`
+    /// or `/dev/urandom` available in the jail. `jail_path` is interpreted relative to the new
+    /// root and created if missing. May be called several times, already performed mounts are
+    /// unmounted if any of them fails. Implies `Namespaces::MOUNT`.
+    #[cfg(target_os = "linux")]
+    pub fn jail_bind_mount<F: AsRef<Path>, J: AsRef<Path>>(
+        mut self,
+        host_path: F,
+        jail_path: J,
+        read_only: bool,
+    ) -> Self {
+        self.bind_mounts.push(namespaces::BindMount {
+            source: host_path.as_ref().to_owned(),
+            target: jail_path.as_ref().to_owned(),
+            read_only,
+        });
+        self
+    }
+
+    /// Allow reading from `paths` after daemonization and forbid access to every path which is not
+    /// allowed with `landlock_read` or `landlock_write`. Paths are resolved after `chroot`.
+    /// Requires Linux with Landlock enabled, also sets `no_new_privileges`.
+    #[cfg(target_os = "linux")]
+    pub fn landlock_read<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.landlock_read
+            .extend(paths.into_iter().map(|path| path.as_ref().to_owned()));
+        self
+    }
+
+    /// Allow reading from and writing to `paths` after daemonization, see `landlock_read`.
+    #[cfg(target_os = "linux")]
+    pub fn landlock_write<I, P>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        self.landlock_write
+            .extend(paths.into_iter().map(|path| path.as_ref().to_owned()));
+        self
+    }
+
+    /// Limit permitted, effective and inheritable privilege sets to `privileges` (e.g. `"basic"`,
+    /// `"net_privaddr"`) when privileges are dropped.
+    #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+    pub fn solaris_privileges<I, S>(mut self, privileges: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.solaris_privileges = Some(
+            privileges
+                .into_iter()
+                .map(|privilege| privilege.as_ref().to_owned())
+                .collect(),
+        );
+        self
+    }
+
+    /// Change umask to `mask` or `0o027` by default. A raw `mode_t` is accepted as is, prefer
+    /// `Mask::from_octal` which rejects decimal literals like `777`.
+    pub fn umask<U: Into<Mask>>(mut self, mask: U) -> Self {
+        self.umask = Some(mask.into());
+        self
+    }
+
+    /// Keep the umask of the calling process instead of changing it.
+    pub fn keep_umask(mut self) -> Self {
+        self.umask = None;
+        self
+    }
+
+    /// If `enable` is true, daemonize performs all the steps in the calling process, except
+    /// forking, session detaching and standard streams redirection, e.g. to run the same binary
+    /// under systemd or in a container. `execute` always returns `Outcome::Child` in this mode.
+    pub fn foreground(mut self, enable: bool) -> Self {
+        self.foreground = enable;
+        self
+    }
+
+    /// If `enable` is true, daemonize switches to the foreground mode when the process is started
+    /// by a supervisor: systemd (`INVOCATION_ID` or `NOTIFY_SOCKET` are set) or a container
+    /// runtime (the process is PID 1).
+    pub fn auto_detect_supervisor(mut self, enable: bool) -> Self {
+        self.auto_detect_supervisor = enable;
+        self
+    }
+
+    /// Set what to do if the process runs as PID 1, `InitPolicy::Refuse` by default. Nothing is
+    /// checked in the foreground mode.
+    pub fn init_policy(mut self, policy: InitPolicy) -> Self {
+        self.init_policy = policy;
+        self
+    }
+
+    /// Make the parent wait up to `timeout` until the daemon notifies readiness with
+    /// `Child::ready`, the parent fails if the daemon doesn't, e.g. because of an error. With
+    /// `start` the daemon is ready as soon as daemonization succeeds. Ignored in the foreground
+    /// mode.
+    pub fn wait_ready(mut self, timeout: Duration) -> Self {
+        self.ready_timeout = Some(timeout);
+        self
+    }
+
+    /// If `enable` is true, errors which happen in the daemon after the fork, e.g. while
+    /// changing the user or the root directory, are reported to the parent, so `start` returns
+    /// them instead of exiting with success. The parent waits until daemonization finishes.
+    /// Enabled by default.
+    pub fn relay_errors(mut self, enable: bool) -> Self {
+        self.relay_errors = enable;
+        self
+    }
+
+    /// Make the parent fail with a timeout error if daemonization doesn't finish in `timeout`,
+    /// e.g. because of a hanging user lookup or pid-file lock on a network file system. The
+    /// parent waits until daemonization finishes like with `relay_errors`, even if errors are not
+    /// relayed, with `wait_ready` the timeout covers readiness too. The first child is killed if it
+    /// hangs, the daemon is not terminated. Ignored in the foreground mode.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run a custom `step` in the daemonization pipeline at the `after` point, e.g. to mount a
+    /// filesystem after the root change. Steps at the same point run in order of insertion.
+    pub fn insert_step<S: Step + 'static>(mut self, after: After, step: S) -> Self {
+        self.steps.push((after, Box::new(step)));
+        self
+    }
+
+    /// If `enable` is true, run `validate` in the original process before the first fork, so
+    /// configuration mistakes like an unknown user or a missing pid file directory are reported
+    /// right away. Ignored in the foreground mode. Disabled by default.
+    pub fn preflight(mut self, enable: bool) -> Self {
+        self.preflight = enable;
+        self
+    }
+
+    /// Execute `hook` in the process which hits a daemonization error, before the error is
+    /// returned, e.g. to log it to syslog while the standard streams are already redirected.
+    /// Errors relayed from the daemon are reported only in the daemon.
+    pub fn on_error<F: FnOnce(Stage, &Error) + 'static>(mut self, hook: F) -> Self {
+        self.on_error = Some(Box::new(hook));
+        self
+    }
+
+    /// Reap terminated children of the daemon in a background thread and call `hook` with the
+    /// PID and the exit status of each, so workers never linger as zombies. Children can't be
+    /// waited with `waitpid` in this mode.
+    pub fn reap_children<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(libc::pid_t, std::process::ExitStatus) + Send + 'static,
+    {
+        self.reap_children = Some(Box::new(hook));
+        self
+    }
+
+    /// Execute `action` in the original process before the first fork, e.g. to check that a port
+    /// is available. An error aborts daemonization and becomes the source of the daemonization
+    /// error.
+    pub fn pre_fork_action<E, F>(mut self, action: F) -> Self
+    where
+        E: Into<BoxError>,
+        F: FnOnce() -> Result<(), E> + 'static,
+    {
+        self.pre_fork_action = Some(Box::new(move || action().map_err(Into::into)));
+        self
+    }
+
+    /// Execute `action` in the new process right after each fork, before any other step, e.g. to
+    /// reseed a random number generator. It's not executed in the foreground mode.
+    pub fn post_fork_action<F: FnMut(Forked) + 'static>(mut self, action: F) -> Self {
+        self.post_fork_action = Some(Box::new(action));
+        self
+    }
+
+    /// Open a channel from the daemon to the parent process, e.g. to report a bound port. The
+    /// daemon gets the sending half with `Child::sender`, the parent gets the receiving half with
+    /// `Parent::receiver`, messages of type `N` are serialized with serde. Ignored in the
+    /// foreground mode.
+    #[cfg(feature = "channel")]
+    pub fn with_channel<N>(self) -> Daemonize<T, N> {
+        let mut new: Daemonize<T, N> = unsafe { transmute(self) };
+        new.channel = true;
+        new
+    }
+
+    /// If `enable` is false, daemonize doesn't create a new session with `setsid`, e.g. if a
+    /// supervisor already started the process in its own session. Enabled by default.
+    pub fn detach_session(mut self, enable: bool) -> Self {
+        self.detach_session = enable;
+        self
+    }
+
+    /// Change root to `path`, working directory is changed to `/` of the new root afterwards, see
+    /// `chroot_working_directory`.
+    pub fn chroot<F: AsRef<Path>>(mut self, path: F) -> Self {
+        self.root = Some(RootImpl::Chroot(path.as_ref().to_owned()));
+        self
+    }
+
+    /// Change root to `new_root` with `pivot_root(2)` instead of `chroot`, the old root is
+    /// temporarily moved to `put_old` directory (relative to `new_root`, created if missing) and
+    /// then unmounted. Unlike `chroot` it can't be escaped. Implies `Namespaces::MOUNT`.
+    #[cfg(target_os = "linux")]
+    pub fn pivot_root<F: AsRef<Path>, O: AsRef<Path>>(mut self, new_root: F, put_old: O) -> Self {
+        self.root = Some(RootImpl::PivotRoot(
+            new_root.as_ref().to_owned(),
+            put_old.as_ref().to_owned(),
+        ));
+        self
+    }
+
+    /// Change working directory to `path` (interpreted relative to the new root) after `chroot` or
+    /// `pivot_root`, `/` by default. `working_directory` is changed before root change, so it is
+    /// not accessible from the jail.
+    pub fn chroot_working_directory<F: AsRef<Path>>(mut self, path: F) -> Self {
+        self.root_directory = path.as_ref().to_owned();
+        self
+    }
+
+    /// If `create` is true, daemonize will create `/dev/null` device node inside the new root if it
+    /// doesn't exist, so the daemon can use it after `chroot` or `pivot_root`. Requires root
+    /// privileges, consider `jail_bind_mount` of `/dev/null` otherwise.
+    pub fn chroot_devnull(mut self, create: bool) -> Self {
+        self.root_devnull = create;
+        self
+    }
+
+    /// Execute `action` just before dropping privileges. Most common use case is to open
+    /// listening socket. Result of `action` execution will be returned by `start` method.
+    pub fn privileged_action<N, F: FnOnce() -> N + 'static>(self, action: F) -> Daemonize<N, M> {
+        self.privileged_action_with_context(move |_| action())
+    }
+
+    /// Same as `privileged_action`, but `action` receives the resolved user and group, the pid
+    /// file and the PID of the daemon, e.g. to change the owner of a socket.
+    pub fn privileged_action_with_context<N, F: FnOnce(&PrivilegedContext) -> N + 'static>(
+        mut self,
+        action: F,
+    ) -> Daemonize<N, M> {
+        self.privileged_action = None;
+        let mut new: Daemonize<N, M> = unsafe { transmute(self) };
+        new.privileged_action = Some(Box::new(move |context| Ok(action(context))));
+        new
+    }
+
+    /// Same as `privileged_action`, but an error of `action` aborts daemonization. The error
+    /// becomes the source of the daemonization error, the parent process gets only its OS error
+    /// code or `EIO`.
+    pub fn try_privileged_action<N, E, F>(mut self, action: F) -> Daemonize<N, M>
+    where
+        E: Into<BoxError>,
+        F: FnOnce() -> Result<N, E> + 'static,
+    {
+        self.privileged_action = None;
+        let mut new: Daemonize<N, M> = unsafe { transmute(self) };
+        new.privileged_action = Some(Box::new(move |_| {
+            action().map_err(|err| Error::from_source(ErrorKind::PrivilegedAction, err))
+        }));
+        new
+    }
+
+    /// Execute `action` right after privileges are dropped, e.g. to check that the daemon user can
+    /// read its configuration. It's a custom step at `After::PrivilegeDrop`, so an error aborts
+    /// daemonization, see `insert_step`.
+    pub fn post_privilege_action<F: FnOnce() -> std::io::Result<()> + 'static>(
+        self,
+        action: F,
+    ) -> Self {
+        let mut action = Some(action);
+        self.insert_step(After::PrivilegeDrop, move || {
+            action.take().map_or(Ok(()), |action| action())
+        })
+    }
+
+    /// Execute `action` after the already registered privileged actions, the result is a tuple of
+    /// their result and the result of `action`, e.g. `((), socket)` for the first call.
+    pub fn and_privileged_action<N, F: FnOnce() -> N + 'static>(
+        mut self,
+        action: F,
+    ) -> Daemonize<(T, N), M>
+    where
+        T: 'static,
+    {
+        let previous = self.privileged_action.take().expect("privileged action is set");
+        let mut new: Daemonize<(T, N), M> = unsafe { transmute(self) };
+        new.privileged_action = Some(Box::new(move |context| {
+            let result = previous(context)?;
+            Ok((result, action()))
+        }));
+        new
+    }
+
+    /// Configuration for the child process's standard input stream.
+    pub fn stdin<S: Into<Stdio>>(mut self, stdio: S) -> Self {
+        self.stdin = stdio.into();
+        self
+    }
+
+    /// Configuration for the child process's standard output stream.
+    pub fn stdout<S: Into<Stdio>>(mut self, stdio: S) -> Self {
+        self.stdout = stdio.into();
+        self
+    }
+
+    /// Configuration for the child process's standard error stream.
+    pub fn stderr<S: Into<Stdio>>(mut self, stdio: S) -> Self {
+        self.stderr = stdio.into();
+        self
+    }
+
+    /// Redirect the child process's standard output stream to `path`, see `Stdio::from_path`.
+    pub fn stdout_path<F: AsRef<Path>>(self, path: F) -> Self {
+        self.stdout(Stdio::from_path(path))
+    }
+
+    /// Redirect the child process's standard error stream to `path`, see `Stdio::from_path`.
+    pub fn stderr_path<F: AsRef<Path>>(self, path: F) -> Self {
+        self.stderr(Stdio::from_path(path))
+    }
+
+    /// Reopen stdout and stderr redirected to paths on `signal`, e.g. to let logrotate move log
+    /// files away. Files are reopened in append mode with the daemon's privileges, streams
+    /// merged with stdout follow it. Can be called several times to handle multiple signals.
+    /// With the `signal-hook` feature, the handler is registered with signal-hook.
+    pub fn reopen_streams_on(mut self, signal: Signal) -> Self {
+        self.reopen_signals.push(signal);
+        self
+    }
+
+    /// Catch `signals` in the daemon and deliver them to `DaemonHandle`, returned in
+    /// `Daemonized::handle` and `Child::handle`. A signal handled both here and by
+    /// `reopen_streams_on` is delivered only here, unless the `signal-hook` feature is enabled:
+    /// then handlers are registered with signal-hook and all of them run, along with the
+    /// application's handlers.
+    pub fn handle_signals(mut self, signals: &[Signal]) -> Self {
+        self.handle_signals.extend_from_slice(signals);
+        self
+    }
+
+    /// Remove the pid file on any of `signals`, e.g. `Signal::Term` sent by a service manager. The
+    /// signal is re-raised with the default action afterwards, so the daemon still terminates.
+    /// The daemon must be able to remove the file, like with `Child::pid_file_guard`.
+    pub fn cleanup_pid_file_on_signals(mut self, signals: &[Signal]) -> Self {
+        self.cleanup_signals.extend_from_slice(signals);
+        self
+    }
+
+    /// Listen on a unix socket at `path` answering `control::Request`s, see `control::request`.
+    /// The socket is created by the daemon after the root change and privileges drop, and
+    /// served by a background thread. Only the daemon user and root may use it. A stale socket
+    /// left by a killed daemon is replaced only if the daemon locks a pid file.
+    pub fn control_socket<F: AsRef<Path>>(mut self, path: F) -> Self {
+        self.control_socket = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Describe what `start` would do, without changing anything. User and group names are
+    /// resolved, so this fails on unknown names.
+    pub fn plan(&self) -> Result<Plan, Error> {
+        let (uid, gid) = self.resolve_ids()?;
+        let working_directory = if self.directory_user_home {
+            Some(unsafe { user_home(uid.unwrap_or_else(|| libc::getuid())) }?)
+        } else {
+            self.directory.clone()
+        };
+        let expanded_pid_file = self
+            .pid_file
+            .as_ref()
+            .map(|path| expand_instance(path, self.instance.as_deref()));
+        let pid_file = match (&expanded_pid_file, &working_directory) {
+            (Some(path), _) if path.is_absolute() => Some(path.clone()),
+            (Some(path), Some(directory)) => Some(directory.join(path)),
+            (Some(path), None) => Some(
+                std::env::current_dir()
+                    .map_err(|_| ErrorKind::ChangeDirectory(errno()))?
+                    .join(path),
+            ),
+            (None, _) => None,
+        };
+        let root = match self.root {
+            Some(RootImpl::Chroot(ref root)) => Some(root.clone()),
+            #[cfg(target_os = "linux")]
+            Some(RootImpl::PivotRoot(ref root, _)) => Some(root.clone()),
+            None => None,
+        };
+        let pid_file = match (&expanded_pid_file, &root) {
+            (Some(path), Some(root)) if self.pid_file_write == WriteStage::AfterPrivilegeDrop => {
+                let path = self.root_directory.join(path);
+                Some(root.join(path.strip_prefix("/").unwrap_or(&path)))
+            }
+            _ => pid_file,
+        };
+
+        let mut steps = Vec::new();
+        let custom = |steps: &mut Vec<Stage>, point: After| {
+            for _ in self.steps.iter().filter(|(after, _)| *after == point) {
+                steps.push(Stage::Custom);
+            }
+        };
+        if !self.foreground {
+            steps.push(Stage::Fork);
+        }
+        if working_directory.is_some() {
+            steps.push(Stage::ChangeDirectory);
+        }
+        if !self.foreground && self.detach_session {
+            steps.push(Stage::DetachSession);
+        }
+        custom(&mut steps, After::SetSid);
+        if self.umask.is_some() {
+            steps.push(Stage::Umask);
+        }
+        if !self.foreground {
+            steps.push(Stage::Fork);
+        }
+        if self.reset_signals || self.ignore_sigpipe {
+            steps.push(Stage::Signals);
+        }
+        if self.clear_environment.is_some() || !self.envs.is_empty() {
+            steps.push(Stage::Environment);
+        }
+        custom(&mut steps, After::Fork);
+        if self.no_new_privileges {
+            steps.push(Stage::NoNewPrivileges);
+        }
+        #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+        {
+            if self.subreaper {
+                steps.push(Stage::Subreaper);
+            }
+        }
+        if self.process_name.is_some() {
+            steps.push(Stage::ProcessName);
+        }
+        let has_pid_file = pid_file.is_some() || self.pid_file_fd.is_some();
+        let pid_file_stage = |stage| has_pid_file && self.pid_file_write == stage;
+        if has_pid_file && self.pid_file_write != WriteStage::AfterPrivilegeDrop {
+            steps.push(Stage::PidFile);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if self.single_instance.is_some() {
+                steps.push(Stage::SingleInstance);
+            }
+        }
+        custom(&mut steps, After::PidFile);
+        if !self.foreground || !self.inherit_fds.is_empty() {
+            steps.push(Stage::RedirectStreams);
+        }
+        custom(&mut steps, After::RedirectStreams);
+        if self.set_user_env && self.user.is_some() {
+            steps.push(Stage::Environment);
+        }
+        steps.push(Stage::PrivilegedAction);
+        custom(&mut steps, After::PrivilegedAction);
+        #[cfg(target_os = "linux")]
+        let linux_priority = self.io_priority.is_some() || self.oom_score_adj.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let linux_priority = false;
+        if self.nice.is_some() || linux_priority {
+            steps.push(Stage::Priority);
+        }
+        if !self.rlimits.is_empty() || self.core_dumps.is_some() || self.lock_memory.is_some() {
+            steps.push(Stage::ResourceLimits);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if !self.effective_namespaces().is_empty() {
+                steps.push(Stage::Namespaces);
+            }
+        }
+        if pid_file_stage(WriteStage::BeforeChroot) {
+            steps.push(Stage::PidFile);
+        }
+        if root.is_some() {
+            steps.push(Stage::Chroot);
+        }
+        custom(&mut steps, After::Chroot);
+        if self.chown_streams && !self.foreground && (uid.is_some() || gid.is_some()) {
+            steps.push(Stage::RedirectStreams);
+        }
+        if gid.is_some() {
+            steps.push(Stage::SetGroup);
+        }
+        if uid.is_some() {
+            steps.push(Stage::SetUser);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if self.core_dumps.is_some() {
+                steps.push(Stage::ResourceLimits);
+            }
+        }
+        if let Some(CoreDumps::Allowed(_)) = self.core_dumps {
+            steps.push(Stage::ChangeDirectory);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if self.parent_death_signal.is_some() {
+                steps.push(Stage::Signals);
+            }
+        }
+        custom(&mut steps, After::PrivilegeDrop);
+        if pid_file_stage(WriteStage::AfterPrivilegeDrop) {
+            steps.push(Stage::PidFile);
+        }
+        if !self.reopen_signals.is_empty()
+            || !self.handle_signals.is_empty()
+            || self.reap_children.is_some()
+        {
+            steps.push(Stage::Signals);
+        }
+        if self.control_socket.is_some() {
+            steps.push(Stage::Control);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if !self.landlock_read.is_empty() || !self.landlock_write.is_empty() {
+                steps.push(Stage::Landlock);
+            }
+        }
+        if pid_file_stage(WriteStage::Last) {
+            steps.push(Stage::PidFile);
+        }
+        if !self.cleanup_signals.is_empty() && self.pid_file.is_some() {
+            steps.push(Stage::Signals);
+        }
+        custom(&mut steps, After::Daemonized);
+
+        Ok(Plan {
+            uid,
+            gid,
+            working_directory,
+            pid_file,
+            root,
+            stdin: self.stdin.inner.target(),
+            stdout: self.stdout.inner.target(),
+            stderr: self.stderr.inner.target(),
+            steps,
+        })
+    }
+
+    /// Perform non-destructive checks of the configuration: resolve user and group names, check
+    /// that the working directory, the pid file directory, the new root and the directories of
+    /// output files exist and are accessible, and that `handle_signals` and
+    /// `cleanup_pid_file_on_signals` don't share signals.
+    pub fn validate(&self) -> Result<(), Error> {
+        let plan = self.plan()?;
+        if self
+            .cleanup_signals
+            .iter()
+            .any(|signal| self.handle_signals.contains(signal))
+        {
+            return Err(ErrorKind::ConflictingSignals.into());
+        }
+        if let Some(directory) = plan
+            .working_directory
+            .as_ref()
+            .filter(|directory| self.create_directory.is_none() || directory.exists())
+        {
+            check_access(directory, libc::X_OK).map_err(ErrorKind::ChangeDirectory)?;
+        }
+        let pid_file_directory = plan.pid_file.as_ref().and_then(|path| path.parent());
+        if let Some(directory) =
+            pid_file_directory.filter(|directory| !self.pid_file_directory || directory.exists())
+        {
+            check_access(directory, libc::W_OK | libc::X_OK).map_err(ErrorKind::OpenPidfile)?;
+        }
+        if let Some(root) = &plan.root {
+            check_access(root, libc::X_OK).map_err(ErrorKind::Chroot)?;
+        }
+        for stdio in &[&self.stdout, &self.stderr] {
+            let path = match stdio.inner {
+                StdioImpl::RedirectToPath(ref path)
+                | StdioImpl::AppendToPath(ref path)
+                | StdioImpl::Logger(ref path) => expand_instance(path, self.instance.as_deref()),
+                _ => continue,
+            };
+            // Output files are opened after root change, except for the logger.
+            let path = match (&plan.root, &stdio.inner) {
+                (_, StdioImpl::Logger(_)) | (None, _) => path,
+                (Some(root), _) => root.join(path.strip_prefix("/").unwrap_or(&path)),
+            };
+            if let Some(directory) = path.parent().filter(|path| !path.as_os_str().is_empty()) {
+                check_access(directory, libc::W_OK | libc::X_OK)
+                    .map_err(ErrorKind::OpenStreamPath)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_instance_paths(&mut self) {
+        let instance = match self.instance.clone() {
+            Some(instance) => instance,
+            None => return,
+        };
+        if let Some(path) = &mut self.pid_file {
+            *path = expand_instance(path, Some(&instance));
+        }
+        for stdio in [&mut self.stdin, &mut self.stdout, &mut self.stderr].iter_mut() {
+            match &mut stdio.inner {
+                StdioImpl::RedirectToPath(path)
+                | StdioImpl::AppendToPath(path)
+                | StdioImpl::Logger(path) => *path = expand_instance(path, Some(&instance)),
+                _ => (),
+            }
+        }
+    }
+
+    /// Namespaces set with `namespaces` and the ones implied by other options.
+    #[cfg(target_os = "linux")]
+    fn effective_namespaces(&self) -> Namespaces {
+        let pivot_root = matches!(self.root, Some(RootImpl::PivotRoot(..)));
+        if self.bind_mounts.is_empty() && !pivot_root {
+            self.namespaces
+        } else {
+            self.namespaces | Namespaces::MOUNT
+        }
+    }
+
+    fn resolve_ids(&self) -> Result<(Option<libc::uid_t>, Option<libc::gid_t>), ErrorKind> {
+        unsafe {
+            let uid = self.user.clone().map(|user| get_user(user)).transpose()?;
+            let gid = self.group.clone().map(|group| get_group(group)).transpose()?;
+            Ok((uid, gid))
+        }
+    }
+
+    /// Start daemonization process, terminate parent after first fork, returns privileged action
+    /// result to the child. Fails with a configuration error if privileges are dropped with
+    /// `PrivilegeDrop::Temporary`, use `start_extended` to get the `PrivilegeToken`.
+    pub fn start(self) -> Result<T, Error> {
+        if self.privilege_drop == PrivilegeDrop::Temporary {
+            return Err(ErrorKind::DiscardedPrivilegeToken.into());
+        }
+        match self.execute() {
+            Outcome::Parent(Ok(Parent {
+                first_child_exit_code,
+                ..
+            })) => exit(first_child_exit_code),
+            Outcome::Parent(Err(err)) => Err(err),
+            Outcome::Child(Ok(Child {
+                privileged_action_result,
+                ready,
+                ..
+            })) => {
+                if let Some(ready) = ready {
+                    ready.notify();
+                }
+                Ok(privileged_action_result)
+            }
+            Outcome::Child(Err(err)) => Err(err),
+        }
+    }
+
+    /// Same as `start`, but also returns the PID, the resolved user and group and the locked pid
+    /// file of the daemon.
+    pub fn start_extended(self) -> Result<Daemonized<T>, Error> {
+        match self.execute() {
+            Outcome::Parent(Ok(Parent {
+                first_child_exit_code,
+                ..
+            })) => exit(first_child_exit_code),
+            Outcome::Parent(Err(err)) => Err(err),
+            Outcome::Child(Ok(mut child)) => {
+                if let Some(ready) = child.ready.take() {
+                    ready.notify();
+                }
+                Ok(Daemonized {
+                    pid: unsafe { libc::getpid() },
+                    uid: child.uid,
+                    gid: child.gid,
+                    pid_file: child.pid_file_guard(),
+                    handle: child.handle.take(),
+                    inherited_fds: std::mem::take(&mut child.inherited_fds),
+                    privilege_token: child.privilege_token.take(),
+                    privileged_action_result: child.privileged_action_result,
+                })
+            }
+            Outcome::Child(Err(err)) => Err(err),
+        }
+    }
+
+    /// Start daemonization process like `start`, but the parent waits until daemonization
+    /// finishes. If the daemon fails, the parent prints the error to stderr and exits with code
+    /// 1, so the launcher sees the result in the exit status. Like `start`, rejects
+    /// `PrivilegeDrop::Temporary`.
+    pub fn start_sync(mut self) -> Result<T, Error> {
+        if self.privilege_drop == PrivilegeDrop::Temporary {
+            return Err(ErrorKind::DiscardedPrivilegeToken.into());
+        }
+        self.relay_errors = true;
+        match self.execute() {
+            Outcome::Parent(Ok(Parent {
+                first_child_exit_code,
+                ..
+            })) => exit(first_child_exit_code),
+            Outcome::Parent(Err(err)) => {
+                eprintln!("{}", err);
+                exit(1)
+            }
+            Outcome::Child(Ok(Child {
+                privileged_action_result,
+                ready,
+                ..
+            })) => {
+                if let Some(ready) = ready {
+                    ready.notify();
+                }
+                Ok(privileged_action_result)
+            }
+            Outcome::Child(Err(err)) => Err(err),
+        }
+    }
+
+    /// Execute daemonization process, don't terminate parent after first fork.
+    pub fn execute(mut self) -> Outcome<T, M> {
+        #[cfg(feature = "tracing")]
+        let _span = ::tracing::debug_span!("daemonize", pid = std::process::id()).entered();
+        let mut on_error = self.on_error.take();
+
+        self.expand_instance_paths();
+
+        if self.directory_user_home {
+            let home = self
+                .resolve_ids()
+                .and_then(|(uid, _)| unsafe { user_home(uid.unwrap_or_else(|| libc::getuid())) });
+            match home {
+                Ok(home) => self.directory = Some(home),
+                Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+            }
+        }
+
+        if self.auto_detect_supervisor && is_supervised() {
+            self.foreground = true;
+        }
+
+        if !self.foreground && std::process::id() == 1 {
+            match self.init_policy {
+                InitPolicy::Refuse => {
+                    return Outcome::Parent(Err(report(&mut on_error, ErrorKind::RunningAsInit)))
+                }
+                InitPolicy::Foreground | InitPolicy::ForegroundReaper => self.foreground = true,
+            }
+        }
+
+        if !self.foreground && self.preflight {
+            debug!("validating configuration");
+            if let Err(err) = self.validate() {
+                return Outcome::Parent(Err(report(&mut on_error, err)));
+            }
+        }
+
+        if let Some(action) = self.pre_fork_action.take() {
+            debug!("running pre-fork action");
+            if let Err(err) = action() {
+                let err = Error::from_source(ErrorKind::PreForkAction, err);
+                return Outcome::Parent(Err(report(&mut on_error, err)));
+            }
+        }
+
+        if self.foreground {
+            debug!("staying in the foreground");
+            return Outcome::Child(
+                self.execute_child(None, &[])
+                    .map_err(|err| report(&mut on_error, err)),
+            );
+        }
+
+        unsafe {
+            let stdout = match parent_pipe(&mut self.stdout) {
+                Ok(stdout) => stdout,
+                Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+            };
+            let stderr = match parent_pipe(&mut self.stderr) {
+                Ok(stderr) => stderr,
+                Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+            };
+            let now = Instant::now();
+            let ready_timeout = self.ready_timeout;
+            let ready_deadline = ready_timeout.map(|timeout| now + timeout);
+            let deadline = self.timeout.map(|timeout| now + timeout);
+            let until_ready = ready_timeout.is_some() || self.relay_errors || deadline.is_some();
+            let (read_end, ready) = match ready::pipe() {
+                Ok(ready_pipe) => ready_pipe,
+                Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+            };
+            #[cfg(feature = "channel")]
+            let channel_pipe = match self.channel.then(|| channel::pipe()).transpose() {
+                Ok(channel_pipe) => channel_pipe,
+                Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+            };
+
+            debug!("forking the first child");
+            match perform_fork() {
                 Ok(Some(first_child_pid)) => {
-                    Outcome::Parent(match waitpid(first_child_pid) {
-                        Err(err) => Err(err.into()),
-                        Ok(first_child_exit_code) => Ok(Parent { first_child_exit_code: first_child_exit_code as i32 }),
-                    })
-                },
-                Err(err) => Outcome::Parent(Err(err.into())),
-                Ok(None) => match self.execute_child() {
-                    Ok(privileged_action_result) => Outcome::Child(Ok(Child {
-                        privileged_action_result,
-                    })),
-                    Err(err) => Outcome::Child(Err(err.into())),
+                    #[cfg(feature = "channel")]
+                    let channel = channel_pipe.map(|(read_end, _)| read_end);
+                    // Close write ends, so the read ends see EOF once the daemon exits.
+                    drop((self, ready));
+                    // The first child exits right after the second fork, so it's waited after
+                    // the daemon to apply the timeout to both.
+                    let status = ready::wait(read_end, until_ready, ready_deadline, deadline);
+                    if let Err(ErrorKind::Timeout) = status {
+                        // Reaped, so it's not left as a zombie, it has exited if it forked the
+                        // daemon.
+                        libc::kill(first_child_pid, libc::SIGKILL);
+                        let _ = waitpid(first_child_pid);
+                        return Outcome::Parent(Err(report(&mut on_error, ErrorKind::Timeout)));
+                    }
+                    let first_child_exit_code = match waitpid(first_child_pid) {
+                        Ok(first_child_exit_code) => first_child_exit_code,
+                        Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+                    };
+                    debug!(
+                        "first child {} exited with code {}",
+                        first_child_pid, first_child_exit_code
+                    );
+                    let daemon = match status {
+                        Ok(Ok(daemon)) => daemon,
+                        // Already reported in the daemon.
+                        Ok(Err(err)) => return Outcome::Parent(Err(err.into())),
+                        Err(err) => return Outcome::Parent(Err(report(&mut on_error, err))),
+                    };
+                    let daemon_pid = daemon.map(|(pid, _)| pid);
+                    debug!("daemon pid is {:?}", daemon_pid);
+                    Outcome::Parent(Ok(Parent {
+                        first_child_pid,
+                        first_child_exit_code,
+                        daemon_pid,
+                        #[cfg(target_os = "linux")]
+                        daemon_pidfd: daemon
+                            .and_then(|(pid, start_time)| open_pidfd(pid, start_time?)),
+                        stdout,
+                        stderr,
+                        #[cfg(feature = "channel")]
+                        channel,
+                        message: PhantomData,
+                    }))
                 },
+                Err(err) => Outcome::Parent(Err(report(&mut on_error, err))),
+                Ok(None) => {
+                    if let Some(action) = &mut self.post_fork_action {
+                        action(Forked::FirstChild);
+                    }
+                    drop((stdout, stderr, read_end));
+                    #[allow(unused_mut)]
+                    let mut private_fds = vec![ready.as_raw_fd()];
+                    #[cfg(feature = "channel")]
+                    let channel = channel_pipe.map(|(_, write_end)| write_end);
+                    #[cfg(feature = "channel")]
+                    private_fds.extend(channel.as_ref().map(|channel| channel.as_raw_fd()));
+                    match self.execute_child(Some(&ready), &private_fds) {
+                        Ok(mut child) => {
+                            #[cfg(feature = "channel")]
+                            {
+                                child.channel = channel;
+                            }
+                            // Without readiness, the daemon is ready once daemonization succeeds.
+                            if until_ready && ready_timeout.is_none() {
+                                ready.notify();
+                            } else if until_ready {
+                                child.ready = Some(ready);
+                            }
+                            Outcome::Child(Ok(child))
+                        }
+                        Err(err) => {
+                            if until_ready {
+                                ready.fail(err.kind());
+                            }
+                            Outcome::Child(Err(report(&mut on_error, err)))
+                        }
+                    }
+                }
             }
         }
     }
 
-    fn execute_child(self) -> Result<T, ErrorKind> {
+    /// `private_fds` are closed in helper processes, e.g. the logger.
+    fn execute_child(
+        mut self,
+        status: Option<&Ready>,
+        private_fds: &[libc::c_int],
+    ) -> Result<Child<T, M>, Error> {
+        let mut steps = std::mem::take(&mut self.steps);
+        #[cfg(target_os = "linux")]
+        let namespaces = self.effective_namespaces();
+        let pid_file_write = self.pid_file_write;
+        let pid_file_format = self.pid_file_format.clone();
+        let pid_file_start_time = self.pid_file_start_time;
+        let adopted_pid_file = self.pid_file_fd.take().map(IntoRawFd::into_raw_fd);
+        let atomic_pid_file = self.atomic_pid_file && adopted_pid_file.is_none();
+        let pid_file_lock = self.pid_file_lock;
+        let pid_file_directory = self.pid_file_directory;
+        let custom_contents = self.pid_file_contents.take();
         unsafe {
-            set_current_dir(&self.directory).map_err(|_| ErrorKind::ChangeDirectory(errno()))?;
-            set_sid()?;
-            libc::umask(self.umask.inner);
+            if let Some(directory) = &self.directory {
+                if let (Some(mode), false) = (self.create_directory, directory.exists()) {
+                    debug!("creating working directory {}", directory.display());
+                    let owner = if self.chown_directory {
+                        Some(self.resolve_ids()?)
+                    } else {
+                        None
+                    };
+                    create_working_directory(directory, mode, owner)?;
+                }
+                debug!("changing working directory to {}", directory.display());
+                set_current_dir(directory).map_err(|_| ErrorKind::ChangeDirectory(errno()))?;
+            }
+            if !self.foreground && self.detach_session {
+                debug!("creating a new session");
+                set_sid()?;
+                debug!("created session {}", libc::getsid(0));
+            }
+            step::run(&mut steps, After::SetSid)?;
+            if let Some(umask) = self.umask {
+                debug!("setting umask {:o}", umask.inner);
+                let previous = libc::umask(umask.inner);
+                debug!("changed umask from {:o}", previous);
+            }
 
-            if perform_fork()?.is_some() {
-                exit(0)
+            #[cfg(target_os = "linux")]
+            let first_child = if self.foreground { None } else { Some(libc::getpid()) };
+            if !self.foreground {
+                debug!("forking the daemon");
+                if perform_fork()?.is_some() {
+                    exit(0)
+                }
+                debug!("forked the daemon with pid {}", libc::getpid());
+                if let Some(action) = &mut self.post_fork_action {
+                    action(Forked::Daemon);
+                }
+            }
+            if let Some(status) = status {
+                status.send_pid(libc::getpid());
+            }
+            // Captured before the root change, which may hide the executable and `/proc`.
+            let identity = pidfile::Identity::current();
+            let pid_file_contents = || match &custom_contents {
+                Some(contents) => contents(libc::getpid()).into_bytes(),
+                None => pidfile::contents(&pid_file_format, pid_file_start_time, &identity),
             };
+            if self.reset_signals {
+                debug!("resetting signal dispositions and mask");
+                reset_signals(&self.keep_ignored_signals)?;
+            }
+            if self.ignore_sigpipe {
+                debug!("ignoring SIGPIPE");
+                if libc::signal(libc::SIGPIPE, libc::SIG_IGN) == libc::SIG_ERR {
+                    return Err(ErrorKind::SetSignalHandler(errno()).into());
+                }
+            }
+            if self.close_fds {
+                debug!("closing inherited descriptors except {:?}", self.keep_fds);
+                let mut keep = self.keep_fds.clone();
+                keep.extend(&self.inherit_fds);
+                keep.extend(private_fds);
+                keep.extend(status.map(AsRawFd::as_raw_fd));
+                keep.extend(adopted_pid_file);
+                keep.extend(
+                    [&self.stdin, &self.stdout, &self.stderr]
+                        .iter()
+                        .filter_map(|stdio| stdio.as_raw_fd()),
+                );
+                close_inherited_fds(&keep);
+            }
+            if let Some(keep) = &self.clear_environment {
+                debug!("clearing the environment except {:?}", keep);
+                clear_environment(keep);
+            }
+            for (key, value) in &self.envs {
+                debug!("setting environment variable {:?}", key);
+                set_env(key, value)?;
+            }
+            step::run(&mut steps, After::Fork)?;
 
-            let pid_file_fd = self
+            if self.no_new_privileges {
+                debug!("setting no_new_privs");
+                set_no_new_privileges()?;
+            }
+            #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+            {
+                if self.subreaper {
+                    debug!("becoming a subreaper");
+                    set_subreaper()?;
+                }
+            }
+            if let Some(name) = &self.process_name {
+                debug!("setting process name {}", name);
+                let name =
+                    CString::new(name.as_str()).map_err(|_| ErrorKind::ProcessNameContainsNul)?;
+                set_process_name(&name)?;
+            }
+
+            // Resolved in the working directory of the daemon, which is changed above.
+            let mut pid_file_path = self
                 .pid_file
-                .clone()
-                .map(|pid_file| create_pid_file(pid_file))
+                .as_ref()
+                .map(|path| std::env::current_dir().unwrap_or_default().join(path));
+            let mut pid_file_fd = match adopted_pid_file {
+                Some(fd) if pid_file_write != WriteStage::AfterPrivilegeDrop => Some(fd),
+                Some(_) => None,
+                None => self
+                    .pid_file
+                    .clone()
+                    .filter(|_| pid_file_write != WriteStage::AfterPrivilegeDrop)
+                    .map(|pid_file| {
+                        if pid_file_directory {
+                            create_pid_file_directory(&pid_file)?;
+                        }
+                        debug!("locking pid file {}", pid_file.display());
+                        if atomic_pid_file {
+                            pidfile::create_atomic(pid_file, &pid_file_contents(), pid_file_lock)
+                        } else {
+                            create_pid_file(pid_file, pid_file_lock)
+                        }
+                    })
+                    .transpose()?,
+            };
+            #[cfg(target_os = "linux")]
+            let instance_fd = self
+                .single_instance
+                .as_ref()
+                .map(|guard| {
+                    debug!("acquiring single instance guard {:?}", guard);
+                    instance::acquire(guard)
+                })
                 .transpose()?;
+            step::run(&mut steps, After::PidFile)?;
 
-            redirect_standard_streams(self.stdin, self.stdout, self.stderr)?;
+            if !self.inherit_fds.is_empty() {
+                debug!("passing descriptors {:?}", self.inherit_fds);
+            }
+            let inherited_fds = self
+                .inherit_fds
+                .iter()
+                .map(|&fd| inherit_fd(fd))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let stream_files = [
+                (libc::STDOUT_FILENO, &self.stdout),
+                (libc::STDERR_FILENO, &self.stderr),
+            ]
+            .iter()
+            .filter(|(_, stdio)| matches!(stdio.inner, StdioImpl::RedirectToFile(_)))
+            .map(|&(fd, _)| fd)
+            .collect::<Vec<_>>();
+            let mut deferred_streams = if self.foreground {
+                DeferredStreams::default()
+            } else {
+                debug!(
+                    "redirecting standard streams, stdin {:?}, stdout {:?}, stderr {:?}",
+                    self.stdin, self.stdout, self.stderr
+                );
+                redirect_standard_streams(self.stdin, self.stdout, self.stderr)?
+            };
+            step::run(&mut steps, After::RedirectStreams)?;
 
             let uid = self.user.map(|user| get_user(user)).transpose()?;
             let gid = self.group.map(|group| get_group(group)).transpose()?;
+            debug!("resolved uid {:?}, gid {:?}", uid, gid);
+            if let (true, Some(uid)) = (self.set_user_env, uid) {
+                debug!("setting the environment of user {}", uid);
+                set_user_env(uid, &self.envs)?;
+            }
+
+            if !deferred_streams.logger.is_empty() {
+                let streams = std::mem::take(&mut deferred_streams.logger);
+                #[allow(unused_mut)]
+                let mut close_fds = pid_file_fd
+                    .into_iter()
+                    .chain(private_fds.iter().copied())
+                    .collect::<Vec<_>>();
+                #[cfg(target_os = "linux")]
+                close_fds.extend(instance_fd);
+                debug!("starting logger process");
+                logger::spawn(streams, uid, gid, &close_fds)?;
+            }
+
+            // The pid file is created by the daemon user after privileges drop.
+            if self.chown_pid_file && pid_file_fd.is_some() {
+                let args: Option<(PathBuf, libc::uid_t, libc::gid_t)> =
+                    match (self.pid_file.clone(), uid, gid) {
+                        (Some(pid), Some(uid), Some(gid)) => Some((pid, uid, gid)),
+                        (Some(pid), None, Some(gid)) => Some((pid, libc::uid_t::MAX - 1, gid)),
+                        (Some(pid), Some(uid), None) => Some((pid, uid, libc::gid_t::MAX - 1)),
+                        // Or pid file is not provided, or both user and group
+                        _ => None,
+                    };
+
+                if let Some((pid, uid, gid)) = args {
+                    debug!("changing pid file owner to {}:{}", uid, gid);
+                    chown_pid_file(pid, uid, gid)?;
+                }
+            }
+
+            if let Some(pid_file_fd) = pid_file_fd {
+                set_cloexec_pid_file(pid_file_fd)?;
+            }
+
+            let context = PrivilegedContext {
+                uid,
+                gid,
+                pid_file_fd,
+                working_directory: match self.root {
+                    Some(_) => self.root_directory.clone(),
+                    None => std::env::current_dir().unwrap_or_default(),
+                },
+                pid: libc::getpid(),
+            };
+            debug!("running privileged action");
+            let action = self.privileged_action.take().expect("privileged action is set");
+            let privileged_action_result = action(&context)?;
+            step::run(&mut steps, After::PrivilegedAction)?;
+
+            if let Some(nice) = self.nice {
+                debug!("setting nice value {}", nice);
+                check_err(
+                    libc::setpriority(libc::PRIO_PROCESS, 0, nice),
+                    ErrorKind::SetPriority,
+                )?;
+            }
+            #[cfg(target_os = "linux")]
+            {
+                if let Some((class, level)) = self.io_priority {
+                    debug!("setting I/O priority {:?} {}", class, level);
+                    set_io_priority(class, level)?;
+                }
+                if let Some(adjustment) = self.oom_score_adj {
+                    debug!("setting OOM score adjustment {}", adjustment);
+                    std::fs::write("/proc/self/oom_score_adj", adjustment.to_string()).map_err(
+                        |err| ErrorKind::SetOomScoreAdj(err.raw_os_error().unwrap_or(libc::EIO)),
+                    )?;
+                }
+            }
+            for &(resource, soft, hard) in &self.rlimits {
+                debug!("setting {:?} limit to {} {}", resource, soft, hard);
+                let limit = libc::rlimit {
+                    rlim_cur: soft,
+                    rlim_max: hard,
+                };
+                check_err(
+                    libc::setrlimit(resource.number() as _, &limit),
+                    ErrorKind::SetResourceLimit,
+                )?;
+            }
+            if let Some(core_dumps) = &self.core_dumps {
+                debug!("setting core file size limit");
+                set_core_limit(matches!(core_dumps, CoreDumps::Allowed(_)))?;
+            }
+            if let Some((flags, required)) = self.lock_memory {
+                debug!("locking memory {:?}", flags);
+                if libc::mlockall(flags.inner) == -1 {
+                    if required {
+                        return Err(ErrorKind::LockMemory(errno()).into());
+                    }
+                    let err = std::io::Error::last_os_error();
+                    warn!("unable to lock memory: {}", err);
+                }
+            }
+
+            #[cfg(target_os = "linux")]
+            {
+                if !namespaces.is_empty() {
+                    debug!("creating namespaces {:?}", namespaces);
+                    namespaces::create_namespaces(namespaces, &self.uid_map, &self.gid_map)?;
+                }
+
+                if !self.bind_mounts.is_empty() {
+                    let root = match self.root {
+                        Some(RootImpl::Chroot(ref root)) => root.as_path(),
+                        Some(RootImpl::PivotRoot(ref root, _)) => root.as_path(),
+                        None => Path::new("/"),
+                    };
+                    namespaces::bind_mounts(&self.bind_mounts, root)?;
+                }
+            }
+
+            if pid_file_write == WriteStage::BeforeChroot && !atomic_pid_file {
+                if let Some(pid_file_fd) = pid_file_fd {
+                    debug!("writing pid file");
+                    write_pid_file(pid_file_fd, &pid_file_contents())?;
+                }
+            }
+
+            if self.root_devnull {
+                match self.root {
+                    Some(RootImpl::Chroot(ref root)) => create_devnull(root)?,
+                    #[cfg(target_os = "linux")]
+                    Some(RootImpl::PivotRoot(ref root, _)) => create_devnull(root)?,
+                    None => (),
+                }
+            }
+
+            // The pid file is removed from inside the new root, so it's kept if it's outside.
+            let new_root = match self.root {
+                Some(RootImpl::Chroot(ref root)) => Some(root),
+                #[cfg(target_os = "linux")]
+                Some(RootImpl::PivotRoot(ref root, _)) => Some(root),
+                None => None,
+            };
+            if let Some(root) = new_root {
+                pid_file_path = pid_file_path.and_then(|path| rebase_path(&path, root));
+            }
+
+            match self.root {
+                Some(RootImpl::Chroot(ref root)) => {
+                    debug!("changing root to {}", root.display());
+                    change_root(root.clone())?
+                }
+                #[cfg(target_os = "linux")]
+                Some(RootImpl::PivotRoot(ref new_root, ref put_old)) => {
+                    debug!("pivoting root to {}", new_root.display());
+                    namespaces::pivot_root(new_root.clone(), put_old.clone())?
+                }
+                None => (),
+            }
+
+            if self.root.is_some() {
+                set_current_dir(&self.root_directory)
+                    .map_err(|_| ErrorKind::ChangeDirectory(errno()))?;
+            }
+            step::run(&mut steps, After::Chroot)?;
+
+            if self.chown_streams && !self.foreground && (uid.is_some() || gid.is_some()) {
+                debug!("changing standard stream files owner to {:?}:{:?}", uid, gid);
+                chown_streams(&mut deferred_streams, &stream_files, uid, gid)?;
+            }
+
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            {
+                if self.solaris_privileges.is_some() {
+                    solaris::set_privilege_aware()?;
+                }
+            }
+
+            let privilege_token = match self.privilege_drop {
+                PrivilegeDrop::Permanent => {
+                    if let Some(gid) = gid {
+                        debug!("setting group {}", gid);
+                        set_group(gid)?;
+                    }
+
+                    if let Some(uid) = uid {
+                        debug!("setting user {}", uid);
+                        set_user(uid)?;
+                    }
+
+                    if self.verify_privilege_drop {
+                        verify_privilege_drop(uid, gid)?;
+                    }
+                    debug!("running as uid {}, gid {}", libc::getuid(), libc::getgid());
+
+                    None
+                }
+                PrivilegeDrop::Temporary => {
+                    let token = PrivilegeToken {
+                        uid,
+                        gid,
+                        saved_uid: libc::geteuid(),
+                        saved_gid: libc::getegid(),
+                    };
+
+                    if let Some(gid) = gid {
+                        debug!("setting effective group {}", gid);
+                        set_effective_group(gid)?;
+                    }
+
+                    if let Some(uid) = uid {
+                        debug!("setting effective user {}", uid);
+                        set_effective_user(uid)?;
+                    }
+                    debug!("running as euid {}, egid {}", libc::geteuid(), libc::getegid());
+
+                    Some(token)
+                }
+            };
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(core_dumps) = &self.core_dumps {
+                    let dumpable = matches!(core_dumps, CoreDumps::Allowed(_));
+                    debug!("setting dumpable flag {}", dumpable);
+                    check_err(
+                        libc::prctl(libc::PR_SET_DUMPABLE, dumpable as libc::c_ulong, 0, 0, 0),
+                        ErrorKind::SetDumpable,
+                    )?;
+                }
+            }
+            if let Some(CoreDumps::Allowed(directory)) = &self.core_dumps {
+                debug!("changing working directory to {}", directory.display());
+                set_current_dir(directory).map_err(|_| ErrorKind::ChangeDirectory(errno()))?;
+            }
+            // Changing credentials clears the parent death signal, so it's set afterwards.
+            #[cfg(target_os = "linux")]
+            {
+                if let Some(signal) = self.parent_death_signal {
+                    debug!("setting parent death signal {:?}", signal);
+                    set_parent_death_signal(signal, first_child)?;
+                }
+            }
+            step::run(&mut steps, After::PrivilegeDrop)?;
 
-            if self.chown_pid_file {
-                let args: Option<(PathBuf, libc::uid_t, libc::gid_t)> =
-                    match (self.pid_file, uid, gid) {
-                        (Some(pid), Some(uid), Some(gid)) => Some((pid, uid, gid)),
-                        (Some(pid), None, Some(gid)) => Some((pid, libc::uid_t::MAX - 1, gid)),
-                        (Some(pid), Some(uid), None) => Some((pid, uid, libc::gid_t::MAX - 1)),
-                        // Or pid file is not provided, or both user and group
-                        _ => None,
+            if pid_file_write == WriteStage::AfterPrivilegeDrop {
+                if let Some(fd) = adopted_pid_file {
+                    debug!("writing adopted pid file");
+                    write_pid_file(fd, &pid_file_contents())?;
+                    set_cloexec_pid_file(fd)?;
+                    pid_file_fd = Some(fd);
+                } else if let Some(pid_file) = self.pid_file.clone() {
+                    if pid_file_directory {
+                        create_pid_file_directory(&pid_file)?;
+                    }
+                    debug!("locking and writing pid file {}", pid_file.display());
+                    let fd = if atomic_pid_file {
+                        pidfile::create_atomic(pid_file, &pid_file_contents(), pid_file_lock)?
+                    } else {
+                        let fd = create_pid_file(pid_file, pid_file_lock)?;
+                        write_pid_file(fd, &pid_file_contents())?;
+                        fd
                     };
+                    set_cloexec_pid_file(fd)?;
+                    pid_file_fd = Some(fd);
+                    pid_file_path = self
+                        .pid_file
+                        .as_ref()
+                        .map(|path| std::env::current_dir().unwrap_or_default().join(path));
+                }
+            }
 
-                if let Some((pid, uid, gid)) = args {
-                    chown_pid_file(pid, uid, gid)?;
+            #[cfg(any(target_os = "illumos", target_os = "solaris"))]
+            {
+                if let Some(privileges) = self.solaris_privileges {
+                    solaris::set_privileges(&privileges)?;
                 }
             }
 
-            if let Some(pid_file_fd) = pid_file_fd {
-                set_cloexec_pid_file(pid_file_fd)?;
+            redirect_standard_streams_to_paths(&mut deferred_streams)?;
+
+            if !self.reopen_signals.is_empty() {
+                reopen::install(&deferred_streams, &self.reopen_signals)?;
             }
+            let handle = if self.handle_signals.is_empty() {
+                None
+            } else {
+                debug!("handling signals {:?}", self.handle_signals);
+                Some(handle::install(&self.handle_signals)?)
+            };
+            let reaper = match self.reap_children.take() {
+                Some(hook) => {
+                    debug!("reaping children");
+                    Some(reaper::install_hook(hook)?)
+                }
+                None => None,
+            };
+
+            let control_listener = match &self.control_socket {
+                Some(path) => {
+                    debug!("listening on control socket {}", path.display());
+                    Some(control::listen(path, pid_file_fd.is_some())?)
+                }
+                None => None,
+            };
 
-            let privileged_action_result = (self.privileged_action)();
+            #[cfg(target_os = "linux")]
+            {
+                if !self.landlock_read.is_empty() || !self.landlock_write.is_empty() {
+                    debug!("enforcing landlock ruleset");
+                    landlock::restrict(&self.landlock_read, &self.landlock_write)?;
+                }
+            }
 
-            if let Some(root) = self.root {
-                change_root(root)?;
+            if reaper.is_none()
+                && std::process::id() == 1
+                && self.init_policy == InitPolicy::ForegroundReaper
+            {
+                reaper::install()?;
             }
 
-            if let Some(gid) = gid {
-                set_group(gid)?;
+            // Started last, so threads inherit all the restrictions of the daemon.
+            for forwarder in deferred_streams.forwarders {
+                std::thread::spawn(forwarder);
+            }
+            if let Some(listener) = control_listener {
+                std::thread::spawn(move || control::serve(listener));
+            }
+            if let Some(reaper) = reaper {
+                std::thread::spawn(move || reaper.run());
             }
 
-            if let Some(uid) = uid {
-                set_user(uid)?;
+            if pid_file_write == WriteStage::Last && !atomic_pid_file {
+                if let Some(pid_file_fd) = pid_file_fd {
+                    debug!("writing pid file");
+                    write_pid_file(pid_file_fd, &pid_file_contents())?;
+                }
             }
 
-            if let Some(pid_file_fd) = pid_file_fd {
-                write_pid_file(pid_file_fd)?;
+            if let (false, Some(path)) = (self.cleanup_signals.is_empty(), &pid_file_path) {
+                debug!("removing pid file on signals {:?}", self.cleanup_signals);
+                pidfile::install_cleanup(path.clone(), &self.cleanup_signals)?;
             }
+            step::run(&mut steps, After::Daemonized)?;
 
-            Ok(privileged_action_result)
+            debug!("daemonized");
+            Ok(Child {
+                privileged_action_result,
+                uid,
+                gid,
+                pid_file_fd,
+                pid_file: pid_file_path,
+                privilege_token,
+                ready: None,
+                handle,
+                inherited_fds,
+                #[cfg(feature = "channel")]
+                channel: None,
+                message: PhantomData,
+            })
         }
     }
 }
 
+/// Path of `path` as seen after the root change to `root`, `None` if it's outside of `root`.
+fn rebase_path(path: &Path, root: &Path) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let directory = path.parent()?.canonicalize().ok()?;
+    let relative = directory.strip_prefix(&root).ok()?;
+    Some(Path::new("/").join(relative).join(path.file_name()?))
+}
+
+fn is_supervised() -> bool {
+    std::env::var_os("INVOCATION_ID").is_some()
+        || std::env::var_os("NOTIFY_SOCKET").is_some()
+        || std::process::id() == 1
+}
+
 unsafe fn perform_fork() -> Result<Option<libc::pid_t>, ErrorKind> {
     let pid = check_err(libc::fork(), ErrorKind::Fork)?;
     if pid == 0 {
@@ -446,22 +3203,56 @@ unsafe fn waitpid(pid: libc::pid_t) -> Result<libc::c_int, ErrorKind> {
      Ok(child_ret)
  }
 
+fn report<E: Into<Error>>(on_error: &mut Option<ErrorHook>, err: E) -> Error {
+    let err = err.into();
+    warn!("daemonization failed at {:?} stage: {}", err.stage(), err);
+    if let Some(hook) = on_error.take() {
+        hook(err.stage(), &err);
+    }
+    err
+}
+
+/// The PID may be reused if the daemon has already exited, so the process is checked to be the
+/// daemon by its start time after the descriptor pins it.
+#[cfg(target_os = "linux")]
+unsafe fn open_pidfd(pid: libc::pid_t, start_time: u64) -> Option<OwnedFd> {
+    let fd = libc::syscall(libc::SYS_pidfd_open, pid, 0);
+    if fd == -1 {
+        return None;
+    }
+    let pidfd = OwnedFd::from_raw_fd(fd as libc::c_int);
+    if pidfile::process_start_time(pid) != Some(start_time) {
+        return None;
+    }
+    Some(pidfd)
+}
+
 unsafe fn set_sid() -> Result<(), ErrorKind> {
-    check_err(libc::setsid(), ErrorKind::DetachSession)?;
+    if libc::setsid() == -1 {
+        let errno = errno();
+        // Nothing to detach if the process is a session leader already.
+        if errno == libc::EPERM && libc::getsid(0) == libc::getpid() {
+            return Ok(());
+        }
+        return Err(ErrorKind::DetachSession(errno));
+    }
     Ok(())
 }
 
+/// Redirect standard streams, streams redirected to paths are returned to be processed later.
 unsafe fn redirect_standard_streams(
     stdin: Stdio,
     stdout: Stdio,
     stderr: Stdio,
-) -> Result<(), ErrorKind> {
+) -> Result<DeferredStreams, ErrorKind> {
+    let mut deferred = DeferredStreams::default();
+
     let devnull_fd = check_err(
         libc::open(b"/dev/null\0" as *const [u8; 10] as _, libc::O_RDWR),
         ErrorKind::OpenDevnull,
     )?;
 
-    let process_stdio = |fd, stdio: Stdio| {
+    let mut process_stdio = |fd, stdio: Stdio| {
         match stdio.inner {
             StdioImpl::Devnull => {
                 check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
@@ -470,6 +3261,101 @@ unsafe fn redirect_standard_streams(
                 let raw_fd = file.as_raw_fd();
                 check_err(libc::dup2(raw_fd, fd), ErrorKind::RedirectStreams)?;
             }
+            StdioImpl::RedirectToPath(path) => {
+                let flags = if fd == libc::STDIN_FILENO {
+                    libc::O_RDONLY
+                } else {
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC
+                };
+                deferred.paths.push((fd, path, flags))
+            }
+            StdioImpl::AppendToPath(path) => {
+                let flags = if fd == libc::STDIN_FILENO {
+                    libc::O_RDONLY
+                } else {
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND
+                };
+                deferred.paths.push((fd, path, flags))
+            }
+            StdioImpl::MergeWithStdout => {
+                check_err(
+                    libc::dup2(libc::STDOUT_FILENO, fd),
+                    ErrorKind::RedirectStreams,
+                )?;
+                deferred.merged.push(fd);
+            }
+            StdioImpl::Decorated(..) if fd == libc::STDIN_FILENO => {
+                check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
+            }
+            StdioImpl::Decorated(file, prefix) => {
+                let pipe = redirect_to_pipe(fd)?;
+                let stream = if fd == libc::STDERR_FILENO {
+                    "stderr"
+                } else {
+                    "stdout"
+                };
+                deferred
+                    .forwarders
+                    .push(Box::new(move || decorate::forward(pipe, file, prefix, stream)));
+            }
+            StdioImpl::Logger(_) if fd == libc::STDIN_FILENO => {
+                check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
+            }
+            StdioImpl::Logger(path) => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(path)
+                    .map_err(|_| ErrorKind::OpenStreamPath(errno()))?;
+                let pipe = redirect_to_pipe(fd)?;
+                deferred.logger.push((pipe, file));
+            }
+            StdioImpl::Piped => {
+                check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
+            }
+            StdioImpl::Tee(_) if fd == libc::STDIN_FILENO => {
+                check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
+            }
+            StdioImpl::Tee(file) => {
+                let original = File::from_raw_fd(check_err(
+                    libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0),
+                    ErrorKind::RedirectStreams,
+                )?);
+                let pipe = redirect_to_pipe(fd)?;
+                deferred
+                    .forwarders
+                    .push(Box::new(move || tee::forward(pipe, file, original)));
+            }
+            #[cfg(target_os = "linux")]
+            StdioImpl::Journald(_) if fd == libc::STDIN_FILENO => {
+                check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
+            }
+            #[cfg(target_os = "linux")]
+            StdioImpl::Journald(identifier) => {
+                let priority = if fd == libc::STDERR_FILENO {
+                    libc::LOG_ERR
+                } else {
+                    libc::LOG_INFO
+                };
+                journald::connect(fd, &identifier, priority)?;
+            }
+            #[cfg(feature = "syslog")]
+            StdioImpl::Syslog { .. } if fd == libc::STDIN_FILENO => {
+                check_err(libc::dup2(devnull_fd, fd), ErrorKind::RedirectStreams)?;
+            }
+            #[cfg(feature = "syslog")]
+            StdioImpl::Syslog { facility, ident } => {
+                syslog::open(&ident, facility)?;
+                let pipe = redirect_to_pipe(fd)?;
+                let priority = if fd == libc::STDERR_FILENO {
+                    libc::LOG_ERR
+                } else {
+                    libc::LOG_INFO
+                };
+                deferred
+                    .forwarders
+                    .push(Box::new(move || syslog::forward(pipe, priority)));
+            }
             StdioImpl::Keep => (),
         };
         Ok(())
@@ -481,6 +3367,166 @@ unsafe fn redirect_standard_streams(
 
     check_err(libc::close(devnull_fd), ErrorKind::CloseDevnull)?;
 
+    Ok(deferred)
+}
+
+/// Streams which can be redirected only in the daemon itself, after root change and privileges
+/// drop.
+#[derive(Default)]
+struct DeferredStreams {
+    /// Standard stream, target path and `open` flags.
+    paths: Vec<(libc::c_int, PathBuf, libc::c_int)>,
+    /// Files of `paths` opened before privileges drop by `chown_streams`, by standard stream.
+    opened: Vec<(libc::c_int, File)>,
+    /// Standard streams which have to follow stdout if it's reopened.
+    merged: Vec<libc::c_int>,
+    /// Threads which pass the output written to pipes to its final destination.
+    forwarders: Vec<Box<dyn FnOnce() + Send>>,
+    /// Pipes and files for the logger process.
+    logger: Vec<(File, File)>,
+}
+
+/// Replace `Stdio::piped` with the write end of a new pipe, returns the read end for the parent.
+unsafe fn parent_pipe(stdio: &mut Stdio) -> Result<Option<File>, ErrorKind> {
+    match stdio.inner {
+        StdioImpl::Piped => (),
+        _ => return Ok(None),
+    }
+
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::CreatePipe)?;
+    let read_end = File::from_raw_fd(fds[0]);
+    let write_end = File::from_raw_fd(fds[1]);
+    for fd in &fds {
+        check_err(
+            libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC),
+            ErrorKind::CreatePipe,
+        )?;
+    }
+
+    stdio.inner = StdioImpl::RedirectToFile(write_end);
+    Ok(Some(read_end))
+}
+
+/// Clear `FD_CLOEXEC` of `fd`, a standard stream is duplicated first, so it isn't replaced by
+/// redirection.
+unsafe fn inherit_fd(fd: RawFd) -> Result<RawFd, ErrorKind> {
+    let fd = if fd <= libc::STDERR_FILENO {
+        check_err(
+            libc::fcntl(fd, libc::F_DUPFD, libc::STDERR_FILENO + 1),
+            ErrorKind::InheritFd,
+        )?
+    } else {
+        fd
+    };
+    let flags = check_err(libc::fcntl(fd, libc::F_GETFD), ErrorKind::InheritFd)?;
+    check_err(
+        libc::fcntl(fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC),
+        ErrorKind::InheritFd,
+    )?;
+    Ok(fd)
+}
+
+/// Search path set by `clear_environment` if `PATH` isn't kept.
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+fn clear_environment(keep: &[String]) {
+    for (name, _) in std::env::vars_os() {
+        // `remove_var` panics on names it can't pass to the OS.
+        let bytes = name.as_bytes();
+        if bytes.is_empty() || bytes.contains(&b'=') || bytes.contains(&0) {
+            continue;
+        }
+        if !keep.iter().any(|kept| name == kept.as_str()) {
+            std::env::remove_var(name);
+        }
+    }
+    if std::env::var_os("PATH").is_none() {
+        std::env::set_var("PATH", DEFAULT_PATH);
+    }
+}
+
+/// `set_var` panics on names and values it can't pass to the OS.
+fn set_env(key: &OsStr, value: &OsStr) -> Result<(), ErrorKind> {
+    let key_bytes = key.as_bytes();
+    if key_bytes.is_empty()
+        || key_bytes.contains(&b'=')
+        || key_bytes.contains(&0)
+        || value.as_bytes().contains(&0)
+    {
+        return Err(ErrorKind::InvalidEnvironmentVariable);
+    }
+    std::env::set_var(key, value);
+    Ok(())
+}
+
+/// Close all descriptors except the standard streams and `keep`.
+unsafe fn close_inherited_fds(keep: &[RawFd]) {
+    #[cfg(target_os = "linux")]
+    {
+        if let Ok(entries) = std::fs::read_dir("/proc/self/fd") {
+            // Collected first, the directory descriptor is closed once the listing is done.
+            let fds = entries
+                .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<RawFd>().ok())
+                .collect::<Vec<_>>();
+            for fd in fds {
+                if fd > libc::STDERR_FILENO && !keep.contains(&fd) {
+                    libc::close(fd);
+                }
+            }
+            return;
+        }
+    }
+
+    let max = match libc::sysconf(libc::_SC_OPEN_MAX) {
+        -1 => 1024,
+        max => max.min(libc::c_int::MAX as libc::c_long) as RawFd,
+    };
+    for fd in libc::STDERR_FILENO + 1..max {
+        if !keep.contains(&fd) {
+            libc::close(fd);
+        }
+    }
+}
+
+/// Replace `fd` with the write end of a new pipe, returns the read end.
+unsafe fn redirect_to_pipe(fd: libc::c_int) -> Result<File, ErrorKind> {
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::CreatePipe)?;
+    let read_fd = File::from_raw_fd(fds[0]);
+    check_err(
+        libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC),
+        ErrorKind::CreatePipe,
+    )?;
+    check_err(libc::dup2(fds[1], fd), ErrorKind::RedirectStreams)?;
+    check_err(libc::close(fds[1]), ErrorKind::RedirectStreams)?;
+    Ok(read_fd)
+}
+
+unsafe fn redirect_standard_streams_to_paths(
+    streams: &mut DeferredStreams,
+) -> Result<(), ErrorKind> {
+    // Closed once the streams are redirected.
+    let opened = std::mem::take(&mut streams.opened);
+    for &(fd, ref path, flags) in &streams.paths {
+        if let Some((_, file)) = opened.iter().find(|(opened, _)| *opened == fd) {
+            check_err(libc::dup2(file.as_raw_fd(), fd), ErrorKind::RedirectStreams)?;
+            continue;
+        }
+        let path_c = pathbuf_into_cstring(path.clone())?;
+        let raw_fd = check_err(
+            libc::open(path_c.as_ptr(), flags, 0o666),
+            ErrorKind::OpenStreamPath,
+        )?;
+        check_err(libc::dup2(raw_fd, fd), ErrorKind::RedirectStreams)?;
+        check_err(libc::close(raw_fd), ErrorKind::RedirectStreams)?;
+    }
+    for &fd in &streams.merged {
+        check_err(
+            libc::dup2(libc::STDOUT_FILENO, fd),
+            ErrorKind::RedirectStreams,
+        )?;
+    }
     Ok(())
 }
 
@@ -498,10 +3544,57 @@ unsafe fn get_group(group: Group) -> Result<libc::gid_t, ErrorKind> {
 }
 
 unsafe fn set_group(group: libc::gid_t) -> Result<(), ErrorKind> {
+    // Nothing to drop, and non-root users may be not permitted to call `setgid` at all.
+    if has_group(group) {
+        return Ok(());
+    }
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    ))]
+    check_err(libc::setresgid(group, group, group), ErrorKind::SetGroup)?;
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    )))]
     check_err(libc::setgid(group), ErrorKind::SetGroup)?;
     Ok(())
 }
 
+/// Whether real, effective and saved group ids are all `group`.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+))]
+unsafe fn has_group(group: libc::gid_t) -> bool {
+    let (mut real, mut effective, mut saved) = (0, 0, 0);
+    libc::getresgid(&mut real, &mut effective, &mut saved) == 0
+        && real == group
+        && effective == group
+        && saved == group
+}
+
+/// The saved group id can't be checked, so it's always set.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+)))]
+unsafe fn has_group(_group: libc::gid_t) -> bool {
+    false
+}
+
 unsafe fn get_user(user: User) -> Result<libc::uid_t, ErrorKind> {
     match user.inner {
         UserImpl::Id(id) => Ok(id),
@@ -515,24 +3608,320 @@ unsafe fn get_user(user: User) -> Result<libc::uid_t, ErrorKind> {
     }
 }
 
+unsafe fn user_home(user: libc::uid_t) -> Result<PathBuf, ErrorKind> {
+    let ptr = libc::getpwuid(user);
+    if ptr.is_null() || (*ptr).pw_dir.is_null() {
+        return Err(ErrorKind::UserNotFound);
+    }
+    let home = CStr::from_ptr((*ptr).pw_dir).to_bytes();
+    Ok(PathBuf::from(OsStr::from_bytes(home)))
+}
+
+/// Variables in `explicit`, set with `Daemonize::env`, are not overwritten.
+unsafe fn set_user_env(
+    user: libc::uid_t,
+    explicit: &[(OsString, OsString)],
+) -> Result<(), ErrorKind> {
+    let ptr = libc::getpwuid(user);
+    if ptr.is_null() {
+        return Err(ErrorKind::UserNotFound);
+    }
+    let entry = &*ptr;
+    let vars = [
+        ("HOME", entry.pw_dir),
+        ("USER", entry.pw_name),
+        ("LOGNAME", entry.pw_name),
+        ("SHELL", entry.pw_shell),
+    ];
+    let is_explicit = |name: &str| explicit.iter().any(|(key, _)| key == name);
+    for (name, value) in vars
+        .iter()
+        .filter(|(name, value)| !value.is_null() && !is_explicit(name))
+    {
+        std::env::set_var(name, OsStr::from_bytes(CStr::from_ptr(*value).to_bytes()));
+    }
+    Ok(())
+}
+
 unsafe fn set_user(user: libc::uid_t) -> Result<(), ErrorKind> {
+    // Nothing to drop, and non-root users may be not permitted to call `setuid` at all.
+    if has_user(user) {
+        return Ok(());
+    }
+    // Unlike `setuid` of a non-root process, `setresuid` also resets a saved root id.
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    ))]
+    check_err(libc::setresuid(user, user, user), ErrorKind::SetUser)?;
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly",
+        target_os = "openbsd"
+    )))]
     check_err(libc::setuid(user), ErrorKind::SetUser)?;
     Ok(())
 }
 
-unsafe fn create_pid_file(path: PathBuf) -> Result<libc::c_int, ErrorKind> {
-    let path_c = pathbuf_into_cstring(path)?;
+/// Whether real, effective and saved user ids are all `user`.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+))]
+unsafe fn has_user(user: libc::uid_t) -> bool {
+    let (mut real, mut effective, mut saved) = (0, 0, 0);
+    libc::getresuid(&mut real, &mut effective, &mut saved) == 0
+        && real == user
+        && effective == user
+        && saved == user
+}
+
+/// The saved user id can't be checked, so it's always set.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "openbsd"
+)))]
+unsafe fn has_user(_user: libc::uid_t) -> bool {
+    false
+}
+
+unsafe fn verify_privilege_drop(
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+) -> Result<(), ErrorKind> {
+    match uid {
+        // Root privileges are kept on purpose if user is not changed.
+        None | Some(0) => return Ok(()),
+        Some(_) => {
+            if libc::setuid(0) != -1 {
+                return Err(ErrorKind::PrivilegeDropIncomplete);
+            }
+        }
+    }
+    match gid {
+        None | Some(0) => (),
+        Some(_) => {
+            if libc::setgid(0) != -1 {
+                return Err(ErrorKind::PrivilegeDropIncomplete);
+            }
+        }
+    }
+    Ok(())
+}
 
-    let fd = check_err(
-        libc::open(path_c.as_ptr(), libc::O_WRONLY | libc::O_CREAT, 0o666),
-        ErrorKind::OpenPidfile,
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn set_no_new_privileges() -> Result<(), ErrorKind> {
+    check_err(
+        libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0),
+        ErrorKind::NoNewPrivileges,
     )?;
+    Ok(())
+}
 
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+unsafe fn set_no_new_privileges() -> Result<(), ErrorKind> {
+    Ok(())
+}
+
+/// Set the parent death signal once the daemon is reparented from `first_child`, which exits
+/// right after the second fork.
+#[cfg(target_os = "linux")]
+unsafe fn set_parent_death_signal(
+    signal: Signal,
+    first_child: Option<libc::pid_t>,
+) -> Result<(), ErrorKind> {
+    if let Some(first_child) = first_child {
+        wait_reparented(first_child)?;
+    }
+    let parent = libc::getppid();
     check_err(
-        libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB),
-        ErrorKind::LockPidfile,
+        libc::prctl(libc::PR_SET_PDEATHSIG, signal.number() as libc::c_ulong),
+        ErrorKind::ParentDeathSignal,
     )?;
-    Ok(fd)
+    // The parent exited before the signal was set.
+    if libc::getppid() != parent {
+        libc::kill(libc::getpid(), signal.number());
+    }
+    Ok(())
+}
+
+/// Block until the parent `first_child` exits, it's not a child of the daemon, so it's waited
+/// through a pidfd. Its exit is reported after the daemon is reparented.
+#[cfg(target_os = "linux")]
+unsafe fn wait_reparented(first_child: libc::pid_t) -> Result<(), ErrorKind> {
+    if libc::getppid() != first_child {
+        return Ok(());
+    }
+    let fd = libc::syscall(libc::SYS_pidfd_open, first_child, 0);
+    if fd == -1 {
+        return match errno() {
+            libc::ESRCH => Ok(()),
+            errno => Err(ErrorKind::ParentDeathSignal(errno)),
+        };
+    }
+    let pidfd = OwnedFd::from_raw_fd(fd as libc::c_int);
+    // Otherwise the PID may have been reused after the first child exited.
+    if libc::getppid() != first_child {
+        return Ok(());
+    }
+    let mut pollfd = libc::pollfd {
+        fd: pidfd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match libc::poll(&mut pollfd, 1, -1) {
+            -1 if errno() == libc::EINTR => continue,
+            -1 => return Err(ErrorKind::ParentDeathSignal(errno())),
+            _ => return Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_process_name(name: &CStr) -> Result<(), ErrorKind> {
+    check_err(
+        libc::prctl(libc::PR_SET_NAME, name.as_ptr(), 0, 0, 0),
+        ErrorKind::SetProcessName,
+    )?;
+    Ok(())
+}
+
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+unsafe fn set_process_name(name: &CStr) -> Result<(), ErrorKind> {
+    libc::setproctitle(b"%s\0".as_ptr() as *const libc::c_char, name.as_ptr());
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+unsafe fn set_process_name(_name: &CStr) -> Result<(), ErrorKind> {
+    Ok(())
+}
+
+/// Raise the soft core file size limit to the hard one if `allow` is true, otherwise set both
+/// to zero.
+unsafe fn set_core_limit(allow: bool) -> Result<(), ErrorKind> {
+    let mut limit: libc::rlimit = std::mem::zeroed();
+    if allow {
+        check_err(
+            libc::getrlimit(libc::RLIMIT_CORE, &mut limit),
+            ErrorKind::SetResourceLimit,
+        )?;
+        limit.rlim_cur = limit.rlim_max;
+    }
+    check_err(
+        libc::setrlimit(libc::RLIMIT_CORE, &limit),
+        ErrorKind::SetResourceLimit,
+    )?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_io_priority(class: IoClass, level: u8) -> Result<(), ErrorKind> {
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+    let class = match class {
+        IoClass::RealTime => 1,
+        IoClass::BestEffort => 2,
+        IoClass::Idle => 3,
+    };
+    let priority = class << IOPRIO_CLASS_SHIFT | level as libc::c_int;
+    check_err(
+        libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, priority) as libc::c_int,
+        ErrorKind::SetIoPriority,
+    )?;
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_subreaper() -> Result<(), ErrorKind> {
+    check_err(
+        libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0),
+        ErrorKind::SetSubreaper,
+    )?;
+    Ok(())
+}
+
+#[cfg(target_os = "freebsd")]
+unsafe fn set_subreaper() -> Result<(), ErrorKind> {
+    check_err(
+        libc::procctl(
+            libc::P_PID,
+            libc::getpid() as libc::id_t,
+            libc::PROC_REAP_ACQUIRE,
+            std::ptr::null_mut(),
+        ),
+        ErrorKind::SetSubreaper,
+    )?;
+    Ok(())
+}
+
+unsafe fn set_effective_group(group: libc::gid_t) -> Result<(), ErrorKind> {
+    check_err(libc::setegid(group), ErrorKind::SetEffectiveGroup)?;
+    Ok(())
+}
+
+unsafe fn set_effective_user(user: libc::uid_t) -> Result<(), ErrorKind> {
+    check_err(libc::seteuid(user), ErrorKind::SetEffectiveUser)?;
+    Ok(())
+}
+
+fn create_working_directory(
+    path: &Path,
+    mode: u32,
+    owner: Option<(Option<libc::uid_t>, Option<libc::gid_t>)>,
+) -> Result<(), ErrorKind> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(mode)
+        .create(path)
+        .map_err(|err| ErrorKind::CreateWorkingDirectory(err.raw_os_error().unwrap_or(libc::EIO)))?;
+    if let Some((uid, gid)) = owner {
+        std::os::unix::fs::chown(path, uid, gid).map_err(|err| {
+            ErrorKind::ChownWorkingDirectory(err.raw_os_error().unwrap_or(libc::EIO))
+        })?;
+    }
+    Ok(())
+}
+
+fn create_pid_file_directory(path: &Path) -> Result<(), ErrorKind> {
+    match path.parent() {
+        Some(directory) => create_dir_all(directory).map_err(|_| ErrorKind::OpenPidfile(errno())),
+        None => Ok(()),
+    }
+}
+
+unsafe fn create_pid_file(path: PathBuf, style: LockStyle) -> Result<libc::c_int, ErrorKind> {
+    let mut pid_file = PidFile::new(path).lock_style(style);
+    pid_file.lock().map_err(Error::into_kind)?;
+    pid_file
+        .into_raw_fd()
+        .ok_or(ErrorKind::OpenPidfile(libc::EBADF))
 }
 
 unsafe fn chown_pid_file(
@@ -548,15 +3937,68 @@ unsafe fn chown_pid_file(
     Ok(())
 }
 
-unsafe fn write_pid_file(fd: libc::c_int) -> Result<(), ErrorKind> {
-    let pid = libc::getpid();
-    let pid_buf = format!("{}\n", pid).into_bytes();
-    let pid_length = pid_buf.len();
-    let pid_c = CString::new(pid_buf).unwrap();
+/// `files` are descriptors of standard streams redirected to files opened by the caller. Paths
+/// are opened here and the same files are redirected to later, so a path replaced in between,
+/// e.g. with a symlink to a file of another user, is never changed. Only regular files are
+/// changed, not e.g. a terminal.
+unsafe fn chown_streams(
+    streams: &mut DeferredStreams,
+    files: &[libc::c_int],
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+) -> Result<(), ErrorKind> {
+    // -1 keeps the owner or the group.
+    let uid = uid.unwrap_or(libc::uid_t::MAX);
+    let gid = gid.unwrap_or(libc::gid_t::MAX);
+    for &fd in files {
+        check_regular_file(fd)?;
+        check_err(libc::fchown(fd, uid, gid), ErrorKind::ChownStreams)?;
+    }
+    for &(fd, ref path, flags) in &streams.paths {
+        if fd == libc::STDIN_FILENO {
+            continue;
+        }
+        let path_c = pathbuf_into_cstring(path.clone())?;
+        // Truncated only once it's known to be a regular file, `O_NONBLOCK` keeps a FIFO from
+        // blocking the open.
+        let raw_fd = check_err(
+            libc::open(
+                path_c.as_ptr(),
+                (flags & !libc::O_TRUNC) | libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC,
+                0o666,
+            ),
+            ErrorKind::ChownStreams,
+        )?;
+        let file = File::from_raw_fd(raw_fd);
+        check_regular_file(raw_fd)?;
+        check_err(
+            libc::fcntl(raw_fd, libc::F_SETFL, flags & libc::O_APPEND),
+            ErrorKind::ChownStreams,
+        )?;
+        if flags & libc::O_TRUNC != 0 {
+            check_err(libc::ftruncate(raw_fd, 0), ErrorKind::ChownStreams)?;
+        }
+        check_err(libc::fchown(raw_fd, uid, gid), ErrorKind::ChownStreams)?;
+        streams.opened.push((fd, file));
+    }
+    Ok(())
+}
+
+unsafe fn check_regular_file(fd: libc::c_int) -> Result<(), ErrorKind> {
+    let mut stat: libc::stat = std::mem::zeroed();
+    check_err(libc::fstat(fd, &mut stat), ErrorKind::ChownStreams)?;
+    if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+        return Err(ErrorKind::ChownStreams(libc::EINVAL));
+    }
+    Ok(())
+}
+
+unsafe fn write_pid_file(fd: libc::c_int, contents: &[u8]) -> Result<(), ErrorKind> {
+    let pid_length = contents.len();
     check_err(libc::ftruncate(fd, 0), ErrorKind::TruncatePidfile)?;
 
     let written = check_err(
-        libc::write(fd, pid_c.as_ptr() as *const libc::c_void, pid_length),
+        libc::write(fd, contents.as_ptr() as *const libc::c_void, pid_length),
         ErrorKind::WritePid,
     )?;
 
@@ -564,9 +4006,21 @@ unsafe fn write_pid_file(fd: libc::c_int) -> Result<(), ErrorKind> {
         return Err(ErrorKind::WritePidUnspecifiedError);
     }
 
+    // Otherwise the pid file may be empty after a crash right after the start.
+    check_err(sync_data(fd), ErrorKind::SyncPidfile)?;
     Ok(())
 }
 
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "redox"))]
+unsafe fn sync_data(fd: libc::c_int) -> libc::c_int {
+    libc::fsync(fd)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "redox")))]
+unsafe fn sync_data(fd: libc::c_int) -> libc::c_int {
+    libc::fdatasync(fd)
+}
+
 unsafe fn set_cloexec_pid_file(fd: libc::c_int) -> Result<(), ErrorKind> {
     if cfg!(not(target_os = "redox")) {
         let flags = check_err(libc::fcntl(fd, libc::F_GETFD), ErrorKind::GetPidfileFlags)?;
@@ -581,6 +4035,29 @@ unsafe fn set_cloexec_pid_file(fd: libc::c_int) -> Result<(), ErrorKind> {
     Ok(())
 }
 
+unsafe fn create_devnull(root: &Path) -> Result<(), ErrorKind> {
+    // Device numbers differ between systems, so they are copied from the current `/dev/null`.
+    let mut stat: libc::stat = zeroed();
+    check_err(
+        libc::stat(b"/dev/null\0" as *const [u8; 10] as _, &mut stat),
+        ErrorKind::CreateDevnull,
+    )?;
+
+    let dev = root.join("dev");
+    create_dir_all(&dev).map_err(|_| ErrorKind::CreateDevnull(errno()))?;
+
+    let path_c = pathbuf_into_cstring(dev.join("null"))?;
+    if libc::mknod(path_c.as_ptr(), libc::S_IFCHR, stat.st_rdev) == -1 {
+        return match errno() {
+            libc::EEXIST => Ok(()),
+            errno => Err(ErrorKind::CreateDevnull(errno)),
+        };
+    }
+    // Mode passed to `mknod` is affected by umask.
+    check_err(libc::chmod(path_c.as_ptr(), 0o666), ErrorKind::CreateDevnull)?;
+    Ok(())
+}
+
 unsafe fn change_root(path: PathBuf) -> Result<(), ErrorKind> {
     let path_c = pathbuf_into_cstring(path)?;
     check_err(libc::chroot(path_c.as_ptr()), ErrorKind::Chroot)?;
@@ -607,6 +4084,36 @@ unsafe fn get_uid_by_name(name: &CString) -> Option<libc::uid_t> {
     }
 }
 
+fn check_access(path: &Path, mode: libc::c_int) -> Result<(), Errno> {
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| libc::EINVAL)?;
+    if unsafe { libc::access(path.as_ptr(), mode) } == -1 {
+        return Err(errno());
+    }
+    Ok(())
+}
+
+/// Substitute `instance` for `%i` and `%` for `%%` in `path`, other sequences are kept as is.
+fn expand_instance(path: &Path, instance: Option<&str>) -> PathBuf {
+    let instance = match instance {
+        Some(instance) => instance,
+        None => return path.to_owned(),
+    };
+    let mut expanded = Vec::new();
+    let mut bytes = path.as_os_str().as_bytes().iter();
+    while let Some(&byte) = bytes.next() {
+        match (byte, bytes.as_slice().first()) {
+            (b'%', Some(b'i')) => expanded.extend_from_slice(instance.as_bytes()),
+            (b'%', Some(b'%')) => expanded.push(b'%'),
+            _ => {
+                expanded.push(byte);
+                continue;
+            }
+        }
+        bytes.next();
+    }
+    PathBuf::from(std::ffi::OsString::from_vec(expanded))
+}
+
 fn pathbuf_into_cstring(path: PathBuf) -> Result<CString, ErrorKind> {
     CString::new(path.into_os_string().into_vec()).map_err(|_| ErrorKind::PathContainsNul)
 }
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+use super::error::{check_err, ErrorKind};
+use super::{perform_fork, set_group, set_user, waitpid};
+
+/// Fork a detached helper process which copies every pipe to its file until the daemon closes
+/// the write ends. The helper drops privileges to `uid` and `gid` and closes `close_fds` first,
+/// so it keeps nothing of the daemon except the log files.
+pub unsafe fn spawn(
+    streams: Vec<(File, File)>,
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    close_fds: &[libc::c_int],
+) -> Result<(), ErrorKind> {
+    let helper_pid = match perform_fork()? {
+        Some(pid) => pid,
+        None => {
+            let code = match prepare(uid, gid, close_fds) {
+                Ok(()) => match perform_fork() {
+                    Ok(Some(_)) => 0,
+                    Ok(None) => {
+                        run(streams);
+                        libc::_exit(0)
+                    }
+                    Err(_) => libc::EAGAIN,
+                },
+                Err(err) => err.errno().unwrap_or(libc::EINVAL),
+            };
+            libc::_exit(code)
+        }
+    };
+
+    // The helper itself is reparented to init, only the intermediate process is waited.
+    let status = waitpid(helper_pid)?;
+    if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+        return Err(ErrorKind::StartLogger(libc::WEXITSTATUS(status)));
+    }
+    Ok(())
+}
+
+unsafe fn prepare(
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+    close_fds: &[libc::c_int],
+) -> Result<(), ErrorKind> {
+    for &fd in close_fds {
+        libc::close(fd);
+    }
+
+    // Standard streams are the write ends by now, the helper must not keep them open.
+    let devnull_fd = check_err(
+        libc::open(b"/dev/null\0" as *const [u8; 10] as _, libc::O_RDWR),
+        ErrorKind::OpenDevnull,
+    )?;
+    for fd in &[libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        check_err(libc::dup2(devnull_fd, *fd), ErrorKind::RedirectStreams)?;
+    }
+    check_err(libc::close(devnull_fd), ErrorKind::CloseDevnull)?;
+
+    if let Some(gid) = gid {
+        set_group(gid)?;
+    }
+    if let Some(uid) = uid {
+        set_user(uid)?;
+    }
+    Ok(())
+}
+
+fn run(streams: Vec<(File, File)>) {
+    let threads = streams
+        .into_iter()
+        .map(|(pipe, file)| std::thread::spawn(move || copy(pipe, file)))
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().ok();
+    }
+}
+
+fn copy(mut pipe: File, mut file: File) {
+    let mut buffer = [0; 4096];
+    loop {
+        let len = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+        if file.write_all(&buffer[..len]).is_err() {
+            break;
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::ffi::CString;
+use std::fs;
+use std::io::Write;
+use std::ops::{BitOr, BitOrAssign};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::ptr;
+
+use super::error::{check_err, errno, ErrorKind};
+use super::{pathbuf_into_cstring, perform_fork, waitpid};
+
+/// Set of Linux namespaces to create with `unshare(2)`. Namespaces can be combined with `|`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Namespaces {
+    inner: libc::c_int,
+}
+
+impl Namespaces {
+    /// Mount namespace, mount points changes are not visible outside of the daemon.
+    pub const MOUNT: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWNS,
+    };
+    /// Network namespace with only loopback interface.
+    pub const NETWORK: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWNET,
+    };
+    /// PID namespace, the daemon itself stays in the current namespace, but its children become
+    /// members of the new one.
+    pub const PID: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWPID,
+    };
+    /// UTS namespace, isolates hostname and NIS domain name.
+    pub const UTS: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWUTS,
+    };
+    /// IPC namespace, isolates System V IPC objects and POSIX message queues.
+    pub const IPC: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWIPC,
+    };
+    /// User namespace, see `Daemonize::user_namespace`.
+    pub const USER: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWUSER,
+    };
+    /// Cgroup namespace, isolates the view of cgroup hierarchy.
+    pub const CGROUP: Namespaces = Namespaces {
+        inner: libc::CLONE_NEWCGROUP,
+    };
+
+    /// No namespaces.
+    pub fn empty() -> Self {
+        Namespaces { inner: 0 }
+    }
+
+    /// Check that all namespaces from `other` are in this set.
+    pub fn contains(&self, other: Namespaces) -> bool {
+        self.inner & other.inner == other.inner
+    }
+
+    /// Check that the set is empty.
+    pub fn is_empty(&self) -> bool {
+        self.inner == 0
+    }
+
+    pub(crate) fn without(self, other: Namespaces) -> Namespaces {
+        Namespaces {
+            inner: self.inner & !other.inner,
+        }
+    }
+}
+
+impl BitOr for Namespaces {
+    type Output = Namespaces;
+
+    fn bitor(self, other: Namespaces) -> Namespaces {
+        Namespaces {
+            inner: self.inner | other.inner,
+        }
+    }
+}
+
+impl BitOrAssign for Namespaces {
+    fn bitor_assign(&mut self, other: Namespaces) {
+        self.inner |= other.inner;
+    }
+}
+
+/// Move the process into new `namespaces`, user namespace is created first, so other ones are
+/// owned by it.
+pub unsafe fn create_namespaces(
+    namespaces: Namespaces,
+    uid_map: &[IdMap],
+    gid_map: &[IdMap],
+) -> Result<(), ErrorKind> {
+    if namespaces.contains(Namespaces::USER) {
+        create_user_namespace(uid_map, gid_map)?;
+    }
+
+    let rest = namespaces.without(Namespaces::USER);
+    if !rest.is_empty() {
+        check_err(libc::unshare(rest.inner), ErrorKind::CreateNamespaces)?;
+    }
+
+    Ok(())
+}
+
+/// Range of ids mapped into a user namespace.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct IdMap {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+/// Move the process into a new user namespace. If no mappings provided, the current user and
+/// group are mapped to root inside the namespace.
+unsafe fn create_user_namespace(uid_map: &[IdMap], gid_map: &[IdMap]) -> Result<(), ErrorKind> {
+    if uid_map.is_empty() && gid_map.is_empty() {
+        let uid = libc::geteuid();
+        let gid = libc::getegid();
+
+        check_err(
+            libc::unshare(libc::CLONE_NEWUSER),
+            ErrorKind::CreateUserNamespace,
+        )?;
+
+        return write_id_maps(
+            "self",
+            true,
+            &format_id_map(&[IdMap {
+                inside: 0,
+                outside: uid,
+                count: 1,
+            }]),
+            &format_id_map(&[IdMap {
+                inside: 0,
+                outside: gid,
+                count: 1,
+            }]),
+        );
+    }
+
+    // Arbitrary mappings can be written only from the parent namespace, so a helper process is
+    // forked before the namespace creation.
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::WriteIdMap)?;
+    let [read_fd, write_fd] = fds;
+
+    let helper_pid = match perform_fork()? {
+        Some(pid) => pid,
+        None => {
+            libc::close(write_fd);
+            let mut buf = [0u8; 1];
+            libc::read(read_fd, buf.as_mut_ptr() as *mut libc::c_void, 1);
+            let code = match map_from_helper(libc::getppid(), uid_map, gid_map) {
+                Ok(()) => 0,
+                Err(ErrorKind::WriteIdMap(errno)) => errno,
+                Err(_) => libc::EINVAL,
+            };
+            libc::_exit(code)
+        }
+    };
+    libc::close(read_fd);
+
+    let result = check_err(
+        libc::unshare(libc::CLONE_NEWUSER),
+        ErrorKind::CreateUserNamespace,
+    );
+    // Closing the pipe wakes the helper up regardless of the unshare result.
+    libc::close(write_fd);
+    let status = waitpid(helper_pid)?;
+    result?;
+
+    if !libc::WIFEXITED(status) {
+        // Killed by a signal, there is no exit code to report.
+        return Err(ErrorKind::WriteIdMap(libc::EINTR));
+    }
+    if libc::WEXITSTATUS(status) != 0 {
+        return Err(ErrorKind::WriteIdMap(libc::WEXITSTATUS(status)));
+    }
+
+    Ok(())
+}
+
+fn map_from_helper(
+    pid: libc::pid_t,
+    uid_map: &[IdMap],
+    gid_map: &[IdMap],
+) -> Result<(), ErrorKind> {
+    if unsafe { libc::geteuid() } == 0 {
+        return write_id_maps(
+            &pid.to_string(),
+            false,
+            &format_id_map(uid_map),
+            &format_id_map(gid_map),
+        );
+    }
+
+    for (program, map) in &[("newuidmap", uid_map), ("newgidmap", gid_map)] {
+        if map.is_empty() {
+            continue;
+        }
+        let mut command = Command::new(program);
+        command.arg(pid.to_string());
+        for range in map.iter() {
+            command
+                .arg(range.inside.to_string())
+                .arg(range.outside.to_string())
+                .arg(range.count.to_string());
+        }
+        match command.status() {
+            Ok(status) if status.success() => (),
+            Ok(_) => return Err(ErrorKind::WriteIdMap(libc::EPERM)),
+            Err(err) => {
+                return Err(ErrorKind::WriteIdMap(
+                    err.raw_os_error().unwrap_or(libc::EINVAL),
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn format_id_map(map: &[IdMap]) -> String {
+    map.iter()
+        .map(|range| format!("{} {} {}\n", range.inside, range.outside, range.count))
+        .collect()
+}
+
+fn write_id_maps(
+    pid: &str,
+    deny_setgroups: bool,
+    uid_map: &str,
+    gid_map: &str,
+) -> Result<(), ErrorKind> {
+    let write = |name: &str, content: &str| {
+        fs::OpenOptions::new()
+            .write(true)
+            .open(format!("/proc/{}/{}", pid, name))
+            .and_then(|mut file| file.write_all(content.as_bytes()))
+            .map_err(|err| ErrorKind::WriteIdMap(err.raw_os_error().unwrap_or(libc::EIO)))
+    };
+
+    if !uid_map.is_empty() {
+        write("uid_map", uid_map)?;
+    }
+    if !gid_map.is_empty() {
+        // Unprivileged processes are permitted to write gid_map only with setgroups disabled.
+        if deny_setgroups {
+            write("setgroups", "deny")?;
+        }
+        write("gid_map", gid_map)?;
+    }
+    Ok(())
+}
+
+/// Bind mount of a host path into the jail.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct BindMount {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    pub read_only: bool,
+}
+
+/// Perform `mounts` with targets interpreted relative to `root`, created if missing. Already
+/// performed mounts are unmounted if any of them fails. Must be called in a new mount namespace.
+pub unsafe fn bind_mounts(mounts: &[BindMount], root: &Path) -> Result<(), ErrorKind> {
+    make_mounts_private()?;
+
+    let mut mounted = Vec::new();
+    for mount in mounts {
+        let target = root.join(mount.target.strip_prefix("/").unwrap_or(&mount.target));
+        match bind_mount(&mount.source, &target, mount.read_only) {
+            Ok(target_c) => mounted.push(target_c),
+            Err(err) => {
+                for target_c in mounted.iter().rev() {
+                    libc::umount2(target_c.as_ptr(), libc::MNT_DETACH);
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+unsafe fn bind_mount(source: &Path, target: &Path, read_only: bool) -> Result<CString, ErrorKind> {
+    let source_c = pathbuf_into_cstring(source.to_owned())?;
+    let target_c = pathbuf_into_cstring(target.to_owned())?;
+
+    // Mount point type must match the mounted one.
+    let created = if source.is_dir() {
+        fs::create_dir_all(target)
+    } else {
+        match target.parent() {
+            Some(parent) => fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+        .and_then(|_| {
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(target)
+        })
+        .map(|_| ())
+    };
+    created.map_err(|_| ErrorKind::BindMount(errno()))?;
+
+    check_err(
+        libc::mount(
+            source_c.as_ptr(),
+            target_c.as_ptr(),
+            ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            ptr::null(),
+        ),
+        ErrorKind::BindMount,
+    )?;
+
+    if read_only {
+        // Read only flag is ignored on bind mount creation and must be applied with remount.
+        let remounted = libc::mount(
+            ptr::null(),
+            target_c.as_ptr(),
+            ptr::null(),
+            libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+            ptr::null(),
+        );
+        if remounted == -1 {
+            let errno = errno();
+            libc::umount2(target_c.as_ptr(), libc::MNT_DETACH);
+            return Err(ErrorKind::BindMount(errno));
+        }
+    }
+
+    Ok(target_c)
+}
+
+/// Make `new_root` the root mount and detach the old root mount from `put_old`, which is
+/// interpreted relative to `new_root`. Must be called in a new mount namespace.
+pub unsafe fn pivot_root(new_root: PathBuf, put_old: PathBuf) -> Result<(), ErrorKind> {
+    let put_old = put_old.strip_prefix("/").unwrap_or(&put_old).to_owned();
+    let new_root_c = pathbuf_into_cstring(new_root)?;
+    let put_old_c = pathbuf_into_cstring(put_old)?;
+
+    make_mounts_private()?;
+
+    // New root is required to be a mount point.
+    check_err(
+        libc::mount(
+            new_root_c.as_ptr(),
+            new_root_c.as_ptr(),
+            ptr::null(),
+            libc::MS_BIND | libc::MS_REC,
+            ptr::null(),
+        ),
+        ErrorKind::PivotRoot,
+    )?;
+    check_err(libc::chdir(new_root_c.as_ptr()), ErrorKind::PivotRoot)?;
+
+    if libc::mkdir(put_old_c.as_ptr(), 0o700) == -1 && errno() != libc::EEXIST {
+        return Err(ErrorKind::PivotRoot(errno()));
+    }
+
+    check_err(
+        libc::syscall(
+            libc::SYS_pivot_root,
+            b".\0".as_ptr() as *const libc::c_char,
+            put_old_c.as_ptr(),
+        ),
+        ErrorKind::PivotRoot,
+    )?;
+    check_err(
+        libc::chdir(b"/\0".as_ptr() as *const libc::c_char),
+        ErrorKind::PivotRoot,
+    )?;
+    check_err(
+        libc::umount2(put_old_c.as_ptr(), libc::MNT_DETACH),
+        ErrorKind::UnmountOldRoot,
+    )?;
+
+    Ok(())
+}
+
+/// Stop mount events propagation between the current mount namespace and other ones.
+unsafe fn make_mounts_private() -> Result<(), ErrorKind> {
+    check_err(
+        libc::mount(
+            ptr::null(),
+            b"/\0".as_ptr() as *const libc::c_char,
+            ptr::null(),
+            libc::MS_REC | libc::MS_PRIVATE,
+            ptr::null(),
+        ),
+        ErrorKind::MakeMountsPrivate,
+    )?;
+    Ok(())
+}
@@ -0,0 +1,598 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::mem::zeroed;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use super::error::{check_err, errno, Error, ErrorKind};
+use super::{pathbuf_into_cstring, write_pid_file, Signal};
+
+/// Locked pid file which is removed when the guard is dropped, see `Child::pid_file_guard`.
+#[derive(Debug)]
+pub struct PidFileGuard {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFileGuard {
+    pub(crate) fn new(path: PathBuf, file: File) -> Self {
+        Self { path, file }
+    }
+
+    /// Path of the pid file as seen by the daemon.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Keep the pid file and its lock until the daemon exits.
+    pub fn keep(self) {
+        // The descriptor is leaked to hold the lock.
+        std::mem::forget(self);
+    }
+}
+
+impl AsRawFd for PidFileGuard {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+impl Drop for PidFileGuard {
+    fn drop(&mut self) {
+        // Removed before the lock is released, so another instance never locks a removed file.
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Format of the pid file, see `Daemonize::pid_file_format`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub enum PidFileFormat {
+    /// The PID followed by a newline, default. The start time is written on the second line with
+    /// `Daemonize::pid_file_start_time`.
+    Plain,
+    /// JSON object with the fields of `PidFileInfo`, `version` is the version of the daemon.
+    #[cfg(feature = "json")]
+    Json { version: Option<String> },
+}
+
+/// Contents of a pid file, see `PidFileInfo::parse`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct PidFileInfo {
+    pub pid: libc::pid_t,
+    /// Start time of the daemon, see `process_start_time`.
+    pub start_time: Option<u64>,
+    /// Path of the daemon executable, present only in JSON pid files.
+    pub exe: Option<PathBuf>,
+    /// Version of the daemon, present only in JSON pid files.
+    pub version: Option<String>,
+    /// Command line of the daemon, empty for plain pid files.
+    pub cmdline: Vec<String>,
+}
+
+impl PidFileInfo {
+    /// Parse the contents of a pid file written in any of `PidFileFormat`s.
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        #[cfg(feature = "json")]
+        {
+            if contents.trim_start().starts_with('{') {
+                return parse_json(contents);
+            }
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid pid file");
+        let mut lines = contents.lines();
+        let pid = lines
+            .next()
+            .and_then(|line| line.trim().parse().ok())
+            .filter(|&pid: &libc::pid_t| pid > 0)
+            .ok_or_else(invalid)?;
+        let start_time = match lines.next() {
+            Some(line) => Some(line.trim().parse().map_err(|_| invalid())?),
+            None => None,
+        };
+        Ok(Self {
+            pid,
+            start_time,
+            exe: None,
+            version: None,
+            cmdline: Vec::new(),
+        })
+    }
+}
+
+#[cfg(feature = "json")]
+fn parse_json(contents: &str) -> io::Result<PidFileInfo> {
+    use std::convert::TryFrom;
+
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid pid file");
+    let value: serde_json::Value = serde_json::from_str(contents)?;
+    let pid = value["pid"]
+        .as_i64()
+        .and_then(|pid| libc::pid_t::try_from(pid).ok())
+        .filter(|&pid| pid > 0)
+        .ok_or_else(invalid)?;
+    Ok(PidFileInfo {
+        pid,
+        start_time: value["start_time"].as_u64(),
+        exe: value["exe"].as_str().map(PathBuf::from),
+        version: value["version"].as_str().map(String::from),
+        cmdline: value["cmdline"]
+            .as_array()
+            .map(|args| {
+                args.iter()
+                    .filter_map(|arg| arg.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+/// Read the PID from the pid file at `path` written in any of `PidFileFormat`s. Fails if the
+/// pid file is not locked, since the daemon is not running then. The check releases `fcntl`
+/// locks of the calling process, so the daemon must not read its own pid file this way with
+/// `LockStyle::PosixFcntl`.
+pub fn read_pid_file<P: AsRef<Path>>(path: P) -> Result<libc::pid_t, Error> {
+    let pid_file = PidFile::new(path);
+    let pid = pid_file.read()?;
+    if pid_file.is_stale()? {
+        return Err(ErrorKind::StalePidfile.into());
+    }
+    Ok(pid)
+}
+
+/// Pid file which can be managed without `Daemonize`, e.g. by a supervisor of several daemons.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    style: LockStyle,
+    file: Option<File>,
+    locked: bool,
+}
+
+impl PidFile {
+    /// Pid file at `path`, it's not opened until `create` or `lock`.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            style: LockStyle::Flock,
+            file: None,
+            locked: false,
+        }
+    }
+
+    /// How the pid file is locked, `LockStyle::Flock` by default.
+    pub fn lock_style(mut self, style: LockStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Path of the pid file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open the pid file for writing, creating it if it doesn't exist.
+    pub fn create(&mut self) -> Result<(), Error> {
+        unsafe { self.open() }?;
+        Ok(())
+    }
+
+    /// Lock the pid file without blocking, creating it if needed. Fails if another process holds
+    /// the lock.
+    pub fn lock(&mut self) -> Result<(), Error> {
+        let fd = unsafe { self.open() }?;
+        if !self.locked {
+            unsafe { lock(fd, self.style) }?;
+            self.locked = true;
+        }
+        Ok(())
+    }
+
+    /// Replace the contents of the pid file with `pid` followed by a newline.
+    pub fn write(&mut self, pid: libc::pid_t) -> Result<(), Error> {
+        let fd = unsafe { self.open() }?;
+        unsafe { write_pid_file(fd, format!("{}\n", pid).as_bytes()) }?;
+        Ok(())
+    }
+
+    /// Read the PID from the pid file written in any of `PidFileFormat`s.
+    pub fn read(&self) -> Result<libc::pid_t, Error> {
+        Ok(self.read_info()?.pid)
+    }
+
+    /// Read all the information recorded in the pid file, see `PidFileInfo`. The opened pid file
+    /// is read through its descriptor, since closing another one would release `fcntl` locks.
+    pub fn read_info(&self) -> Result<PidFileInfo, Error> {
+        let contents = match &self.file {
+            Some(file) => read_at_start(file),
+            None => std::fs::read(&self.path),
+        }
+        .map_err(|err| ErrorKind::ReadPidfile(err.raw_os_error().unwrap_or(libc::EIO)))?;
+        let contents = String::from_utf8(contents).map_err(|_| ErrorKind::InvalidPidfile)?;
+        Ok(PidFileInfo::parse(&contents).map_err(|_| ErrorKind::InvalidPidfile)?)
+    }
+
+    /// Check that no process holds the lock of the pid file, so the daemon which wrote it is not
+    /// running. Checking a pid file which is not opened by this `PidFile` releases `fcntl` locks
+    /// of the calling process, see `read_pid_file`.
+    pub fn is_stale(&self) -> Result<bool, Error> {
+        if self.locked {
+            return Ok(false);
+        }
+        if let Some(file) = &self.file {
+            return Ok(!unsafe { is_locked(file.as_raw_fd()) }?);
+        }
+        let file = File::open(&self.path)
+            .map_err(|err| ErrorKind::ReadPidfile(err.raw_os_error().unwrap_or(libc::EIO)))?;
+        Ok(!unsafe { is_locked(file.as_raw_fd()) }?)
+    }
+
+    /// Remove the pid file and release its lock.
+    pub fn remove(self) -> Result<(), Error> {
+        std::fs::remove_file(&self.path)
+            .map_err(|err| ErrorKind::RemovePidfile(err.raw_os_error().unwrap_or(libc::EIO)))?;
+        Ok(())
+    }
+
+    /// Descriptor of the pid file, present after `create` or `lock`.
+    pub fn as_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.file.as_ref().map(|file| file.as_fd())
+    }
+
+    /// Leak the descriptor, so the lock is held until the process exits.
+    pub(crate) fn into_raw_fd(self) -> Option<RawFd> {
+        self.file.map(IntoRawFd::into_raw_fd)
+    }
+
+    unsafe fn open(&mut self) -> Result<libc::c_int, ErrorKind> {
+        if let Some(file) = &self.file {
+            return Ok(file.as_raw_fd());
+        }
+        let path_c = pathbuf_into_cstring(self.path.clone())?;
+        let fd = check_err(
+            libc::open(path_c.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o666),
+            ErrorKind::OpenPidfile,
+        )?;
+        self.file = Some(File::from_raw_fd(fd));
+        Ok(fd)
+    }
+}
+
+/// Read the whole file with `pread`, so the file offset used for writing is kept.
+fn read_at_start(file: &File) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        match file.read_at(&mut buf, contents.len() as u64) {
+            Ok(0) => return Ok(contents),
+            Ok(len) => contents.extend_from_slice(&buf[..len]),
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Check if any process holds a lock of any `LockStyle` on `fd` without acquiring one, which
+/// would make a daemon locking the pid file at the same time fail.
+unsafe fn is_locked(fd: libc::c_int) -> Result<bool, ErrorKind> {
+    // Record locks of both kinds conflict with each other, so the query reports both. Unlike
+    // `F_GETLK`, `F_OFD_GETLK` also reports `fcntl` locks of the calling process.
+    #[cfg(target_os = "linux")]
+    let command = libc::F_OFD_GETLK;
+    #[cfg(not(target_os = "linux"))]
+    let command = libc::F_GETLK;
+    let mut flock: libc::flock = zeroed();
+    flock.l_type = libc::F_WRLCK as _;
+    flock.l_whence = libc::SEEK_SET as _;
+    check_err(libc::fcntl(fd, command, &mut flock), ErrorKind::ReadPidfile)?;
+    if flock.l_type as libc::c_int != libc::F_UNLCK as libc::c_int {
+        return Ok(true);
+    }
+    is_flock_locked(fd)
+}
+
+/// `flock` locks are separate from record locks on Linux and only listed in `/proc/locks`.
+#[cfg(target_os = "linux")]
+unsafe fn is_flock_locked(fd: libc::c_int) -> Result<bool, ErrorKind> {
+    let mut stat: libc::stat = zeroed();
+    check_err(libc::fstat(fd, &mut stat), ErrorKind::ReadPidfile)?;
+    let locks = std::fs::read_to_string("/proc/locks")
+        .map_err(|err| ErrorKind::ReadPidfile(err.raw_os_error().unwrap_or(libc::EIO)))?;
+    let file = (
+        libc::major(stat.st_dev),
+        libc::minor(stat.st_dev),
+        stat.st_ino as u64,
+    );
+    // E.g. `1: FLOCK  ADVISORY  WRITE 1234 08:01:5678 0 EOF`, waiters are marked with `->`.
+    Ok(locks.lines().any(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        fields.get(1) == Some(&"FLOCK")
+            && fields.get(5).and_then(|id| parse_lock_file(id)) == Some(file)
+    }))
+}
+
+/// Parse `major:minor:inode` of `/proc/locks`, the device numbers are hexadecimal.
+#[cfg(target_os = "linux")]
+fn parse_lock_file(id: &str) -> Option<(libc::c_uint, libc::c_uint, u64)> {
+    let mut parts = id.split(':');
+    let major = libc::c_uint::from_str_radix(parts.next()?, 16).ok()?;
+    let minor = libc::c_uint::from_str_radix(parts.next()?, 16).ok()?;
+    let inode = parts.next()?.parse().ok()?;
+    Some((major, minor, inode))
+}
+
+/// `flock` and record locks are the same locks on other systems, so `F_GETLK` reports both.
+#[cfg(not(target_os = "linux"))]
+unsafe fn is_flock_locked(_fd: libc::c_int) -> Result<bool, ErrorKind> {
+    Ok(false)
+}
+
+/// Start time and executable of the current process, written to its pid file.
+pub(crate) struct Identity {
+    start_time: Option<u64>,
+    #[cfg_attr(not(feature = "json"), allow(dead_code))]
+    exe: Option<String>,
+}
+
+impl Identity {
+    pub(crate) fn current() -> Self {
+        Identity {
+            start_time: process_start_time(unsafe { libc::getpid() }),
+            exe: std::env::current_exe()
+                .ok()
+                .map(|exe| exe.to_string_lossy().into_owned()),
+        }
+    }
+}
+
+/// Contents of the pid file of the current process.
+pub(crate) fn contents(
+    format: &PidFileFormat,
+    with_start_time: bool,
+    identity: &Identity,
+) -> Vec<u8> {
+    let pid = unsafe { libc::getpid() };
+    match format {
+        PidFileFormat::Plain => {
+            let mut contents = format!("{}\n", pid);
+            if let Some(start_time) = identity.start_time.filter(|_| with_start_time) {
+                contents.push_str(&format!("{}\n", start_time));
+            }
+            contents.into_bytes()
+        }
+        #[cfg(feature = "json")]
+        PidFileFormat::Json { version } => {
+            let cmdline = std::env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>();
+            let info = serde_json::json!({
+                "pid": pid,
+                "start_time": identity.start_time,
+                "exe": identity.exe,
+                "version": version,
+                "cmdline": cmdline,
+            });
+            let mut contents = info.to_string();
+            contents.push('\n');
+            contents.into_bytes()
+        }
+    }
+}
+
+/// How the pid file is locked, see `Daemonize::pid_file_lock`.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum LockStyle {
+    /// `flock`, default.
+    Flock,
+    /// Open file description `fcntl` lock, which is not supported by `flock` on some NFS setups,
+    /// but still belongs to the file description like `flock`.
+    #[cfg(target_os = "linux")]
+    OfdFcntl,
+    /// POSIX `fcntl` record lock. It belongs to the process and is released once any descriptor
+    /// of the pid file is closed in the daemon, e.g. when the daemon reads its own pid file other
+    /// than through the locked `PidFile`.
+    PosixFcntl,
+}
+
+impl LockStyle {
+    /// `fcntl` write locks require a file opened for writing.
+    fn open_flags(self) -> libc::c_int {
+        match self {
+            LockStyle::Flock => libc::O_RDONLY,
+            _ => libc::O_WRONLY,
+        }
+    }
+}
+
+/// Lock the whole pid file without blocking.
+pub(crate) unsafe fn lock(fd: libc::c_int, style: LockStyle) -> Result<(), ErrorKind> {
+    let command = match style {
+        LockStyle::Flock => {
+            check_err(
+                libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB),
+                ErrorKind::LockPidfile,
+            )?;
+            return Ok(());
+        }
+        #[cfg(target_os = "linux")]
+        LockStyle::OfdFcntl => libc::F_OFD_SETLK,
+        LockStyle::PosixFcntl => libc::F_SETLK,
+    };
+    let mut flock: libc::flock = zeroed();
+    flock.l_type = libc::F_WRLCK as _;
+    flock.l_whence = libc::SEEK_SET as _;
+    // Zero length locks the whole file, regardless of its size.
+    flock.l_start = 0;
+    flock.l_len = 0;
+    check_err(libc::fcntl(fd, command, &flock), ErrorKind::LockPidfile)?;
+    Ok(())
+}
+
+/// Create, lock and write the pid file at `path` without exposing an empty or partially written
+/// file: `contents` are written to a temporary file next to it, which is then linked or renamed
+/// to `path`. The lock is held on the final file.
+pub(crate) unsafe fn create_atomic(
+    path: PathBuf,
+    contents: &[u8],
+    style: LockStyle,
+) -> Result<libc::c_int, ErrorKind> {
+    let name = path
+        .file_name()
+        .ok_or(ErrorKind::OpenPidfile(libc::EISDIR))?
+        .to_string_lossy()
+        .into_owned();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", name, libc::getpid()));
+    let path_c = pathbuf_into_cstring(path)?;
+    let temp_c = pathbuf_into_cstring(temp_path)?;
+
+    let fd = check_err(
+        libc::open(
+            temp_c.as_ptr(),
+            libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+            0o666,
+        ),
+        ErrorKind::OpenPidfile,
+    )?;
+    let result = lock_and_publish(fd, &path_c, &temp_c, contents, style);
+    // The temporary name is either linked or renamed to the final one at this point, or the
+    // pid file is not created at all.
+    libc::unlink(temp_c.as_ptr());
+    if result.is_err() {
+        libc::close(fd);
+    }
+    result.map(|()| fd)
+}
+
+unsafe fn lock_and_publish(
+    fd: libc::c_int,
+    path: &CString,
+    temp_path: &CString,
+    contents: &[u8],
+    style: LockStyle,
+) -> Result<(), ErrorKind> {
+    lock(fd, style)?;
+    write_pid_file(fd, contents)?;
+    loop {
+        if libc::link(temp_path.as_ptr(), path.as_ptr()) == 0 {
+            return Ok(());
+        }
+        if errno() != libc::EEXIST {
+            return Err(ErrorKind::RenamePidfile(errno()));
+        }
+
+        // The existing pid file is replaced only if it's stale, its lock prevents other
+        // instances from replacing it at the same time.
+        let existing = libc::open(path.as_ptr(), style.open_flags());
+        if existing == -1 {
+            match errno() {
+                libc::ENOENT => continue,
+                errno => return Err(ErrorKind::OpenPidfile(errno)),
+            }
+        }
+        let result = replace_stale(existing, path, temp_path, style);
+        libc::close(existing);
+        match result {
+            Ok(true) => return Ok(()),
+            Ok(false) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Replace the stale pid file opened as `existing`, returns false if it was replaced by another
+/// process before it's locked.
+unsafe fn replace_stale(
+    existing: libc::c_int,
+    path: &CString,
+    temp_path: &CString,
+    style: LockStyle,
+) -> Result<bool, ErrorKind> {
+    lock(existing, style)?;
+    let mut opened: libc::stat = zeroed();
+    let mut current: libc::stat = zeroed();
+    check_err(libc::fstat(existing, &mut opened), ErrorKind::OpenPidfile)?;
+    if libc::stat(path.as_ptr(), &mut current) == -1 {
+        return Ok(false);
+    }
+    if (opened.st_dev, opened.st_ino) != (current.st_dev, current.st_ino) {
+        return Ok(false);
+    }
+    check_err(
+        libc::rename(temp_path.as_ptr(), path.as_ptr()),
+        ErrorKind::RenamePidfile,
+    )?;
+    Ok(true)
+}
+
+/// Path of the pid file for the signal handler, prepared in advance since allocations are not
+/// async-signal-safe.
+static CLEANUP_PATH: AtomicPtr<CString> = AtomicPtr::new(ptr::null_mut());
+
+/// Install a handler for `signals` which removes the pid file at `path` and re-raises the signal
+/// with the default action, so the daemon terminates as if the handler was not installed.
+pub unsafe fn install_cleanup(path: PathBuf, signals: &[Signal]) -> Result<(), ErrorKind> {
+    let path = Box::new(pathbuf_into_cstring(path)?);
+    // Leaked on purpose, the handler can be invoked at any moment until the process exit.
+    CLEANUP_PATH.store(Box::into_raw(path), Ordering::Release);
+
+    let mut action: libc::sigaction = zeroed();
+    action.sa_sigaction = cleanup_handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESETHAND;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    for signal in signals {
+        check_err(
+            libc::sigaction(signal.number(), &action, ptr::null_mut()),
+            ErrorKind::SetSignalHandler,
+        )?;
+    }
+    Ok(())
+}
+
+extern "C" fn cleanup_handler(signal: libc::c_int) {
+    unsafe {
+        let path = CLEANUP_PATH.load(Ordering::Acquire);
+        if !path.is_null() {
+            libc::unlink((*path).as_ptr());
+        }
+        // The default action is restored by `SA_RESETHAND`, the signal is delivered once the
+        // handler returns.
+        libc::raise(signal);
+    }
+}
+
+/// Start time of the process `pid` in clock ticks since boot, together with the PID it identifies
+/// the process even if the PID is reused. Available only on Linux.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn process_start_time(pid: libc::pid_t) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name may contain spaces and parentheses, fields are counted after it.
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+/// Start time of the process `pid` in clock ticks since boot, together with the PID it identifies
+/// the process even if the PID is reused. Available only on Linux.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn process_start_time(_pid: libc::pid_t) -> Option<u64> {
+    None
+}
+
+/// Check that the process `pid` exists and, if `start_time` is known, that it was started at
+/// `start_time`, so a process which reused the PID of a stale pid file is not trusted. PIDs which
+/// are not positive name process groups for `kill` and are never running.
+pub fn is_process_running(pid: libc::pid_t, start_time: Option<u64>) -> bool {
+    if pid <= 0 {
+        return false;
+    }
+    let exists = unsafe { libc::kill(pid, 0) == 0 || super::error::errno() == libc::EPERM };
+    match (exists, start_time, process_start_time(pid)) {
+        (false, _, _) => false,
+        (true, Some(start_time), Some(time)) => time == start_time,
+        (true, _, _) => true,
+    }
+}
@@ -0,0 +1,86 @@
+//! Process title shown by `ps`, e.g. to display the state of the daemon the way nginx and
+//! postgres do. Unlike `Daemonize::process_name` it can be updated at any moment.
+
+use std::ffi::{CStr, CString};
+
+use super::error::{Error, ErrorKind};
+
+/// Replace the command line of the process shown by `ps` with `title`. On Linux the arguments
+/// are overwritten in place, so the title is truncated to their length and `std::env::args`
+/// returns the title afterwards. Uses `setproctitle` on the BSDs, does nothing on other
+/// platforms.
+///
+/// # Safety
+///
+/// On Linux the memory of the original arguments is written without synchronization with
+/// other readers, so nothing else may access it concurrently, e.g. `std::env::args` in another
+/// thread or a C library which kept pointers to `argv`.
+pub unsafe fn set(title: &str) -> Result<(), Error> {
+    let title = CString::new(title).map_err(|_| ErrorKind::ProcessNameContainsNul)?;
+    set_title(&title).map_err(Error::from)
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_title(title: &CStr) -> Result<(), ErrorKind> {
+    use std::sync::Mutex;
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    let (start, end) = arguments_area()?;
+    let _guard = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+    let area = std::slice::from_raw_parts_mut(start as *mut u8, end - start);
+    let bytes = title.to_bytes();
+    let len = bytes.len().min(area.len().saturating_sub(1));
+    area[..len].copy_from_slice(&bytes[..len]);
+    // The kernel stops at the first NUL if the area ends with one.
+    for byte in &mut area[len..] {
+        *byte = 0;
+    }
+    Ok(())
+}
+
+/// Bounds of the arguments in memory, `arg_start` and `arg_end` fields of /proc/self/stat.
+#[cfg(target_os = "linux")]
+fn arguments_area() -> Result<(usize, usize), ErrorKind> {
+    let stat = std::fs::read_to_string("/proc/self/stat")
+        .map_err(|err| ErrorKind::SetProcessTitle(err.raw_os_error().unwrap_or(libc::EIO)))?;
+    // The command name may contain spaces and parentheses, the third field follows the last one.
+    let fields: Vec<&str> = match stat.rfind(") ") {
+        Some(index) => stat[index + 2..].split(' ').collect(),
+        None => Vec::new(),
+    };
+    let field = |number: usize| {
+        fields
+            .get(number - 3)
+            .and_then(|field| field.trim().parse::<usize>().ok())
+            .ok_or(ErrorKind::SetProcessTitle(libc::EINVAL))
+    };
+    let (start, end) = (field(48)?, field(49)?);
+    if start == 0 || end <= start {
+        return Err(ErrorKind::SetProcessTitle(libc::EINVAL));
+    }
+    Ok((start, end))
+}
+
+#[cfg(any(
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+unsafe fn set_title(title: &CStr) -> Result<(), ErrorKind> {
+    // The leading dash omits the program name.
+    libc::setproctitle(b"-%s\0".as_ptr() as *const libc::c_char, title.as_ptr());
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "freebsd",
+    target_os = "dragonfly",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+unsafe fn set_title(_title: &CStr) -> Result<(), ErrorKind> {
+    Ok(())
+}
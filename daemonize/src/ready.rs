@@ -0,0 +1,199 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::Instant;
+
+use super::error::{check_err, errno, Errno, ErrorKind};
+use super::pidfile::process_start_time;
+
+const READY: u8 = 1;
+const FAILED: u8 = 2;
+const PID: u8 = 3;
+const START_TIME: u8 = 4;
+
+/// Tag, value and errno of messages other than `READY`.
+const MESSAGE_LEN: usize = 1 + 8 + 4;
+
+/// Handle to notify the parent process that the daemon is ready, see `Daemonize::wait_ready`.
+/// Dropping the handle without notification makes the parent fail.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ready {
+    fd: RawFd,
+}
+
+impl Ready {
+    /// Notify the parent process that the daemon is ready.
+    pub fn notify(self) {
+        unsafe {
+            libc::write(self.fd, &READY as *const u8 as *const libc::c_void, 1);
+        }
+    }
+
+    /// Report the daemonization error to the parent process as the code of the kind and errno,
+    /// see `ErrorKind::encode`.
+    pub(crate) fn fail(self, err: &ErrorKind) {
+        let (code, errno) = err.encode();
+        self.send(FAILED, code, errno);
+    }
+
+    /// Report the PID of the daemon to the parent process, preceded by its start time if known,
+    /// see `process_start_time`.
+    pub(crate) fn send_pid(&self, pid: libc::pid_t) {
+        if let Some(start_time) = process_start_time(pid) {
+            self.send(START_TIME, start_time, 0);
+        }
+        self.send(PID, pid as u64, 0);
+    }
+
+    // Messages are shorter than PIPE_BUF and written at once, so they're never split.
+    fn send(&self, tag: u8, value: u64, errno: Errno) {
+        let mut message = [0u8; MESSAGE_LEN];
+        message[0] = tag;
+        message[1..9].copy_from_slice(&value.to_ne_bytes());
+        message[9..].copy_from_slice(&errno.to_ne_bytes());
+        unsafe {
+            libc::write(
+                self.fd,
+                message.as_ptr() as *const libc::c_void,
+                MESSAGE_LEN,
+            );
+        }
+    }
+}
+
+impl AsRawFd for Ready {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl Drop for Ready {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Create the readiness pipe, the read end is kept by the parent.
+pub unsafe fn pipe() -> Result<(File, Ready), ErrorKind> {
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::CreatePipe)?;
+    let read_end = File::from_raw_fd(fds[0]);
+    let ready = Ready { fd: fds[1] };
+    for fd in &fds {
+        check_err(
+            libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC),
+            ErrorKind::CreatePipe,
+        )?;
+    }
+    Ok((read_end, ready))
+}
+
+/// Wait until the daemon notifies readiness, reports an error, closes the pipe or one of the
+/// deadlines expires, and return the PID of the daemon with its start time if known. If
+/// `until_ready` is false, return as soon as the daemon reports its PID, `None` means the daemon
+/// failed before. The inner error is reported by the daemon, the outer one is a failure to wait.
+#[allow(clippy::type_complexity)]
+pub unsafe fn wait(
+    mut read_end: File,
+    until_ready: bool,
+    ready_deadline: Option<Instant>,
+    deadline: Option<Instant>,
+) -> Result<Result<Option<(libc::pid_t, Option<u64>)>, ErrorKind>, ErrorKind> {
+    let mut pid = None;
+    let mut start_time = None;
+    loop {
+        poll(&read_end, ready_deadline, deadline)?;
+
+        let mut tag = [0];
+        match read_end.read(&mut tag) {
+            Ok(0) if until_ready => return Err(ErrorKind::NotReady),
+            Ok(0) => return Ok(Ok(None)),
+            Ok(_) => {}
+            Err(err) => {
+                return Err(ErrorKind::WaitReady(
+                    err.raw_os_error().unwrap_or(libc::EIO),
+                ))
+            }
+        }
+        match tag[0] {
+            PID => {
+                let (value, _) = receive(&mut read_end)?;
+                match libc::pid_t::try_from(value) {
+                    Ok(value) if value > 0 => pid = Some((value, start_time)),
+                    _ => return Err(ErrorKind::NotReady),
+                }
+                if !until_ready {
+                    return Ok(Ok(pid));
+                }
+            }
+            START_TIME => start_time = Some(receive(&mut read_end)?.0),
+            READY => return Ok(Ok(pid)),
+            FAILED => {
+                let (code, errno) = receive(&mut read_end)?;
+                let err = ErrorKind::decode(code, errno).ok_or(ErrorKind::NotReady)?;
+                return Ok(Err(err));
+            }
+            _ => return Err(ErrorKind::NotReady),
+        }
+    }
+}
+
+unsafe fn poll(
+    read_end: &File,
+    ready_deadline: Option<Instant>,
+    deadline: Option<Instant>,
+) -> Result<(), ErrorKind> {
+    loop {
+        let now = Instant::now();
+        if matches!(deadline, Some(deadline) if deadline <= now) {
+            return Err(ErrorKind::Timeout);
+        }
+        if matches!(ready_deadline, Some(deadline) if deadline <= now) {
+            return Err(ErrorKind::ReadyTimeout);
+        }
+
+        let timeout_ms = match ready_deadline.into_iter().chain(deadline).min() {
+            Some(deadline) => {
+                // Rounded up, so poll doesn't return early and spin with a zero timeout.
+                let remaining = deadline.saturating_duration_since(now);
+                let remaining_ms =
+                    remaining.as_millis() + u128::from(remaining.subsec_nanos() % 1_000_000 != 0);
+                remaining_ms.min(libc::c_int::MAX as u128) as libc::c_int
+            }
+            None => -1,
+        };
+        let mut pollfd = libc::pollfd {
+            fd: read_end.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ret = libc::poll(&mut pollfd, 1, timeout_ms);
+        match ret {
+            -1 if errno() == libc::EINTR => continue,
+            -1 => return Err(ErrorKind::WaitReady(errno())),
+            // Checked at the next iteration.
+            0 => continue,
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// Read the value and errno of a message after its tag, a truncated message means the daemon
+/// exited while writing it.
+fn receive(read_end: &mut File) -> Result<(u64, Errno), ErrorKind> {
+    let mut message = [0u8; MESSAGE_LEN - 1];
+    read_end
+        .read_exact(&mut message)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => ErrorKind::NotReady,
+            _ => ErrorKind::WaitReady(err.raw_os_error().unwrap_or(libc::EIO)),
+        })?;
+    let mut value = [0u8; 8];
+    let mut errno = [0u8; 4];
+    value.copy_from_slice(&message[..8]);
+    errno.copy_from_slice(&message[8..]);
+    Ok((u64::from_ne_bytes(value), Errno::from_ne_bytes(errno)))
+}
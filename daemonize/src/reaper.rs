@@ -0,0 +1,124 @@
+use std::mem::zeroed;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use super::error::{check_err, errno, restore_errno, ErrorKind};
+use super::ReapHook;
+
+static WAKE_END: AtomicI32 = AtomicI32::new(-1);
+
+/// Install a `SIGCHLD` handler which reaps all terminated children, including orphans
+/// reparented to the process.
+pub unsafe fn install() -> Result<(), ErrorKind> {
+    let mut action: libc::sigaction = zeroed();
+    action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+    libc::sigemptyset(&mut action.sa_mask);
+
+    check_err(
+        libc::sigaction(libc::SIGCHLD, &action, ptr::null_mut()),
+        ErrorKind::SetSignalHandler,
+    )?;
+
+    // Children terminated before the handler was installed.
+    handler(libc::SIGCHLD);
+    Ok(())
+}
+
+extern "C" fn handler(_signal: libc::c_int) {
+    unsafe {
+        let saved_errno = errno();
+        while libc::waitpid(-1, ptr::null_mut(), libc::WNOHANG) > 0 {}
+        restore_errno(saved_errno);
+    }
+}
+
+/// Install a `SIGCHLD` handler which wakes up the returned reaper, see `Daemonize::reap_children`.
+pub unsafe fn install_hook(hook: ReapHook) -> Result<Reaper, ErrorKind> {
+    let mut fds = [0; 2];
+    check_err(libc::pipe(fds.as_mut_ptr()), ErrorKind::CreatePipe)?;
+    let reaper = Reaper { fd: fds[0], hook };
+    for fd in &fds {
+        check_err(
+            libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC),
+            ErrorKind::CreatePipe,
+        )?;
+    }
+    // A pending wakeup is enough, the handler never blocks if the pipe is full.
+    check_err(
+        libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK),
+        ErrorKind::CreatePipe,
+    )?;
+    // Kept open until the process exit, the handler can be invoked at any moment.
+    WAKE_END.store(fds[1], Ordering::Release);
+
+    let mut action: libc::sigaction = zeroed();
+    action.sa_sigaction = wake_handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+    libc::sigemptyset(&mut action.sa_mask);
+    check_err(
+        libc::sigaction(libc::SIGCHLD, &action, ptr::null_mut()),
+        ErrorKind::SetSignalHandler,
+    )?;
+    Ok(reaper)
+}
+
+extern "C" fn wake_handler(_signal: libc::c_int) {
+    unsafe {
+        let saved_errno = errno();
+        libc::write(
+            WAKE_END.load(Ordering::Acquire),
+            &0u8 as *const u8 as *const libc::c_void,
+            1,
+        );
+        restore_errno(saved_errno);
+    }
+}
+
+/// Waits terminated children and passes them to the hook, runs in its own thread.
+pub struct Reaper {
+    fd: RawFd,
+    hook: ReapHook,
+}
+
+impl Reaper {
+    /// Reap children until the wakeup pipe fails, children terminated before the handler was
+    /// installed are reaped first.
+    pub fn run(mut self) {
+        loop {
+            let mut status = 0;
+            loop {
+                let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+                if pid <= 0 {
+                    break;
+                }
+                (self.hook)(pid, ExitStatus::from_raw(status));
+            }
+
+            let mut buffer = [0u8; 64];
+            let ret = unsafe {
+                libc::read(
+                    self.fd,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+            match ret {
+                -1 if errno() == libc::EINTR => continue,
+                -1 | 0 => return,
+                _ => {}
+            }
+        }
+    }
+}
+
+impl Drop for Reaper {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::ffi::CString;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use super::error::{errno, restore_errno, ErrorKind};
+use super::{pathbuf_into_cstring, register_handler, DeferredStreams, Signal};
+
+const FLAGS: libc::c_int = libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND;
+
+/// Everything the signal handler needs, prepared in advance since allocations are not
+/// async-signal-safe.
+struct Streams {
+    paths: Vec<(libc::c_int, CString)>,
+    merged: Vec<libc::c_int>,
+}
+
+static STREAMS: AtomicPtr<Streams> = AtomicPtr::new(ptr::null_mut());
+
+/// Install a handler for `signals` which reopens output streams redirected to paths. Files are
+/// opened in append mode, so the handler never truncates logs which were not rotated.
+pub unsafe fn install(deferred: &DeferredStreams, signals: &[Signal]) -> Result<(), ErrorKind> {
+    let mut paths = Vec::new();
+    for &(fd, ref path, _) in &deferred.paths {
+        if fd != libc::STDIN_FILENO {
+            paths.push((fd, pathbuf_into_cstring(path.clone())?));
+        }
+    }
+    let streams = Box::new(Streams {
+        paths,
+        merged: deferred.merged.clone(),
+    });
+    // Leaked on purpose, the handler can be invoked at any moment until the process exit.
+    STREAMS.store(Box::into_raw(streams), Ordering::Release);
+
+    register_handler(signals, handler)?;
+    Ok(())
+}
+
+extern "C" fn handler(_signal: libc::c_int) {
+    unsafe {
+        let streams = STREAMS.load(Ordering::Acquire);
+        if streams.is_null() {
+            return;
+        }
+        let saved_errno = errno();
+
+        let mut stdout_reopened = false;
+        for &(fd, ref path) in &(*streams).paths {
+            let raw_fd = libc::open(path.as_ptr(), FLAGS, 0o666);
+            if raw_fd == -1 {
+                continue;
+            }
+            libc::dup2(raw_fd, fd);
+            libc::close(raw_fd);
+            stdout_reopened |= fd == libc::STDOUT_FILENO;
+        }
+        if stdout_reopened {
+            for &fd in &(*streams).merged {
+                libc::dup2(libc::STDOUT_FILENO, fd);
+            }
+        }
+
+        restore_errno(saved_errno);
+    }
+}
@@ -0,0 +1,60 @@
+use std::ffi::CString;
+
+use super::error::{check_err, errno, ErrorKind};
+
+#[allow(non_camel_case_types)]
+enum priv_set_t {}
+
+const PRIV_SET: libc::c_int = 2;
+const PRIV_AWARE: libc::c_uint = 0x0002;
+
+const PRIV_PERMITTED: &[u8] = b"Permitted\0";
+const PRIV_EFFECTIVE: &[u8] = b"Effective\0";
+const PRIV_INHERITABLE: &[u8] = b"Inheritable\0";
+
+extern "C" {
+    fn priv_str_to_set(
+        buf: *const libc::c_char,
+        sep: *const libc::c_char,
+        endptr: *mut *const libc::c_char,
+    ) -> *mut priv_set_t;
+    fn priv_freeset(sp: *mut priv_set_t);
+    fn setppriv(op: libc::c_int, which: *const libc::c_char, set: *const priv_set_t)
+        -> libc::c_int;
+    fn setpflags(flag: libc::c_uint, value: libc::c_uint) -> libc::c_int;
+}
+
+/// Make the process privilege aware, so the permitted set is not changed by `setuid`.
+pub unsafe fn set_privilege_aware() -> Result<(), ErrorKind> {
+    check_err(setpflags(PRIV_AWARE, 1), ErrorKind::SetPrivileges)?;
+    Ok(())
+}
+
+/// Replace permitted, effective and inheritable privilege sets with `privileges`.
+pub unsafe fn set_privileges(privileges: &[String]) -> Result<(), ErrorKind> {
+    let privileges_c =
+        CString::new(privileges.join(",")).map_err(|_| ErrorKind::PrivilegesContainNul)?;
+
+    let set = priv_str_to_set(
+        privileges_c.as_ptr(),
+        b",\0".as_ptr() as *const libc::c_char,
+        std::ptr::null_mut(),
+    );
+    if set.is_null() {
+        return Err(ErrorKind::ParsePrivileges(errno()));
+    }
+
+    let result = [PRIV_PERMITTED, PRIV_EFFECTIVE, PRIV_INHERITABLE]
+        .iter()
+        .try_for_each(|which| {
+            check_err(
+                setppriv(PRIV_SET, which.as_ptr() as *const libc::c_char, set),
+                ErrorKind::SetPrivileges,
+            )
+            .map(|_| ())
+        });
+
+    priv_freeset(set);
+
+    result
+}
@@ -0,0 +1,45 @@
+use super::error::{BoxError, Error, ErrorKind};
+
+/// Custom daemonization step, see `Daemonize::insert_step`.
+pub trait Step {
+    /// Run the step in the daemonized process. The error becomes the source of the daemonization
+    /// error, the parent process gets only its OS error code or `EIO`.
+    fn run(&mut self) -> Result<(), BoxError>;
+}
+
+impl<E: Into<BoxError>, F: FnMut() -> Result<(), E>> Step for F {
+    fn run(&mut self) -> Result<(), BoxError> {
+        self().map_err(Into::into)
+    }
+}
+
+/// Point of the daemonization pipeline to run a custom step at. Steps run at their point even if
+/// the preceding built-in step is disabled, e.g. in the foreground mode.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum After {
+    /// After the new session is created, before the second fork.
+    SetSid,
+    /// After the second fork, in the daemon.
+    Fork,
+    /// After the pid file is locked.
+    PidFile,
+    /// After the standard streams are redirected.
+    RedirectStreams,
+    /// After the privileged action.
+    PrivilegedAction,
+    /// After the root change.
+    Chroot,
+    /// After the user and group change.
+    PrivilegeDrop,
+    /// After the pid file is written, when daemonization is finished.
+    Daemonized,
+}
+
+/// Run all steps inserted at `point` in order of insertion.
+pub fn run(steps: &mut [(After, Box<dyn Step>)], point: After) -> Result<(), Error> {
+    for (_, step) in steps.iter_mut().filter(|(after, _)| *after == point) {
+        step.run()
+            .map_err(|err| Error::from_source(ErrorKind::Step, err))?;
+    }
+    Ok(())
+}
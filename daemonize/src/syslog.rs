@@ -0,0 +1,40 @@
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use super::error::ErrorKind;
+
+/// Open the connection to the system logger in advance, before root change, so it's available
+/// from the new root too. `ident` is shared by the whole process.
+pub unsafe fn open(ident: &str, facility: libc::c_int) -> Result<(), ErrorKind> {
+    let ident = CString::new(ident).map_err(|_| ErrorKind::SyslogIdentContainsNul)?;
+    // `openlog` keeps the pointer, so the identifier has to live until the process exit.
+    libc::openlog(ident.into_raw(), libc::LOG_PID | libc::LOG_NDELAY, facility);
+    Ok(())
+}
+
+/// Send every line read from `pipe` to the system logger with `priority`, until all the write
+/// ends are closed.
+pub fn forward(pipe: File, priority: libc::c_int) {
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => (),
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        line.retain(|&byte| byte != 0);
+        let message = CString::new(line.as_slice()).expect("NUL bytes are removed");
+        unsafe {
+            libc::syslog(
+                priority,
+                b"%s\0".as_ptr() as *const libc::c_char,
+                message.as_ptr(),
+            );
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use std::fs::File;
+use std::io::{Read, Write};
+
+/// Copy everything read from `pipe` to both `file` and `original`, until all the write ends are
+/// closed. Errors of the original stream are ignored, e.g. the terminal may be gone.
+pub fn forward(mut pipe: File, mut file: File, mut original: File) {
+    let mut buffer = [0; 4096];
+    let mut original_alive = true;
+    loop {
+        let len = match pipe.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+
+        if file.write_all(&buffer[..len]).is_err() {
+            break;
+        }
+        if original_alive {
+            original_alive = original.write_all(&buffer[..len]).is_ok();
+        }
+    }
+}